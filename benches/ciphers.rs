@@ -0,0 +1,136 @@
+/*
+   Encrypt and decrypt cost of every Encryption impl at a short chat line, a long paste and the biggest frame
+   anyone would send. Run with cargo bench --bench ciphers, criterion keeps the last run under target/criterion
+   and reports the change against it
+*/
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use kryptos::cipher::{
+    AESContext, AesMode, AesSize, Encryption, NullCipher, RatchetContext, RatchetRole, Rc4State,
+    SequencedContext, MAX_REPLAY_WINDOW,
+};
+use std::hint::black_box;
+
+const MESSAGE_SIZES: [usize; 3] = [16, 1024, 65536];
+const KEY: [u8; 32] = [0x42; 32];
+
+/*
+   A sending and a receiving side sharing a key, the wrappers keep per direction state so they can't be one context
+*/
+type Pair = (Box<dyn Encryption>, Box<dyn Encryption>);
+
+fn aes(mode: AesMode, size: AesSize) -> Pair {
+    (
+        Box::new(AESContext::new(mode, size, Some(&KEY))),
+        Box::new(AESContext::new(mode, size, Some(&KEY))),
+    )
+}
+
+fn pairs() -> Vec<(String, Pair)> {
+    let mut pairs = Vec::new();
+    for (mode_name, mode) in [
+        ("ecb", AesMode::ECB),
+        ("cbc", AesMode::CBC),
+        ("ctr", AesMode::CTR),
+    ] {
+        for (bits, size) in [
+            (128, AesSize::S128),
+            (192, AesSize::S192),
+            (256, AesSize::S256),
+        ] {
+            pairs.push((format!("aes-{}-{}", bits, mode_name), aes(mode, size)));
+        }
+    }
+    pairs.push((
+        "rc4".to_string(),
+        (
+            Box::new(Rc4State::new(Some(&KEY))),
+            Box::new(Rc4State::new(Some(&KEY))),
+        ),
+    ));
+    pairs.push((
+        "null".to_string(),
+        (Box::new(NullCipher), Box::new(NullCipher)),
+    ));
+    let (sender, receiver) = aes(AesMode::CTR, AesSize::S256);
+    pairs.push((
+        "sequenced-aes-256-ctr".to_string(),
+        (
+            Box::new(SequencedContext::new(sender, MAX_REPLAY_WINDOW, &KEY, &KEY)),
+            Box::new(SequencedContext::new(
+                receiver,
+                MAX_REPLAY_WINDOW,
+                &KEY,
+                &KEY,
+            )),
+        ),
+    ));
+    let (sender, receiver) = aes(AesMode::CTR, AesSize::S256);
+    pairs.push((
+        "ratchet-aes-256-ctr".to_string(),
+        (
+            Box::new(RatchetContext::from_boxed(
+                sender,
+                RatchetRole::Client,
+                &KEY,
+            )),
+            Box::new(RatchetContext::from_boxed(
+                receiver,
+                RatchetRole::Server,
+                &KEY,
+            )),
+        ),
+    ));
+    pairs
+}
+
+fn encrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encrypt");
+    for (name, (mut sender, _)) in pairs() {
+        for size in MESSAGE_SIZES {
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(&name, size), &size, |b, &size| {
+                let message = vec![0x61u8; size];
+                let mut output = Vec::new();
+                b.iter_batched_ref(
+                    || message.clone(),
+                    |input| sender.encrypt(black_box(input), &mut output),
+                    BatchSize::SmallInput,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+/*
+   Every iteration gets a fresh frame, decrypting the same one over and over would just time the replay check
+   in the sequenced and ratchet contexts
+*/
+fn decrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decrypt");
+    for (name, (mut sender, mut receiver)) in pairs() {
+        for size in MESSAGE_SIZES {
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(&name, size), &size, |b, &size| {
+                let message = vec![0x61u8; size];
+                let mut output = Vec::new();
+                b.iter_batched_ref(
+                    || {
+                        let mut frame = Vec::new();
+                        sender.encrypt(&mut message.clone(), &mut frame);
+                        frame
+                    },
+                    |frame| {
+                        receiver.decrypt(black_box(frame), &mut output);
+                        assert!(!receiver.rejected_last_frame());
+                    },
+                    BatchSize::SmallInput,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encrypt, decrypt);
+criterion_main!(benches);
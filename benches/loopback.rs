@@ -0,0 +1,100 @@
+/*
+   Whole server cost per chat message: one client talks, everyone else in the lobby gets the broadcast, over real
+   sockets on 127.0.0.1. Criterion times whole broadcasts so its throughput line is messages per second, the p50
+   and p99 from send to each receiver's read are printed after each client count since criterion has no
+   percentiles. The sender waits for every receiver before the next message, so this is latency bound throughput,
+   not what the server does with many people typing at once
+*/
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kryptos::protocol::SUITE_PREFERENCE;
+use kryptos::test_support::{TestClient, TestServer};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLIENT_COUNTS: [usize; 3] = [2, 8, 32];
+const MESSAGE: &str = "the quick brown fox jumps over the lazy dog";
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+   Reads on its own thread and reports the arrival time of every frame
+*/
+fn listen(mut client: TestClient) -> (TcpStream, Receiver<Instant>) {
+    let (sender, receiver) = channel();
+    let stream = client.stream_handle();
+    thread::spawn(move || {
+        while client.receive(Duration::from_secs(3600)).is_some() {
+            if sender.send(Instant::now()).is_err() {
+                return;
+            }
+        }
+    });
+    (stream, receiver)
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    sorted[(sorted.len() * percent / 100).min(sorted.len() - 1)]
+}
+
+fn broadcast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broadcast");
+    group.throughput(Throughput::Elements(1));
+
+    for clients in CLIENT_COUNTS {
+        let suite = SUITE_PREFERENCE[0];
+        let server = TestServer::start(suite.encryption_type, suite.key_size);
+        let mut talker = server.join("talker");
+        let mut listeners: Vec<TestClient> = (1..clients)
+            .map(|i| server.join(&format!("listener{}", i)))
+            .collect();
+
+        /*
+           Drops the join notices, they come in bursts and would otherwise be counted as broadcasts
+        */
+        talker.settle();
+        listeners.iter_mut().for_each(TestClient::settle);
+
+        let (streams, arrivals): (Vec<_>, Vec<_>) = listeners.into_iter().map(listen).unzip();
+        let mut latencies = Vec::new();
+
+        group.bench_with_input(BenchmarkId::from_parameter(clients), &clients, |b, _| {
+            b.iter_custom(|iterations| {
+                let start = Instant::now();
+                for _ in 0..iterations {
+                    let sent = Instant::now();
+                    talker.send(MESSAGE);
+                    for arrival in &arrivals {
+                        let at = arrival
+                            .recv_timeout(RECEIVE_TIMEOUT)
+                            .expect("a listener never got the broadcast");
+                        latencies.push(at - sent);
+                    }
+                }
+                start.elapsed()
+            });
+        });
+
+        /*
+           Nothing was measured when a filter on the command line skipped this client count
+        */
+        latencies.sort();
+        if !latencies.is_empty() {
+            println!(
+                "broadcast/{}: p50 {:?}, p99 {:?} over {} deliveries",
+                clients,
+                percentile(&latencies, 50),
+                percentile(&latencies, 99),
+                latencies.len()
+            );
+        }
+        streams.iter().for_each(|x| {
+            let _ = x.shutdown(Shutdown::Both);
+        });
+        talker.leave();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broadcast);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+#![no_main]
+/*
+   Whatever a client sends goes straight into decrypt, the first byte picks the mode and key size
+*/
+use kryptos::cipher::{AESContext, AesMode, AesSize, Encryption};
+use libfuzzer_sys::fuzz_target;
+
+const KEY: [u8; 32] = [7u8; 32];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, frame)) = data.split_first() else {
+        return;
+    };
+    let mode = match selector % 3 {
+        0 => AesMode::CBC,
+        1 => AesMode::CTR,
+        _ => AesMode::ECB,
+    };
+    let size = match (selector / 3) % 3 {
+        0 => AesSize::S128,
+        1 => AesSize::S192,
+        _ => AesSize::S256,
+    };
+
+    let mut aes = AESContext::new(mode, size, Some(&KEY));
+    let mut input = frame.to_vec();
+    let mut output = vec![0u8; (selector as usize) % 64];
+    aes.decrypt(&mut input, &mut output);
+});
@@ -0,0 +1,28 @@
+#![no_main]
+/*
+   The byte stream an encrypted connection reads its frames from, arriving in whatever pieces TCP hands over
+*/
+use kryptos::protocol::{encode_frame, FrameReader, MAX_FRAME_BYTES};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|chunks: Vec<Vec<u8>>| {
+    let mut reader = FrameReader::new();
+    let mut input = Vec::new();
+    let mut framed = Vec::new();
+    for chunk in chunks {
+        input.extend_from_slice(&chunk);
+        reader.push(&chunk);
+        loop {
+            match reader.next_frame() {
+                Ok(Some(frame)) => {
+                    assert!(frame.len() <= MAX_FRAME_BYTES);
+                    framed.extend_from_slice(&encode_frame(&frame));
+                }
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+    }
+    // Every frame handed out is exactly the bytes that came in, in order
+    assert!(input.starts_with(&framed));
+});
@@ -0,0 +1,63 @@
+#![no_main]
+/*
+   Either side of the hello exchange fed whatever the other end might send, the first byte picks the side
+*/
+use kryptos::cryptography::ed25519::Ed25519KeyPair;
+use kryptos::protocol::{
+    client_handshake, server_handshake, RsaPrivateKey, ServerKeys, SUITE_PREFERENCE,
+};
+use libfuzzer_sys::fuzz_target;
+use std::io::{self, Read, Write};
+use std::sync::OnceLock;
+
+const SESSION_KEY: &[u8] = b"abcdefghijklmnopqrstuvwxyz012345";
+
+/*
+   Reads come from the fuzzer, whatever gets written back is thrown away
+*/
+struct FuzzStream<'a> {
+    input: &'a [u8],
+}
+
+impl Read for FuzzStream<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buffer)
+    }
+}
+
+impl Write for FuzzStream<'_> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/*
+   Generating keys is far slower than a handshake, so every run shares one set. A small RSA key is enough to
+   get wrapped transport keys through OAEP decoding
+*/
+fn server_keys() -> &'static ServerKeys {
+    static KEYS: OnceLock<ServerKeys> = OnceLock::new();
+    KEYS.get_or_init(|| ServerKeys {
+        host_key: Ed25519KeyPair::from_seed([7u8; 32]),
+        rsa_key: Some(RsaPrivateKey::generate(1024).unwrap()),
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&side, input)) = data.split_first() else {
+        return;
+    };
+    let mut stream = FuzzStream { input };
+    match side % 2 {
+        0 => {
+            let _ = server_handshake(&mut stream, SESSION_KEY, &SUITE_PREFERENCE, server_keys());
+        }
+        _ => {
+            let _ = client_handshake(&mut stream, SESSION_KEY, &SUITE_PREFERENCE, None);
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use kryptos::cipher::{Encryption, NullCipher};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = data.to_vec();
+    let mut output = Vec::new();
+    NullCipher.decrypt(&mut input, &mut output);
+    assert_eq!(output, data);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+/*
+   A run of frames through the receiving chain, counters anywhere from replayed to far ahead
+*/
+use kryptos::cipher::{AESContext, AesMode, AesSize, Encryption, RatchetContext, RatchetRole};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|frames: Vec<Vec<u8>>| {
+    let aes = AESContext::new(AesMode::CBC, AesSize::S256, None);
+    let mut context = RatchetContext::new(aes, RatchetRole::Server, &[7u8; 32]);
+    for frame in frames {
+        let mut input = frame;
+        let mut output = Vec::new();
+        context.decrypt(&mut input, &mut output);
+        if context.rejected_last_frame() {
+            assert!(output.is_empty());
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use kryptos::cipher::{Encryption, Rc4State};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut rc4 = Rc4State::new(Some(&[7u8; 32]));
+    let mut input = data.to_vec();
+    let mut output = vec![0u8; data.len()];
+    rc4.decrypt(&mut input, &mut output);
+});
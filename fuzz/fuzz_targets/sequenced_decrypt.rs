@@ -0,0 +1,21 @@
+#![no_main]
+/*
+   A run of frames through the tag check and replay window the server wraps every connection's cipher in
+*/
+use kryptos::cipher::{AESContext, AesMode, AesSize, Encryption, SequencedContext};
+use kryptos::cryptography::sequence::STREAM_REPLAY_WINDOW;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|frames: Vec<Vec<u8>>| {
+    let aes = AESContext::new(AesMode::CTR, AesSize::S256, Some(&[7u8; 32]));
+    let mut context =
+        SequencedContext::new(Box::new(aes), STREAM_REPLAY_WINDOW, &[7u8; 32], &[7u8; 32]);
+    for frame in frames {
+        let mut input = frame;
+        let mut output = vec![0u8; input.len()];
+        context.decrypt(&mut input, &mut output);
+        if context.rejected_last_frame() {
+            assert!(output.is_empty());
+        }
+    }
+});
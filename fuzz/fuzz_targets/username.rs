@@ -0,0 +1,25 @@
+#![no_main]
+/*
+   The decrypted frame a client answers the greeting with, through the telnet filter and the trimming the
+   server does before the name is checked or taken as a resume token
+*/
+use kryptos::protocol::{frame_text, TelnetOptions};
+use kryptos::server::{SessionRegistry, UsernamePolicy};
+use libfuzzer_sys::fuzz_target;
+use std::time::Duration;
+
+fuzz_target!(|data: &[u8]| {
+    let mut telnet = TelnetOptions::new();
+    let (filtered, _replies) = telnet.receive(data);
+    let name = frame_text(&filtered);
+
+    match name.strip_prefix("/resume ") {
+        Some(token) => {
+            let sessions = SessionRegistry::new(Duration::from_secs(120), 100);
+            assert!(sessions.take(token.trim()).is_none());
+        }
+        None => {
+            let _ = UsernamePolicy::default().validate(&name);
+        }
+    }
+});
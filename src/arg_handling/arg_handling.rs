@@ -1,11 +1,17 @@
 pub mod arg_handling {
+    use crate::logging::logging::{parse_log_format, LogFormat};
+    use crate::telnet::handshake::{CipherSuite, DEFAULT_HOST_KEY_FILE, DEFAULT_RSA_KEY_BITS};
+    use crate::telnet::history::DEFAULT_HISTORY_LENGTH;
+    use crate::telnet::sessions::{DEFAULT_MISSED_MESSAGE_LIMIT, DEFAULT_RESUME_GRACE_SECONDS};
+    use crate::telnet::usernames::{DEFAULT_RESERVED_NAMES, DEFAULT_USERNAME_PATTERN};
     use crate::{ERROR, SUCCESS};
     use std::process::exit;
+    use std::str::FromStr;
 
     /*
        Enum we will use to pass encryption info for creation of context
     */
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, PartialEq)]
     pub enum EncryptionInfo {
         AesCbc,
         AesCtr,
@@ -13,7 +19,7 @@ pub mod arg_handling {
         Rc4,
     }
 
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, PartialEq)]
     pub enum KeySize {
         Size128,
         Size192,
@@ -30,34 +36,285 @@ pub mod arg_handling {
         }
     }
 
+    /*
+       A room that is created on startup rather than on the fly with /join, it may carry its own cipher and key
+       so that sensitive rooms do not share a secret with the lobby
+    */
+    #[derive(Clone)]
+    pub struct RoomConfig {
+        pub name: String,
+        pub encryption: Option<(EncryptionInfo, KeySize, String)>,
+    }
+
+    pub const DEFAULT_SHUTDOWN_GRACE_SECONDS: u64 = 5;
+    pub const DEFAULT_PLAINTEXT_BIND: &str = "127.0.0.1";
+
     pub struct KryptosConfig {
         pub enc_type: EncryptionInfo,
         pub key_size: KeySize,
         pub optional_key: Option<String>,
         pub port: u16,
+        pub rooms: Vec<RoomConfig>,
+        pub username_pattern: String,
+        pub reserved_names: Vec<String>,
+        pub accounts_file: Option<String>,
+        pub resume_grace_seconds: u64,
+        pub missed_message_limit: usize,
+        pub transcript_file: Option<String>,
+        pub history_length: usize,
+        pub log_format: LogFormat,
+        pub shutdown_grace_seconds: u64,
+        pub rekey_interval_seconds: u64,
+        pub rekey_bytes: u64,
+        pub admins: Vec<String>,
+        pub ratchet: bool,
+        pub suites: Vec<CipherSuite>,
+        pub rsa_key_file: Option<String>,
+        pub rsa_key_bits: usize,
+        pub host_key_file: String,
+        /*
+           Unencrypted listener for stock telnet clients, off unless a port is given
+        */
+        pub plaintext_port: Option<u16>,
+        pub plaintext_bind: String,
+    }
+
+    pub fn parse_encryption_type(encryption_type: &str) -> Option<EncryptionInfo> {
+        match encryption_type {
+            "AesCbc" => Some(EncryptionInfo::AesCbc),
+            "AesCtr" => Some(EncryptionInfo::AesCtr),
+            "AesEcb" => Some(EncryptionInfo::AesEcb),
+            "Rc4" => Some(EncryptionInfo::Rc4),
+            _ => None,
+        }
+    }
+
+    pub fn parse_key_size(key_size: &str) -> Option<KeySize> {
+        match key_size.parse::<usize>() {
+            Ok(128) => Some(KeySize::Size128),
+            Ok(192) => Some(KeySize::Size192),
+            Ok(256) => Some(KeySize::Size256),
+            _ => None,
+        }
+    }
+
+    /*
+       Room specs look like name:encryption-type:key-size:key, or just name for a room that uses the server cipher
+    */
+    pub fn parse_room(spec: &str) -> RoomConfig {
+        let parts: Vec<&str> = spec.splitn(4, ':').collect();
+        let name = parts[0].trim_start_matches('#').to_string();
+
+        if name.is_empty() {
+            eprintln!("Room name must not be empty!");
+            exit(ERROR);
+        }
+
+        if parts.len() == 1 {
+            return RoomConfig {
+                name,
+                encryption: None,
+            };
+        }
+
+        if parts.len() != 4 {
+            eprintln!("Invalid room {spec}! Expected name:encryption-type:key-size:key");
+            exit(ERROR);
+        }
+
+        let encryption_type = match parse_encryption_type(parts[1]) {
+            Some(x) => x,
+            None => {
+                eprintln!("Invalid encryption type for room {name}!");
+                exit(ERROR);
+            }
+        };
+
+        let key_size = match parse_key_size(parts[2]) {
+            Some(x) => x,
+            None => {
+                eprintln!("Invalid key size for room {name}! Valid sizes are: 128, 192, 256");
+                exit(ERROR);
+            }
+        };
+
+        if parts[3].len() * 8 != <KeySize as Into<usize>>::into(key_size) {
+            eprintln!("Invalid key for room {name}, the key length does not match the key size!");
+            exit(ERROR);
+        }
+
+        RoomConfig {
+            name,
+            encryption: Some((encryption_type, key_size, parts[3].to_string())),
+        }
+    }
+
+    fn parse_name_list(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    pub fn parse_number<T: FromStr>(option: &str, value: &str) -> T {
+        match value.parse() {
+            Ok(x) => x,
+            Err(_) => {
+                eprintln!("Option {} expects a number, got {}!", option, value);
+                exit(ERROR);
+            }
+        }
+    }
+
+    pub fn parse_suites(value: &str) -> Vec<CipherSuite> {
+        parse_name_list(value)
+            .iter()
+            .map(|x| match CipherSuite::parse(x) {
+                Some(x) => x,
+                None => {
+                    eprintln!("Invalid cipher suite {}! Expected encryption-type-key-size, e.g. AesCtr-256", x);
+                    exit(ERROR);
+                }
+            })
+            .collect()
+    }
+
+    pub fn parse_switch(option: &str, value: &str) -> bool {
+        match value {
+            "on" => true,
+            "off" => false,
+            _ => {
+                eprintln!("Option {} expects on or off, got {}!", option, value);
+                exit(ERROR);
+            }
+        }
+    }
+
+    /*
+       Anything after the positional arguments is treated as a --flag value pair
+    */
+    fn parse_options(options: &[String], config: &mut KryptosConfig) {
+        let mut index = 0;
+        while index < options.len() {
+            let value = match options.get(index + 1) {
+                Some(x) => x,
+                None => {
+                    eprintln!("Option {} is missing a value!", options[index]);
+                    exit(ERROR);
+                }
+            };
+
+            match options[index].as_str() {
+                "--room" => config.rooms.push(parse_room(value)),
+                "--username-pattern" => config.username_pattern = value.clone(),
+                "--accounts" => config.accounts_file = Some(value.clone()),
+                "--transcript" => config.transcript_file = Some(value.clone()),
+                "--history" => config.history_length = parse_number(&options[index], value),
+                "--rekey-interval" => {
+                    config.rekey_interval_seconds = parse_number(&options[index], value)
+                }
+                "--rekey-bytes" => config.rekey_bytes = parse_number(&options[index], value),
+                "--admins" => config.admins = parse_name_list(value),
+                "--ratchet" => config.ratchet = parse_switch(&options[index], value),
+                "--suites" => config.suites = parse_suites(value),
+                "--rsa-key" => config.rsa_key_file = Some(value.clone()),
+                "--host-key" => config.host_key_file = value.clone(),
+                "--plaintext-port" => {
+                    config.plaintext_port = Some(parse_number(&options[index], value))
+                }
+                "--plaintext-bind" => config.plaintext_bind = value.clone(),
+                "--rsa-bits" => {
+                    config.rsa_key_bits = match value.as_str() {
+                        "2048" | "4096" => parse_number(&options[index], value),
+                        _ => {
+                            eprintln!("RSA keys are 2048 or 4096 bits, got {}!", value);
+                            exit(ERROR);
+                        }
+                    }
+                }
+                "--shutdown-grace" => {
+                    config.shutdown_grace_seconds = parse_number(&options[index], value)
+                }
+                "--log-format" => {
+                    config.log_format = match parse_log_format(value) {
+                        Some(x) => x,
+                        None => {
+                            eprintln!("Log format must be text or json, got {}!", value);
+                            exit(ERROR);
+                        }
+                    }
+                }
+                "--resume-grace" => {
+                    config.resume_grace_seconds = parse_number(&options[index], value)
+                }
+                "--resume-backlog" => {
+                    config.missed_message_limit = parse_number(&options[index], value)
+                }
+                "--reserved-names" => config.reserved_names = parse_name_list(value),
+                _ => {
+                    eprintln!("Unknown option {}!", options[index]);
+                    eprintln!("Try --help for help.");
+                    exit(ERROR);
+                }
+            }
+            index += 2;
+        }
     }
 
     pub fn parse_arguments(args: Vec<String>) -> KryptosConfig {
+        let option_start = match args.iter().skip(2).position(|x| x.starts_with("--")) {
+            Some(x) => x + 2,
+            None => args.len(),
+        };
+        let options = args[option_start..].to_vec();
+        let args = args[..option_start].to_vec();
+
         let use_key: bool = args.len() == 5;
         if (args.len() > 5) {
             println!("Too many arguments!");
-            println!("Usage: kryptos port encryption-type key-size (optional provided key, generated otherwise)");
+            println!("Usage: kryptos-server port encryption-type key-size (optional provided key, generated otherwise)");
             println!("Try --help for help.");
             exit(ERROR);
         }
 
         if { args.len() < 2 } {
-            println!("Usage: kryptos port encryption-type key-size (optional provided key, generated otherwise)");
+            println!("Usage: kryptos-server port encryption-type key-size (optional provided key, generated otherwise)");
             println!("Try --help for help.");
             exit(ERROR);
         }
         if (args[1] == "--help") {
-            println!("Usage: kryptos port encryption-type key-size (optional provided key, generated otherwise)");
+            println!("Usage: kryptos-server port encryption-type key-size (optional provided key, generated otherwise)");
             println!("Encryption Options: AesCbc, AesCtr, AesEcb (unsafe), Rc4 (unsafe)");
             println!("Key Size Options: 128, 192, 256");
             println!("This is a simple encrypted telnet chat server written in Rust.");
             println!("The client is available on my github");
             println!("Options: --help, --version");
+            println!("Server options:");
+            println!("  --room name[:encryption-type:key-size:key]   create a room on startup, optionally with its own cipher and key");
+            println!("  --username-pattern regex                      usernames must match this, defaults to {DEFAULT_USERNAME_PATTERN}");
+            println!("  --reserved-names name,name                    names nobody may take, defaults to server,admin");
+            println!("  --accounts file                               enable accounts, registered names need a password");
+            println!("  --resume-grace seconds                        how long a dropped user's name is held for /resume, defaults to {DEFAULT_RESUME_GRACE_SECONDS}, 0 disables");
+            println!("  --resume-backlog count                        messages kept for a dropped user to replay on /resume, defaults to {DEFAULT_MISSED_MESSAGE_LIMIT}");
+            println!("  --transcript file                             keep an encrypted transcript, key from KRYPTOS_LOG_KEY or asked for");
+            println!("  --history count                               messages per room replayed to people joining, defaults to {DEFAULT_HISTORY_LENGTH}, 0 disables");
+            println!("  --log-format text|json                        log output format, filter with RUST_LOG (info by default)");
+            println!("  --rekey-interval seconds                      rotate keys in-band this often, off by default");
+            println!("  --rekey-bytes count                           rotate keys once a connection has been sent this many bytes, off by default");
+            println!("  --admins name,name                            registered accounts allowed to use /rekey");
+            println!("  --suites suite,suite                          suites clients may negotiate for the session key, e.g. AesCtr-256,AesCbc-128, defaults to the one given above");
+            println!("  --host-key file                               Ed25519 key the server signs handshakes with, generated if missing, defaults to {DEFAULT_HOST_KEY_FILE}");
+            println!("  --rsa-key file                                RSA key clients can wrap a fresh key to instead of knowing the session key, generated if missing");
+            println!("  --rsa-bits 2048|4096                          size of a newly generated --rsa-key, defaults to {DEFAULT_RSA_KEY_BITS}");
+            println!("  --plaintext-port port                         also accept unencrypted stock telnet clients on this port (insecure), off by default");
+            println!("  --plaintext-bind address                      address the plaintext listener binds to, defaults to {DEFAULT_PLAINTEXT_BIND}");
+            println!("  --ratchet on|off                              derive a fresh key for every message (forward secrecy), off by default");
+            println!("  --shutdown-grace seconds                      warning given to users on SIGINT/SIGTERM before disconnecting, defaults to {DEFAULT_SHUTDOWN_GRACE_SECONDS}");
+            println!(
+                "Account administration: kryptos-server user add|del|passwd name (optional accounts file)"
+            );
+            println!("Transcripts: kryptos-server log decrypt file | log grep pattern file | log export --json file");
             exit(SUCCESS);
         }
 
@@ -67,7 +324,7 @@ pub mod arg_handling {
         }
 
         if { args.len() < 4 } {
-            println!("Usage: kryptos port encryption-type key-size (optional provided key, generated otherwise)");
+            println!("Usage: kryptos-server port encryption-type key-size (optional provided key, generated otherwise)");
             println!("Try --help for help.");
             exit(ERROR);
         }
@@ -100,12 +357,9 @@ pub mod arg_handling {
 
         let size_usize = args[3].parse::<usize>().unwrap();
 
-        let encryption_type = match args[2].as_str() {
-            "AesCbc" => EncryptionInfo::AesCbc,
-            "AesCtr" => EncryptionInfo::AesCtr,
-            "AesEcb" => EncryptionInfo::AesEcb,
-            "Rc4" => EncryptionInfo::Rc4,
-            _ => {
+        let encryption_type = match parse_encryption_type(args[2].as_str()) {
+            Some(x) => x,
+            None => {
                 eprintln!("Invalid encryption type!");
                 eprintln!("Try --help for help.");
                 exit(ERROR);
@@ -129,7 +383,7 @@ pub mod arg_handling {
             exit(ERROR);
         }
 
-        let config = KryptosConfig {
+        let mut config = KryptosConfig {
             enc_type: encryption_type,
             key_size: size,
             optional_key: match use_key {
@@ -137,8 +391,33 @@ pub mod arg_handling {
                 false => None,
             },
             port,
+            rooms: Vec::new(),
+            username_pattern: DEFAULT_USERNAME_PATTERN.to_string(),
+            reserved_names: DEFAULT_RESERVED_NAMES
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            accounts_file: None,
+            resume_grace_seconds: DEFAULT_RESUME_GRACE_SECONDS,
+            missed_message_limit: DEFAULT_MISSED_MESSAGE_LIMIT,
+            transcript_file: None,
+            history_length: DEFAULT_HISTORY_LENGTH,
+            log_format: LogFormat::Text,
+            shutdown_grace_seconds: DEFAULT_SHUTDOWN_GRACE_SECONDS,
+            rekey_interval_seconds: 0,
+            rekey_bytes: 0,
+            admins: Vec::new(),
+            ratchet: false,
+            suites: Vec::new(),
+            rsa_key_file: None,
+            rsa_key_bits: DEFAULT_RSA_KEY_BITS,
+            host_key_file: DEFAULT_HOST_KEY_FILE.to_string(),
+            plaintext_port: None,
+            plaintext_bind: DEFAULT_PLAINTEXT_BIND.to_string(),
         };
 
+        parse_options(&options, &mut config);
+
         config
     }
 }
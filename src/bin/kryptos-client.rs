@@ -0,0 +1,626 @@
+use crossterm::cursor::MoveTo;
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, size, Clear, ClearType, EnterAlternateScreen,
+    LeaveAlternateScreen,
+};
+use crossterm::{execute, queue};
+use kryptos::cipher::{EncryptionContext, RatchetRole};
+use kryptos::protocol::{
+    client_handshake, client_handshake_with_rsa, fingerprint, parse_rekey_announce, write_frame,
+    CipherSuite, ClientHandshake, FrameReader, HostKeyStatus, KnownHosts, RoomEncryption,
+    RsaPublicKey, DEFAULT_KNOWN_HOSTS_FILE, LOBBY, REKEY_ACK, ROOM_KEY_SWITCH, SUCCESS_STRING,
+};
+use kryptos::server::{
+    parse_encryption_type, parse_key_size, parse_room, parse_suites, parse_switch, RoomConfig,
+    RoomRegistry,
+};
+use kryptos::{ERROR, SUCCESS};
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{stdout, Stdout, Write};
+use std::net::TcpStream;
+use std::process::exit;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/*
+   Interactive terminal client for the Kryptos server. It goes through the same handshake, key bookkeeping and
+   framing code the server uses, so a protocol change that breaks one breaks the other.
+
+   Usage: kryptos-client host port encryption-type key-size (session key, or --rsa-key server.pub) [options]
+*/
+const USAGE: &str =
+    "Usage: kryptos-client host port encryption-type key-size (session key, or --rsa-key file)";
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PASSWORD_PROMPT: &str = "Password for ";
+const UNREADABLE_FRAME: &str =
+    "*** A message from the server could not be decrypted or was replayed and has been dropped ***";
+const NAME_COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+struct ClientConfig {
+    host: String,
+    port: u16,
+    key: Option<String>,
+    rooms: Vec<RoomConfig>,
+    suites: Vec<CipherSuite>,
+    ratchet: bool,
+    rsa_key_file: Option<String>,
+    known_hosts_file: String,
+}
+
+fn usage_and_exit() -> ! {
+    eprintln!("{}", USAGE);
+    eprintln!("Options:");
+    eprintln!("  --room name:encryption-type:key-size:key    key for a room that has its own, as given to the server");
+    eprintln!("  --suites suite,suite                        suites to offer, defaults to the one given above");
+    eprintln!(
+        "  --ratchet on|off                            has to match the server, off by default"
+    );
+    eprintln!("  --rsa-key file                              server RSA public key, used instead of the session key");
+    eprintln!("  --known-hosts file                          pinned host keys, defaults to {DEFAULT_KNOWN_HOSTS_FILE}");
+    exit(ERROR);
+}
+
+fn parse_client_arguments(args: &[String]) -> ClientConfig {
+    let option_start = args
+        .iter()
+        .position(|x| x.starts_with("--"))
+        .unwrap_or(args.len());
+    let (positional, options) = args.split_at(option_start);
+
+    if positional.len() < 5 || positional.len() > 6 {
+        usage_and_exit();
+    }
+
+    let port = match positional[2].parse::<u16>() {
+        Ok(x) => x,
+        Err(_) => {
+            eprintln!("Error occurred while parsing port!");
+            exit(ERROR);
+        }
+    };
+    let suite = match (
+        parse_encryption_type(&positional[3]),
+        parse_key_size(&positional[4]),
+    ) {
+        (Some(encryption_type), Some(key_size)) => CipherSuite::new(encryption_type, key_size),
+        _ => {
+            eprintln!("Invalid encryption type or key size!");
+            usage_and_exit();
+        }
+    };
+
+    let mut config = ClientConfig {
+        host: positional[1].clone(),
+        port,
+        key: positional.get(5).cloned(),
+        rooms: Vec::new(),
+        suites: vec![suite],
+        ratchet: false,
+        rsa_key_file: None,
+        known_hosts_file: DEFAULT_KNOWN_HOSTS_FILE.to_string(),
+    };
+
+    for pair in options.chunks(2) {
+        let value = match pair.get(1) {
+            Some(x) => x,
+            None => {
+                eprintln!("Option {} is missing a value!", pair[0]);
+                exit(ERROR);
+            }
+        };
+        match pair[0].as_str() {
+            "--room" => config.rooms.push(parse_room(value)),
+            "--suites" => config.suites = parse_suites(value),
+            "--ratchet" => config.ratchet = parse_switch(&pair[0], value),
+            "--rsa-key" => config.rsa_key_file = Some(value.clone()),
+            "--known-hosts" => config.known_hosts_file = value.clone(),
+            _ => {
+                eprintln!("Unknown option {}!", pair[0]);
+                usage_and_exit();
+            }
+        }
+    }
+
+    if config.key.is_none() && config.rsa_key_file.is_none() {
+        eprintln!("Either the session key or --rsa-key is needed!");
+        usage_and_exit();
+    }
+    config
+}
+
+/*
+   Runs the handshake with the host key pinned in known hosts, pinning it if this is the first visit
+*/
+fn connect(config: &ClientConfig, stream: &mut TcpStream) -> Result<ClientHandshake, String> {
+    let host = format!("{}:{}", config.host, config.port);
+    let mut known_hosts = KnownHosts::open(&config.known_hosts_file)
+        .map_err(|e| format!("Could not read {}: {}", config.known_hosts_file, e))?;
+    let pinned = known_hosts.lookup(&host).copied();
+
+    let result = match (&config.rsa_key_file, &config.key) {
+        (Some(path), _) => {
+            let pem = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let server_key = RsaPublicKey::from_pem(&pem).map_err(|e| e.message())?;
+            client_handshake_with_rsa(stream, &server_key, &config.suites, pinned.as_ref())
+        }
+        (None, Some(key)) => {
+            client_handshake(stream, key.as_bytes(), &config.suites, pinned.as_ref())
+        }
+        (None, None) => unreachable!(),
+    }
+    .map_err(|e| e.message())?;
+
+    match known_hosts.check(&host, &result.host_key) {
+        Ok(HostKeyStatus::Pinned) => println!(
+            "Pinned the host key for {}, fingerprint {}",
+            host,
+            fingerprint(&result.host_key)
+        ),
+        Ok(_) => {}
+        Err(e) => return Err(format!("Could not pin the host key: {}", e)),
+    }
+    Ok(result)
+}
+
+/*
+   What the client has to mirror of the server's key state: the keys for every epoch and which room we are in
+*/
+struct Session {
+    stream: TcpStream,
+    context: EncryptionContext,
+    keys: RoomRegistry,
+    suite: CipherSuite,
+    traffic_salt: Vec<u8>,
+    room: String,
+}
+
+impl Session {
+    fn current_context(&self) -> EncryptionContext {
+        self.keys
+            .encryption_for(&self.room, self.suite)
+            .create_context_for(RatchetRole::Client, &self.traffic_salt)
+    }
+
+    fn send(&mut self, text: &[u8]) -> io::Result<()> {
+        let mut plain = text.to_vec();
+        let mut encrypted = plain.clone();
+        self.context.context.encrypt(&mut plain, &mut encrypted);
+        write_frame(&mut self.stream, &encrypted)
+    }
+
+    /*
+       Answers under the old key and moves every key on to the announced epoch, the next one, see rekey.rs
+    */
+    fn rekey(&mut self, epoch: u64) -> io::Result<()> {
+        self.send(format!("{} {}\n", REKEY_ACK, epoch).as_bytes())?;
+        self.keys.rotate();
+        self.context = self.current_context();
+        Ok(())
+    }
+
+    /*
+       Takes a decrypted frame, handling the ones meant for the client itself. Returns what should be shown
+    */
+    fn handle_frame(&mut self, text: &str) -> io::Result<Vec<String>> {
+        if let Some(epoch) = parse_rekey_announce(text, self.keys.epoch()) {
+            self.rekey(epoch)?;
+            return Ok(Vec::new());
+        }
+        let shown = text.lines().map(|x| x.to_string()).collect();
+
+        if let Some(room) = text
+            .strip_prefix("Joined #")
+            .and_then(|x| x.strip_suffix(ROOM_KEY_SWITCH))
+        {
+            self.send(format!("{} {}\n", REKEY_ACK, self.keys.epoch()).as_bytes())?;
+            self.room = room.to_string();
+            self.context = self.current_context();
+        } else if let Some(room) = text.strip_prefix("Joined #") {
+            self.room = room.trim().to_string();
+        }
+        Ok(shown)
+    }
+}
+
+enum Incoming {
+    Lines(Vec<String>),
+    Disconnected(String),
+}
+
+/*
+   Puts the server's frames back together and decrypts them. A frame that won't decrypt is dropped, but the user
+   is told, it means someone is tampering with the connection or our keys no longer match the server's
+*/
+fn spawn_reader(mut stream: TcpStream, session: Arc<Mutex<Session>>, sender: Sender<Incoming>) {
+    thread::spawn(move || {
+        let mut frames = FrameReader::new();
+        loop {
+            let mut encrypted = match frames.read_frame(&mut stream) {
+                Ok(x) => x,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    let _ = sender.send(Incoming::Disconnected(
+                        "Server closed the connection".to_string(),
+                    ));
+                    return;
+                }
+                Err(e) => {
+                    let _ = sender.send(Incoming::Disconnected(e.to_string()));
+                    return;
+                }
+            };
+
+            let mut session = session.lock().unwrap();
+            let mut plain = vec![0u8; encrypted.len()];
+            session.context.context.decrypt(&mut encrypted, &mut plain);
+            if session.context.context.rejected_last_frame() {
+                let _ = sender.send(Incoming::Lines(vec![UNREADABLE_FRAME.to_string()]));
+                continue;
+            }
+
+            let text = String::from_utf8_lossy(&plain)
+                .trim_matches('\0')
+                .to_string();
+            match session.handle_frame(&text) {
+                Ok(lines) if !lines.is_empty() => {
+                    let _ = sender.send(Incoming::Lines(lines));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = sender.send(Incoming::Disconnected(e.to_string()));
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/*
+   Message pane above a one line editor. The pane keeps every line and scrolls with Page Up / Page Down
+*/
+struct Screen {
+    lines: Vec<String>,
+    scroll: usize,
+    input: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    history_index: usize,
+    /* The server doesn't echo our own messages back, so we show them ourselves once we know our name */
+    name: Option<String>,
+    last_sent: String,
+}
+
+fn name_color(name: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    NAME_COLORS[(hasher.finish() % NAME_COLORS.len() as u64) as usize]
+}
+
+/*
+   Chat lines are "name: message", the name is the part that gets colored. Anything else is from the server
+*/
+fn split_name(line: &str) -> Option<(&str, &str)> {
+    let (name, rest) = line.split_once(": ")?;
+    let bare = name.strip_prefix("[dm] ").unwrap_or(name);
+    match !bare.is_empty() && !bare.trim_end_matches(" [insecure]").contains(' ') {
+        true => Some((name, rest)),
+        false => None,
+    }
+}
+
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(width.max(1))
+        .map(|x| x.iter().collect())
+        .collect()
+}
+
+impl Screen {
+    fn new() -> Screen {
+        Screen {
+            lines: Vec::new(),
+            scroll: 0,
+            input: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            history_index: 0,
+            name: None,
+            last_sent: String::new(),
+        }
+    }
+
+    fn receive(&mut self, lines: Vec<String>) {
+        for line in &lines {
+            if line.as_str() == SUCCESS_STRING.trim_end() {
+                self.name = Some(self.last_sent.clone());
+            } else if let Some(rest) = line.strip_prefix("Welcome back ") {
+                self.name = rest.split(',').next().map(str::to_string);
+            }
+        }
+        self.lines.extend(lines);
+    }
+
+    fn sent(&mut self, line: &str) {
+        if self.masked() {
+            return;
+        }
+        self.last_sent = line.to_string();
+        if let Some(name) = &self.name {
+            if !line.starts_with('/') && !line.is_empty() {
+                self.lines.push(format!("{}: {}", name, line));
+            }
+        }
+    }
+
+    fn masked(&self) -> bool {
+        self.lines
+            .last()
+            .is_some_and(|x| x.starts_with(PASSWORD_PROMPT))
+    }
+
+    fn draw(&self, out: &mut Stdout) -> io::Result<()> {
+        let (width, height) = size()?;
+        let (width, height) = (width as usize, height as usize);
+        let pane_height = height.saturating_sub(2);
+
+        /*
+           Rows are laid out bottom up so scrolling just skips rows from the end
+        */
+        let mut rows: Vec<(String, Option<(usize, Color)>)> = Vec::new();
+        for line in &self.lines {
+            let colored =
+                split_name(line).map(|(name, _)| (name.chars().count(), name_color(name)));
+            for (index, row) in wrap(line, width).into_iter().enumerate() {
+                rows.push((row, if index == 0 { colored } else { None }));
+            }
+        }
+        let end = rows.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(pane_height);
+
+        queue!(out, Clear(ClearType::All))?;
+        for (y, (row, colored)) in rows[start..end].iter().enumerate() {
+            queue!(out, MoveTo(0, y as u16))?;
+            match colored {
+                Some((length, color)) => {
+                    let split = row.char_indices().nth(*length).map_or(row.len(), |x| x.0);
+                    queue!(
+                        out,
+                        SetForegroundColor(*color),
+                        Print(&row[..split]),
+                        ResetColor,
+                        Print(&row[split..])
+                    )?;
+                }
+                None => queue!(
+                    out,
+                    SetForegroundColor(Color::DarkGrey),
+                    Print(row),
+                    ResetColor
+                )?,
+            }
+        }
+
+        let status = match self.scroll {
+            0 => String::new(),
+            x => format!(" scrolled back {} rows, Page Down to return ", x),
+        };
+        queue!(
+            out,
+            MoveTo(0, pane_height as u16),
+            SetForegroundColor(Color::DarkGrey),
+            Print(format!("{:-<width$}", status, width = width)),
+            ResetColor
+        )?;
+
+        /*
+           The input line scrolls sideways to keep the cursor in view
+        */
+        let shown: String = match self.masked() {
+            true => "*".repeat(self.input.len()),
+            false => self.input.iter().collect(),
+        };
+        let prompt_width = width.saturating_sub(3).max(1);
+        let offset = self.cursor.saturating_sub(prompt_width - 1);
+        let visible: String = shown.chars().skip(offset).take(prompt_width).collect();
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1) as u16),
+            Print("> "),
+            Print(visible),
+            MoveTo(
+                (self.cursor - offset + 2) as u16,
+                height.saturating_sub(1) as u16
+            )
+        )?;
+        out.flush()
+    }
+
+    /*
+       Returns a finished line when Enter is pressed
+    */
+    fn handle_key(&mut self, key: KeyEvent) -> Option<String> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => {
+                let line: String = self.input.drain(..).collect();
+                self.cursor = 0;
+                if !line.is_empty() && !self.masked() {
+                    self.history.push(line.clone());
+                }
+                self.history_index = self.history.len();
+                self.scroll = 0;
+                return Some(line);
+            }
+            KeyCode::Char('a') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.input.len(),
+            KeyCode::Char('u') if control => {
+                self.input.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char('k') if control => self.input.truncate(self.cursor),
+            KeyCode::Char(c) if !control => {
+                self.input.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
+            KeyCode::Up if self.history_index > 0 => {
+                self.history_index -= 1;
+                self.input = self.history[self.history_index].chars().collect();
+                self.cursor = self.input.len();
+            }
+            KeyCode::Down if self.history_index < self.history.len() => {
+                self.history_index += 1;
+                self.input = match self.history.get(self.history_index) {
+                    Some(x) => x.chars().collect(),
+                    None => Vec::new(),
+                };
+                self.cursor = self.input.len();
+            }
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            _ => {}
+        }
+        None
+    }
+}
+
+/*
+   Runs the interface until the user quits or the server goes away, returns why it ended
+*/
+fn run(session: Arc<Mutex<Session>>, receiver: Receiver<Incoming>) -> io::Result<String> {
+    let mut out = stdout();
+    let mut screen = Screen::new();
+    screen.draw(&mut out)?;
+
+    loop {
+        let mut dirty = false;
+        while let Ok(incoming) = receiver.try_recv() {
+            match incoming {
+                Incoming::Lines(lines) => screen.receive(lines),
+                Incoming::Disconnected(reason) => return Ok(reason),
+            }
+            dirty = true;
+        }
+
+        if poll(INPUT_POLL_INTERVAL)? {
+            match read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => {
+                    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+                    if matches!(key.code, KeyCode::Char('c') | KeyCode::Char('d')) && control {
+                        return Ok("Bye".to_string());
+                    }
+                    if let Some(line) = screen.handle_key(key) {
+                        if line == "/quit" {
+                            return Ok("Bye".to_string());
+                        }
+                        session.lock().unwrap().send(line.as_bytes())?;
+                        screen.sent(&line);
+                    }
+                }
+                _ => {}
+            }
+            dirty = true;
+        }
+
+        if dirty {
+            screen.draw(&mut out)?;
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let config = parse_client_arguments(&args);
+
+    let mut stream = match TcpStream::connect((config.host.as_str(), config.port)) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!(
+                "Could not connect to {}:{}: {}",
+                config.host, config.port, e
+            );
+            exit(ERROR);
+        }
+    };
+    let handshake = match connect(&config, &mut stream) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Handshake failed: {}", e);
+            exit(ERROR);
+        }
+    };
+
+    /*
+       Same bookkeeping as the server: the session key and room keys at epoch 0, moved on with every rekey
+    */
+    let session_encryption = RoomEncryption {
+        encryption_type: handshake.suite.encryption_type,
+        key_size: handshake.suite.key_size,
+        key: handshake.session_key.clone(),
+        ratchet: config.ratchet,
+    };
+    let keys = RoomRegistry::new(session_encryption, &config.rooms);
+    let context = keys
+        .encryption_for(LOBBY, handshake.suite)
+        .create_context_for(RatchetRole::Client, &handshake.traffic_salt);
+    let reader = stream.try_clone().expect("Could not clone the connection");
+    let session = Arc::new(Mutex::new(Session {
+        stream,
+        context,
+        keys,
+        suite: handshake.suite,
+        traffic_salt: handshake.traffic_salt,
+        room: LOBBY.to_string(),
+    }));
+
+    let (sender, receiver) = channel();
+    spawn_reader(reader, Arc::clone(&session), sender);
+
+    let mut out = stdout();
+    if let Err(e) = enable_raw_mode().and_then(|_| execute!(out, EnterAlternateScreen)) {
+        eprintln!("Could not set up the terminal: {}", e);
+        exit(ERROR);
+    }
+    let result = run(session, receiver);
+    let _ = execute!(out, LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+
+    match result {
+        Ok(reason) => {
+            println!("{}", reason);
+            exit(SUCCESS);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(ERROR);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use kryptos::cipher::KeySize;
+use kryptos::protocol::{fingerprint, RoomEncryption, SHUTDOWN_MESSAGE};
+use kryptos::server::{
+    generate_session_token, init_logging, load_host_key, load_rsa_key, log_command,
+    open_insecure_telnet_connection, open_telnet_connection, parse_arguments, read_transcript,
+    shutdown_server, spawn_rekey_thread, spawn_server_thread, transcript_key, user_command,
+    AccountStore, ConnectionPool, LogFormat, RoomHistory, RoomRegistry, Server, ServerFunctions,
+    ServerKeys, ServerState, SessionRegistry, Transcript, UsernamePolicy,
+};
+use kryptos::{ERROR, PORT, SUCCESS};
+use std::env;
+use std::net::TcpListener;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+const ACCEPT_BACKOFF_START: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_LIMIT: Duration = Duration::from_secs(1);
+
+/*
+   Sleeps after a failed accept, twice as long each time it fails in a row up to ACCEPT_BACKOFF_LIMIT, so running
+   out of file descriptors doesn't turn an accept loop into a busy loop
+*/
+fn back_off(delay: &mut Duration) {
+    thread::sleep(*delay);
+    *delay = (*delay * 2).min(ACCEPT_BACKOFF_LIMIT);
+}
+
+/*
+   Accept loop for the plaintext listener, its connections join the same rooms but never get encrypted.
+   Returns once the server is shutting down, which closes the listener
+*/
+fn accept_plaintext_connections(listener: TcpListener, server: Server) {
+    let mut delay = ACCEPT_BACKOFF_START;
+    loop {
+        let mut server_connection = match open_insecure_telnet_connection(&listener) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to accept plaintext connection: {}", e);
+                back_off(&mut delay);
+                continue;
+            }
+        };
+        delay = ACCEPT_BACKOFF_START;
+
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            server_connection
+                .send_closing_message_and_disconnect(Some(SHUTDOWN_MESSAGE.to_string()));
+            return;
+        }
+
+        let peer = server_connection.get_address();
+        warn!(
+            conn_id = server_connection.connection_id,
+            %peer,
+            "accepted unencrypted connection"
+        );
+        spawn_server_thread(
+            Arc::new(RwLock::new(server_connection)),
+            Arc::clone(&server),
+        );
+    }
+}
+
+/*
+   Main loop, binds to all addresses possible, listens for connections and spawns threads on each new connection
+*/
+fn main() {
+    let mut port = 0;
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "user" {
+        init_logging(LogFormat::Text);
+        exit(user_command(&args[2..]));
+    }
+    if args.len() > 1 && args[1] == "log" {
+        init_logging(LogFormat::Text);
+        exit(log_command(&args[2..]));
+    }
+    let config = parse_arguments(args);
+    init_logging(config.log_format);
+    let mut session_token: String;
+
+    /*
+       Key will be validated inside parse_arguments function
+    */
+    match config.optional_key {
+        None => {
+            session_token = generate_session_token();
+            let key_size: usize = <KeySize as Into<usize>>::into(config.key_size) / 8;
+            session_token.truncate(key_size);
+        }
+        Some(ref key) => {
+            session_token = key.clone();
+        }
+    }
+    port = config.port;
+    let key_size = config.key_size;
+    let encryption_type = config.enc_type;
+    info!(port = PORT, "starting telnet server");
+    /*
+       Printed rather than logged so the key doesn't get shipped off with the rest of the logs
+    */
+    println!("Session key: {}", session_token);
+    let conn_pool = ConnectionPool::new(RwLock::new(Default::default()));
+    /*
+       Everyone connects on this and keeps it until they move to a room with its own key or keys are rotated
+    */
+    let session_encryption = RoomEncryption {
+        encryption_type,
+        key_size,
+        key: session_token.as_bytes().to_vec(),
+        ratchet: config.ratchet,
+    };
+    let rooms =
+        RoomRegistry::new(session_encryption.clone(), &config.rooms).with_suites(&config.suites);
+    let usernames = match UsernamePolicy::new(&config.username_pattern, &config.reserved_names) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Invalid username pattern: {}", e);
+            exit(ERROR);
+        }
+    };
+    let accounts = match config.accounts_file {
+        Some(ref path) => match AccountStore::open(path) {
+            Ok(x) => Some(x.with_admins(&config.admins)),
+            Err(e) => {
+                error!("Could not open accounts file {}: {}", path, e);
+                exit(ERROR);
+            }
+        },
+        None => None,
+    };
+    let sessions = SessionRegistry::new(
+        Duration::from_secs(config.resume_grace_seconds),
+        config.missed_message_limit,
+    );
+    let history = RoomHistory::new(config.history_length);
+    let transcript = match config.transcript_file {
+        Some(ref path) => {
+            let key = transcript_key();
+            /*
+               Refill the scrollback from the end of the transcript so it survives a restart
+            */
+            if config.history_length > 0 {
+                if let Ok(records) = read_transcript(path, &key) {
+                    records.into_iter().for_each(|x| history.push(x));
+                }
+            }
+            match Transcript::open(path, &key) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    error!("{}", e.message());
+                    exit(ERROR);
+                }
+            }
+        }
+        None => None,
+    };
+    let rsa_key = match config.rsa_key_file {
+        Some(ref path) => match load_rsa_key(path, config.rsa_key_bits) {
+            Ok(x) => {
+                info!(path = %path, bits = x.n.bits(), "loaded RSA key");
+                Some(x)
+            }
+            Err(e) => {
+                error!("Could not load RSA key {}", e);
+                exit(ERROR);
+            }
+        },
+        None => None,
+    };
+    let host_key = match load_host_key(&config.host_key_file) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Could not load the host key {}", e);
+            exit(ERROR);
+        }
+    };
+    /*
+       Clients pin this on first connect, printing it lets people check it out of band like with ssh
+    */
+    println!("Host key fingerprint: {}", fingerprint(&host_key.public));
+    let keys = ServerKeys { host_key, rsa_key };
+    let server = ServerState::new(
+        conn_pool, rooms, usernames, accounts, sessions, transcript, history, keys,
+    );
+    if let Some(plaintext_port) = config.plaintext_port {
+        let address = format!("{}:{}", config.plaintext_bind, plaintext_port);
+        match TcpListener::bind(&address) {
+            Ok(x) => {
+                warn!(%address, "accepting UNENCRYPTED telnet connections");
+                let plaintext_server = Arc::clone(&server);
+                thread::spawn(move || accept_plaintext_connections(x, plaintext_server));
+            }
+            Err(e) => {
+                error!(
+                    "Could not bind the plaintext listener to {}: {}",
+                    address, e
+                );
+                exit(ERROR);
+            }
+        }
+    }
+    let server_listener: TcpListener = TcpListener::bind(format!("0.0.0.0:{}", PORT)).unwrap();
+    let reference = Arc::new(RwLock::new(server_listener));
+
+    /*
+       First SIGINT/SIGTERM starts a graceful shutdown, a second one while that is running exits straight away
+    */
+    let (shutdown_sender, shutdown_receiver) = mpsc::channel();
+    if let Err(e) = ctrlc::set_handler(move || {
+        if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+            exit(ERROR);
+        }
+        let _ = shutdown_sender.send(());
+    }) {
+        error!("Could not install the signal handler: {}", e);
+    }
+
+    spawn_rekey_thread(
+        Arc::clone(&server),
+        (config.rekey_interval_seconds > 0)
+            .then(|| Duration::from_secs(config.rekey_interval_seconds)),
+        (config.rekey_bytes > 0).then_some(config.rekey_bytes),
+    );
+
+    let shutdown_server_ref = Arc::clone(&server);
+    let shutdown_grace = Duration::from_secs(config.shutdown_grace_seconds);
+    thread::spawn(move || {
+        if shutdown_receiver.recv().is_ok() {
+            info!(grace = shutdown_grace.as_secs(), "shutting down");
+            shutdown_server(&shutdown_server_ref, shutdown_grace);
+            info!("shutdown complete");
+            exit(SUCCESS);
+        }
+    });
+
+    let mut delay = ACCEPT_BACKOFF_START;
+    loop {
+        let curr = Arc::clone(&reference);
+
+        let mut server_connection = match open_telnet_connection(curr, &session_encryption) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                back_off(&mut delay);
+                continue;
+            }
+        };
+        delay = ACCEPT_BACKOFF_START;
+
+        /*
+           The handshake never started, so the client is refused in the clear. Then the listener is closed, so
+           anyone after it is turned away by the kernel, and this thread waits for the shutdown thread to exit
+        */
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            server_connection
+                .send_closing_message_and_disconnect(Some(SHUTDOWN_MESSAGE.to_string()));
+            drop(reference);
+            loop {
+                thread::park();
+            }
+        }
+
+        let peer = server_connection.get_address();
+        info!(
+            conn_id = server_connection.connection_id,
+            %peer,
+            "accepted connection"
+        );
+        let reference = Arc::new(RwLock::new(server_connection));
+        let unwrapped = Arc::clone(&reference);
+
+        // Spawn a new thread to handle the connection
+
+        spawn_server_thread(Arc::clone(&unwrapped), Arc::clone(&server));
+    }
+}
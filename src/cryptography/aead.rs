@@ -0,0 +1,80 @@
+use crate::cryptography::aes::{AESContext, AesMode, AesSize, AES_BLOCK_LENGTH_BYTES};
+use crate::cryptography::sha256::{constant_time_eq, hkdf, hmac_sha256, SHA256_OUTPUT_SIZE_BYTES};
+use rand::RngCore;
+
+/*
+   AEAD built from what we already have, AES-256-CTR then HMAC-SHA256 over the associated data and the ciphertext.
+   The associated data is length prefixed in the MAC input so it can't be shifted into the ciphertext.
+
+   Frame layout is IV (16 bytes) | ciphertext | tag (32 bytes)
+*/
+const AEAD_INFO: &[u8] = b"kryptos aes-256-ctr hmac-sha256 v1";
+pub const AEAD_OVERHEAD_BYTES: usize = AES_BLOCK_LENGTH_BYTES + SHA256_OUTPUT_SIZE_BYTES;
+
+pub struct CtrHmacAead {
+    cipher: AESContext,
+    mac_key: [u8; SHA256_OUTPUT_SIZE_BYTES],
+}
+
+impl CtrHmacAead {
+    /*
+       The key is split into independent cipher and MAC keys, it should already be high entropy
+    */
+    pub fn new(key: &[u8]) -> CtrHmacAead {
+        let key_material = hkdf(&[], key, AEAD_INFO, 64);
+        let mut mac_key = [0u8; SHA256_OUTPUT_SIZE_BYTES];
+        mac_key.copy_from_slice(&key_material[32..]);
+
+        CtrHmacAead {
+            cipher: AESContext::new(AesMode::CTR, AesSize::S256, Some(&key_material[..32])),
+            mac_key,
+        }
+    }
+
+    pub fn seal(&mut self, associated_data: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut iv = [0u8; AES_BLOCK_LENGTH_BYTES];
+        rand::rng().fill_bytes(&mut iv);
+
+        let mut frame = iv.to_vec();
+        frame.extend_from_slice(&self.keystream_xor(&iv, plaintext));
+        let tag = self.tag(associated_data, &frame);
+        frame.extend_from_slice(&tag);
+        frame
+    }
+
+    /*
+       Returns None if the frame or the associated data were changed, or the key is wrong
+    */
+    pub fn open(&mut self, associated_data: &[u8], frame: &[u8]) -> Option<Vec<u8>> {
+        if frame.len() < AEAD_OVERHEAD_BYTES {
+            return None;
+        }
+
+        let (body, tag) = frame.split_at(frame.len() - SHA256_OUTPUT_SIZE_BYTES);
+        if !constant_time_eq(&self.tag(associated_data, body), tag) {
+            return None;
+        }
+
+        let (iv, ciphertext) = body.split_at(AES_BLOCK_LENGTH_BYTES);
+        Some(self.keystream_xor(iv.try_into().unwrap(), ciphertext))
+    }
+
+    fn tag(&self, associated_data: &[u8], body: &[u8]) -> [u8; SHA256_OUTPUT_SIZE_BYTES] {
+        let mut message = (associated_data.len() as u64).to_be_bytes().to_vec();
+        message.extend_from_slice(associated_data);
+        message.extend_from_slice(body);
+        hmac_sha256(&self.mac_key, &message)
+    }
+
+    fn keystream_xor(&mut self, iv: &[u8; AES_BLOCK_LENGTH_BYTES], input: &[u8]) -> Vec<u8> {
+        let mut counter = u128::from_be_bytes(*iv);
+        let mut output = Vec::with_capacity(input.len());
+
+        for chunk in input.chunks(AES_BLOCK_LENGTH_BYTES) {
+            let keystream = self.cipher.encrypt_block(&counter.to_be_bytes());
+            output.extend(chunk.iter().zip(keystream.iter()).map(|(x, y)| x ^ y));
+            counter = counter.wrapping_add(1);
+        }
+        output
+    }
+}
@@ -2,7 +2,7 @@ use crate::cryptography::cryptography::Encryption;
 use rand::RngCore;
 use std::cmp::PartialEq;
 
-const AES_BLOCK_LENGTH_BYTES: usize = 16;
+pub const AES_BLOCK_LENGTH_BYTES: usize = 16;
 const AES_KEY_LENGTH_BYTES_MAX: usize = 32;
 
 const NUM_COLUMNS: u8 = 4;
@@ -118,12 +118,35 @@ fn from_2d_array(state: &AesState) -> [u8; 16] {
     buffer
 }
 
+/*
+   Takes PKCS#7 padding off, false if the last block doesn't end in valid padding
+*/
+fn remove_padding(buffer: &mut Vec<u8>) -> bool {
+    let pad_len = match buffer.last() {
+        Some(&x) => x as usize,
+        None => return false,
+    };
+    if pad_len == 0 || pad_len > AES_BLOCK_LENGTH_BYTES || pad_len > buffer.len() {
+        return false;
+    }
+    if !buffer[buffer.len() - pad_len..]
+        .iter()
+        .all(|&b| b as usize == pad_len)
+    {
+        return false;
+    }
+    buffer.truncate(buffer.len() - pad_len);
+    true
+}
+
+#[derive(Clone, Copy)]
 pub enum AesMode {
     CBC, // Cipher block chaining
     ECB, //Codebook
     CTR, // Counter
 }
 
+#[derive(Clone, Copy)]
 pub enum AesSize {
     S128, // 128-bit key
     S192, // 192-bit key
@@ -469,19 +492,6 @@ impl AESContext {
         rand::fill(&mut self.initialization_vector);
     }
 
-    /*
-       The last 16 bytes will hold the IV
-    */
-    fn read_initialization_vector(&mut self, buffer: &mut [u8]) -> [u8; AES_BLOCK_LENGTH_BYTES] {
-        let len = buffer.len();
-        let start = len - AES_BLOCK_LENGTH_BYTES;
-        let mut array = [0u8; AES_BLOCK_LENGTH_BYTES];
-
-        for i in 0..AES_BLOCK_LENGTH_BYTES {
-            array[i] = buffer[start + i];
-        }
-        array
-    }
     /*
        Xor single block in the buffer with the initialization vector stored
        internally
@@ -516,10 +526,6 @@ impl AESContext {
 
     */
     fn cbc_encrypt(&mut self, buffer: &[u8], output: &mut Vec<u8>) {
-        /*
-           Generate a fresh IV every encryption operation
-        */
-        self.generate_initialization_vector();
         /*
            Casting these just in case it goes negative on the subtraction operation, don't want wraparound or panic because of this
         */
@@ -597,9 +603,6 @@ impl AESContext {
     }
 
     fn ctr_encrypt(&mut self, buffer: &[u8], output: &mut Vec<u8>) {
-        /*
-           Generate a fresh IV every encryption operation
-        */
         let mut xor_buffer;
         /*
            Casting these just in case it goes negative on the subtraction operation, don't want wraparound or panic because of this
@@ -613,8 +616,6 @@ impl AESContext {
            On decryption we need to extract the nonce from the prefix of the input buffer (first 16 bytes)
         */
 
-        self.generate_initialization_vector();
-
         /*
            Resize if required to store the 16 byte IV as a prefix to the rest of the data
         */
@@ -639,7 +640,7 @@ impl AESContext {
         for i in 0..input_len as usize {
             if counter_index == AES_BLOCK_LENGTH_BYTES {
                 self.cipher(&mut xor_buffer, &mut output_slice); // Encrypt IV as AES block
-                counter += 1;
+                counter = counter.wrapping_add(1);
                 xor_buffer = counter.to_be_bytes();
                 counter_index = 0; // Reset counter
             }
@@ -678,7 +679,7 @@ impl AESContext {
         for i in 0..input_len as usize - 16usize {
             if counter_index == AES_BLOCK_LENGTH_BYTES {
                 self.cipher(&mut xor_buffer, &mut output_slice); // Encrypt IV as AES block
-                counter += 1;
+                counter = counter.wrapping_add(1);
                 xor_buffer = counter.to_be_bytes();
                 counter_index = 0; // Reset counter
             }
@@ -689,6 +690,79 @@ impl AESContext {
             counter_index += 1;
         }
     }
+    /*
+       Single raw block through the cipher, for constructions built on top of AES that need exact lengths
+       and can't go through the padding handling in the Encryption impl
+    */
+    pub fn encrypt_block(
+        &mut self,
+        block: &[u8; AES_BLOCK_LENGTH_BYTES],
+    ) -> [u8; AES_BLOCK_LENGTH_BYTES] {
+        let mut output = [0u8; AES_BLOCK_LENGTH_BYTES];
+        self.cipher(block, &mut output);
+        output
+    }
+
+    /*
+       Runs the mode with a given IV and no padding, leaving the IV out of the output. This is the shape published
+       test vectors come in, frames for the wire go through the Encryption impl. ECB and CBC need whole blocks and
+       give back nothing otherwise, ECB ignores the IV
+    */
+    pub fn encrypt_unpadded(
+        &mut self,
+        initialization_vector: &[u8; AES_BLOCK_LENGTH_BYTES],
+        input: &[u8],
+    ) -> Vec<u8> {
+        if self.mode != AesMode::CTR && !input.len().is_multiple_of(AES_BLOCK_LENGTH_BYTES) {
+            return Vec::new();
+        }
+        self.initialization_vector = *initialization_vector;
+        let mut output = vec![0u8; input.len() + AES_BLOCK_LENGTH_BYTES];
+
+        match self.mode {
+            AesMode::CBC => self.cbc_encrypt(input, &mut output),
+            AesMode::CTR => self.ctr_encrypt(input, &mut output),
+            AesMode::ECB => {
+                for i in 0..input.len() / AES_BLOCK_LENGTH_BYTES {
+                    let block = i * AES_BLOCK_LENGTH_BYTES..(i + 1) * AES_BLOCK_LENGTH_BYTES;
+                    self.ecb_encrypt(&input[block.clone()], &mut output[block]);
+                }
+                output.truncate(input.len());
+                return output;
+            }
+        }
+        output.split_off(AES_BLOCK_LENGTH_BYTES)
+    }
+
+    pub fn decrypt_unpadded(
+        &mut self,
+        initialization_vector: &[u8; AES_BLOCK_LENGTH_BYTES],
+        input: &[u8],
+    ) -> Vec<u8> {
+        if self.mode != AesMode::CTR && !input.len().is_multiple_of(AES_BLOCK_LENGTH_BYTES) {
+            return Vec::new();
+        }
+        let mut output = vec![0u8; input.len()];
+        if self.mode == AesMode::ECB {
+            for i in 0..input.len() / AES_BLOCK_LENGTH_BYTES {
+                let block = i * AES_BLOCK_LENGTH_BYTES..(i + 1) * AES_BLOCK_LENGTH_BYTES;
+                self.ecb_decrypt(&input[block.clone()], &mut output[block]);
+            }
+            return output;
+        }
+
+        /*
+           The mode functions read the IV off the front like they would on a frame
+        */
+        let mut frame = initialization_vector.to_vec();
+        frame.extend_from_slice(input);
+        match self.mode {
+            AesMode::CBC => self.cbc_decrypt(&frame, &mut output),
+            _ => self.ctr_decrypt(&frame, &mut output),
+        }
+        output
+    }
+
     /*
        Functions below are just for testing. I can remove them but fuggit they can stay
     */
@@ -756,22 +830,26 @@ impl Encryption for AESContext {
         self.initialize_context();
     }
 
+    /*
+       ECB and CBC are padded PKCS#7 style, always at least one byte so a message can end in anything.
+       CTR is a stream mode and keeps the message length
+    */
     fn encrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
-        let mut len = input.len();
-        let output_len = output.len();
-        if (self.mode != AesMode::ECB) {
-            output.resize(len + AES_BLOCK_LENGTH_BYTES, 0);
+        if self.mode != AesMode::CTR {
+            let padding_len = AES_BLOCK_LENGTH_BYTES - (input.len() % AES_BLOCK_LENGTH_BYTES);
+            input.resize(input.len() + padding_len, padding_len as u8);
         }
+        let output_len = match self.mode {
+            AesMode::ECB => input.len(),
+            _ => input.len() + AES_BLOCK_LENGTH_BYTES, // IV goes up front
+        };
+        output.clear();
+        output.resize(output_len, 0);
+        /*
+           Fresh IV for every frame, it goes out in front of the ciphertext
+        */
+        self.generate_initialization_vector();
 
-        let padding_len = (AES_BLOCK_LENGTH_BYTES - (len % AES_BLOCK_LENGTH_BYTES));
-        if (padding_len < AES_BLOCK_LENGTH_BYTES) {
-            for _ in 0..padding_len {
-                input.push(padding_len as u8);
-            }
-        }
-        if (output.len() < input.len()) {
-            output.resize(input.len(), 0);
-        }
         match self.mode {
             AesMode::CBC => {
                 self.cbc_encrypt(input, output);
@@ -793,17 +871,23 @@ impl Encryption for AESContext {
         }
     }
 
+    /*
+       Frames we can't have made, too short, not whole blocks or with broken padding, decrypt to nothing
+    */
     fn decrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
-        let input_size = input.len();
-        let output_size = output.len();
-
-        if (self.mode != AesMode::ECB) {
-            if (input_size > AES_BLOCK_LENGTH_BYTES) {
-                output.resize(input_size - AES_BLOCK_LENGTH_BYTES, 0); // Shave off the IV
-            }
-        } else {
-            output.resize(input_size, 0);
+        let body_len = match self.mode {
+            AesMode::ECB => input.len(),
+            _ => input.len().saturating_sub(AES_BLOCK_LENGTH_BYTES), // Shave off the IV
+        };
+        let well_formed = match self.mode {
+            AesMode::CTR => input.len() >= AES_BLOCK_LENGTH_BYTES,
+            _ => body_len > 0 && body_len % AES_BLOCK_LENGTH_BYTES == 0,
+        };
+        output.clear();
+        if !well_formed {
+            return;
         }
+        output.resize(body_len, 0);
 
         match self.mode {
             AesMode::CBC => {
@@ -812,7 +896,7 @@ impl Encryption for AESContext {
             AesMode::ECB => {
                 for i in 0..input.len() / AES_BLOCK_LENGTH_BYTES {
                     self.ecb_decrypt(
-                        &mut input[i * AES_BLOCK_LENGTH_BYTES
+                        &input[i * AES_BLOCK_LENGTH_BYTES
                             ..(i * AES_BLOCK_LENGTH_BYTES) + AES_BLOCK_LENGTH_BYTES],
                         &mut output[i * AES_BLOCK_LENGTH_BYTES
                             ..(i * AES_BLOCK_LENGTH_BYTES) + AES_BLOCK_LENGTH_BYTES],
@@ -824,24 +908,8 @@ impl Encryption for AESContext {
             }
         }
 
-        let mut len = output.len();
-        for i in 0..len - 1 {
-            if (output[i] == 0) && output[i + 1] == 0 {
-                len = i;
-                output.resize(len, 0);
-                break;
-            }
-        }
-        if let Some(&last_byte) = output.last() {
-            let pad_len = last_byte as usize;
-            if pad_len > 0 && pad_len <= AES_BLOCK_LENGTH_BYTES && output.len() >= pad_len {
-                let padding_valid = output[output.len() - pad_len..]
-                    .iter()
-                    .all(|&b| b == last_byte);
-                if padding_valid {
-                    output.resize(output.len() - pad_len, 0);
-                }
-            }
+        if self.mode != AesMode::CTR && !remove_padding(output) {
+            output.clear();
         }
     }
 
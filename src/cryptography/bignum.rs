@@ -0,0 +1,462 @@
+use rand::RngCore;
+use std::cmp::Ordering;
+
+/*
+   Arbitrary precision unsigned integers, just enough for RSA.
+
+   Limbs are 64 bit, least significant first, with no zero limbs on the top end so equal values always compare
+   equal limb for limb. Modular exponentiation with an odd modulus, everything RSA and Miller-Rabin need, goes
+   through Montgomery multiplication so there is no division inside the hot loop.
+
+   Nothing here is constant time, the running time depends on the values involved
+*/
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BigUint {
+    limbs: Vec<u64>,
+}
+
+const LIMB_BITS: usize = 64;
+const WINDOW_BITS: usize = 4;
+
+impl BigUint {
+    pub fn zero() -> BigUint {
+        BigUint { limbs: Vec::new() }
+    }
+
+    pub fn one() -> BigUint {
+        BigUint::from_u64(1)
+    }
+
+    pub fn from_u64(value: u64) -> BigUint {
+        BigUint::from_limbs(vec![value])
+    }
+
+    fn from_limbs(limbs: Vec<u64>) -> BigUint {
+        let mut new = BigUint { limbs };
+        new.normalize();
+        new
+    }
+
+    fn normalize(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    pub fn from_bytes_be(bytes: &[u8]) -> BigUint {
+        let limbs = bytes
+            .rchunks(8)
+            .map(|chunk| chunk.iter().fold(0u64, |acc, x| (acc << 8) | *x as u64))
+            .collect();
+        BigUint::from_limbs(limbs)
+    }
+
+    /*
+       Big endian with no leading zeros, zero comes out as an empty vector
+    */
+    pub fn to_bytes_be(&self) -> Vec<u8> {
+        let bytes = self.to_bytes_be_padded(self.bits().div_ceil(8));
+        bytes.unwrap_or_default()
+    }
+
+    /*
+       Big endian left padded with zeros to exactly length bytes, None if the value doesn't fit
+    */
+    pub fn to_bytes_be_padded(&self, length: usize) -> Option<Vec<u8>> {
+        if self.bits().div_ceil(8) > length {
+            return None;
+        }
+        let mut bytes = vec![0u8; length];
+        for (index, byte) in bytes.iter_mut().rev().enumerate() {
+            let limb = self.limbs.get(index / 8).copied().unwrap_or(0);
+            *byte = (limb >> ((index % 8) * 8)) as u8;
+        }
+        Some(bytes)
+    }
+
+    pub fn from_hex(hex: &str) -> Option<BigUint> {
+        let padded = if hex.len() % 2 == 1 {
+            format!("0{}", hex)
+        } else {
+            hex.to_string()
+        };
+        crate::cryptography::cryptography::from_hex(&padded).map(|x| BigUint::from_bytes_be(&x))
+    }
+
+    pub fn to_hex(&self) -> String {
+        crate::cryptography::cryptography::to_hex(&self.to_bytes_be())
+    }
+
+    pub fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * LIMB_BITS - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub fn bit(&self, index: usize) -> bool {
+        match self.limbs.get(index / LIMB_BITS) {
+            Some(limb) => (limb >> (index % LIMB_BITS)) & 1 == 1,
+            None => false,
+        }
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        if self.limbs.len() <= index / LIMB_BITS {
+            self.limbs.resize(index / LIMB_BITS + 1, 0);
+        }
+        self.limbs[index / LIMB_BITS] |= 1 << (index % LIMB_BITS);
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_one(&self) -> bool {
+        self.limbs == [1]
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.limbs.first().is_none_or(|x| x & 1 == 0)
+    }
+
+    pub fn low_u64(&self) -> u64 {
+        self.limbs.first().copied().unwrap_or(0)
+    }
+
+    pub fn add(&self, other: &BigUint) -> BigUint {
+        let length = self.limbs.len().max(other.limbs.len());
+        let mut limbs = Vec::with_capacity(length + 1);
+        let mut carry = 0u64;
+        for index in 0..length {
+            let a = self.limbs.get(index).copied().unwrap_or(0);
+            let b = other.limbs.get(index).copied().unwrap_or(0);
+            let sum = a as u128 + b as u128 + carry as u128;
+            limbs.push(sum as u64);
+            carry = (sum >> LIMB_BITS) as u64;
+        }
+        limbs.push(carry);
+        BigUint::from_limbs(limbs)
+    }
+
+    /*
+       Panics if other is bigger, callers are expected to know which way round they are
+    */
+    pub fn sub(&self, other: &BigUint) -> BigUint {
+        let mut result = self.clone();
+        result.sub_assign(other);
+        result
+    }
+
+    fn sub_assign(&mut self, other: &BigUint) {
+        assert!(*self >= *other, "BigUint subtraction would go negative");
+        let mut borrow = 0u64;
+        for index in 0..self.limbs.len() {
+            let b = other.limbs.get(index).copied().unwrap_or(0);
+            let (difference, borrow_a) = self.limbs[index].overflowing_sub(b);
+            let (difference, borrow_b) = difference.overflowing_sub(borrow);
+            self.limbs[index] = difference;
+            borrow = (borrow_a || borrow_b) as u64;
+        }
+        self.normalize();
+    }
+
+    pub fn mul(&self, other: &BigUint) -> BigUint {
+        if self.is_zero() || other.is_zero() {
+            return BigUint::zero();
+        }
+        let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let product = (*a as u128) * (*b as u128) + limbs[i + j] as u128 + carry;
+                limbs[i + j] = product as u64;
+                carry = product >> LIMB_BITS;
+            }
+            limbs[i + other.limbs.len()] = carry as u64;
+        }
+        BigUint::from_limbs(limbs)
+    }
+
+    pub fn shl(&self, bits: usize) -> BigUint {
+        if self.is_zero() {
+            return BigUint::zero();
+        }
+        let (limb_shift, bit_shift) = (bits / LIMB_BITS, bits % LIMB_BITS);
+        let mut limbs = vec![0u64; limb_shift];
+        let mut carry = 0u64;
+        for limb in &self.limbs {
+            if bit_shift == 0 {
+                limbs.push(*limb);
+            } else {
+                limbs.push((limb << bit_shift) | carry);
+                carry = limb >> (LIMB_BITS - bit_shift);
+            }
+        }
+        limbs.push(carry);
+        BigUint::from_limbs(limbs)
+    }
+
+    pub fn shr(&self, bits: usize) -> BigUint {
+        let (limb_shift, bit_shift) = (bits / LIMB_BITS, bits % LIMB_BITS);
+        if limb_shift >= self.limbs.len() {
+            return BigUint::zero();
+        }
+        let source = &self.limbs[limb_shift..];
+        let limbs = (0..source.len())
+            .map(|index| {
+                if bit_shift == 0 {
+                    return source[index];
+                }
+                let high = source.get(index + 1).copied().unwrap_or(0);
+                (source[index] >> bit_shift) | (high << (LIMB_BITS - bit_shift))
+            })
+            .collect();
+        BigUint::from_limbs(limbs)
+    }
+
+    /*
+       Plain shift and subtract long division, it only costs as many steps as the quotient has bits so it is
+       cheap for the reductions and small quotients RSA actually does
+    */
+    pub fn div_rem(&self, divisor: &BigUint) -> (BigUint, BigUint) {
+        assert!(!divisor.is_zero(), "BigUint division by zero");
+        if *self < *divisor {
+            return (BigUint::zero(), self.clone());
+        }
+
+        let shift = self.bits() - divisor.bits();
+        let mut remainder = self.clone();
+        let mut quotient = BigUint::zero();
+        let mut shifted = divisor.shl(shift);
+        for index in (0..=shift).rev() {
+            if remainder >= shifted {
+                remainder.sub_assign(&shifted);
+                quotient.set_bit(index);
+            }
+            shifted = shifted.shr(1);
+        }
+        quotient.normalize();
+        (quotient, remainder)
+    }
+
+    pub fn rem(&self, modulus: &BigUint) -> BigUint {
+        self.div_rem(modulus).1
+    }
+
+    /*
+       Remainder by a single limb, a lot cheaper than div_rem for the small prime sieve
+    */
+    pub fn rem_u64(&self, modulus: u64) -> u64 {
+        assert!(modulus != 0, "BigUint division by zero");
+        self.limbs.iter().rev().fold(0u64, |acc, limb| {
+            ((((acc as u128) << LIMB_BITS) | *limb as u128) % modulus as u128) as u64
+        })
+    }
+
+    pub fn gcd(&self, other: &BigUint) -> BigUint {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        while !b.is_zero() {
+            let remainder = a.rem(&b);
+            a = b;
+            b = remainder;
+        }
+        a
+    }
+
+    /*
+       Extended Euclid with the coefficient kept reduced mod the modulus so it never goes negative
+    */
+    pub fn mod_inverse(&self, modulus: &BigUint) -> Option<BigUint> {
+        let (mut old_r, mut r) = (self.rem(modulus), modulus.clone());
+        let (mut old_s, mut s) = (BigUint::one(), BigUint::zero());
+
+        while !r.is_zero() {
+            let (quotient, remainder) = old_r.div_rem(&r);
+            old_r = std::mem::replace(&mut r, remainder);
+
+            let product = quotient.mul(&s).rem(modulus);
+            let next = old_s.add(modulus).sub(&product).rem(modulus);
+            old_s = std::mem::replace(&mut s, next);
+        }
+
+        old_r.is_one().then_some(old_s)
+    }
+
+    pub fn mod_pow(&self, exponent: &BigUint, modulus: &BigUint) -> BigUint {
+        if modulus.is_one() {
+            return BigUint::zero();
+        }
+        if !modulus.is_even() {
+            return Montgomery::new(modulus).pow(self, exponent);
+        }
+
+        let mut result = BigUint::one();
+        let base = self.rem(modulus);
+        for index in (0..exponent.bits()).rev() {
+            result = result.mul(&result).rem(modulus);
+            if exponent.bit(index) {
+                result = result.mul(&base).rem(modulus);
+            }
+        }
+        result
+    }
+
+    /*
+       Uniformly random with exactly the given number of bits available, the top one may still come out zero
+    */
+    pub fn random_bits(bits: usize) -> BigUint {
+        let mut bytes = vec![0u8; bits.div_ceil(8)];
+        rand::rng().fill_bytes(&mut bytes);
+        if !bits.is_multiple_of(8) {
+            bytes[0] &= (1u8 << (bits % 8)) - 1;
+        }
+        BigUint::from_bytes_be(&bytes)
+    }
+
+    /*
+       Uniformly random in [low, high), by rejection
+    */
+    pub fn random_range(low: &BigUint, high: &BigUint) -> BigUint {
+        let span = high.sub(low);
+        loop {
+            let candidate = BigUint::random_bits(span.bits());
+            if candidate < span {
+                return candidate.add(low);
+            }
+        }
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+/*
+   Montgomery form for an odd modulus n with R = 2^(64 * limbs of n). Values are kept as fixed length limb
+   vectors while in Montgomery form
+*/
+struct Montgomery {
+    modulus: BigUint,
+    length: usize,
+    /*
+       -n^-1 mod 2^64
+    */
+    inverse: u64,
+    /*
+       R mod n, the Montgomery form of one
+    */
+    one: Vec<u64>,
+    /*
+       R^2 mod n, multiplying by it moves a value into Montgomery form
+    */
+    r_squared: Vec<u64>,
+}
+
+impl Montgomery {
+    fn new(modulus: &BigUint) -> Montgomery {
+        let length = modulus.limbs.len();
+
+        // Newton's iteration doubles the correct low bits each round, 1 is right for the lowest bit of an odd number
+        let low = modulus.limbs[0];
+        let mut inverse = 1u64;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(low.wrapping_mul(inverse)));
+        }
+
+        let r = BigUint::one().shl(length * LIMB_BITS);
+        let one = r.rem(modulus);
+        let r_squared = one.mul(&one).rem(modulus);
+        Montgomery {
+            modulus: modulus.clone(),
+            length,
+            inverse: inverse.wrapping_neg(),
+            one: Self::padded(&one, length),
+            r_squared: Self::padded(&r_squared, length),
+        }
+    }
+
+    fn padded(value: &BigUint, length: usize) -> Vec<u64> {
+        let mut limbs = value.limbs.clone();
+        limbs.resize(length, 0);
+        limbs
+    }
+
+    /*
+       a * b * R^-1 mod n, coarsely integrated operand scanning
+    */
+    fn multiply(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let length = self.length;
+        let modulus = &self.modulus.limbs;
+        let mut t = vec![0u64; length + 2];
+
+        for b_limb in b.iter().take(length) {
+            let mut carry = 0u128;
+            for j in 0..length {
+                let sum = t[j] as u128 + (a[j] as u128) * (*b_limb as u128) + carry;
+                t[j] = sum as u64;
+                carry = sum >> LIMB_BITS;
+            }
+            let sum = t[length] as u128 + carry;
+            t[length] = sum as u64;
+            t[length + 1] = (sum >> LIMB_BITS) as u64;
+
+            let m = t[0].wrapping_mul(self.inverse);
+            let mut carry = (t[0] as u128 + (m as u128) * (modulus[0] as u128)) >> LIMB_BITS;
+            for j in 1..length {
+                let sum = t[j] as u128 + (m as u128) * (modulus[j] as u128) + carry;
+                t[j - 1] = sum as u64;
+                carry = sum >> LIMB_BITS;
+            }
+            let sum = t[length] as u128 + carry;
+            t[length - 1] = sum as u64;
+            t[length] = t[length + 1] + (sum >> LIMB_BITS) as u64;
+        }
+
+        let mut result = BigUint::from_limbs(t[..=length].to_vec());
+        if result >= self.modulus {
+            result.sub_assign(&self.modulus);
+        }
+        Self::padded(&result, length)
+    }
+
+    fn pow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        let base = Self::padded(&base.rem(&self.modulus), self.length);
+        let base = self.multiply(&base, &self.r_squared);
+
+        // Fixed window, table[i] holds base^i
+        let mut table = vec![self.one.clone(), base.clone()];
+        for index in 2..(1 << WINDOW_BITS) {
+            table.push(self.multiply(&table[index - 1], &base));
+        }
+
+        let mut result = self.one.clone();
+        let windows = exponent.bits().div_ceil(WINDOW_BITS);
+        for window in (0..windows).rev() {
+            for _ in 0..WINDOW_BITS {
+                result = self.multiply(&result, &result);
+            }
+            let digit = (0..WINDOW_BITS).fold(0, |acc, bit| {
+                acc | ((exponent.bit(window * WINDOW_BITS + bit) as usize) << bit)
+            });
+            if digit != 0 {
+                result = self.multiply(&result, &table[digit]);
+            }
+        }
+
+        let mut plain_one = vec![0u64; self.length];
+        plain_one[0] = 1;
+        BigUint::from_limbs(self.multiply(&result, &plain_one))
+    }
+}
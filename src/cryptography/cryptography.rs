@@ -12,6 +12,13 @@ pub trait Encryption {
     fn set_key(&mut self, key: &[u8]);
 
     fn get_key(&self) -> &[u8];
+
+    /*
+       True when the last decrypt threw the frame away (a replay, a bad counter), the plain ciphers never do
+    */
+    fn rejected_last_frame(&self) -> bool {
+        false
+    }
 }
 
 /*
@@ -51,3 +58,21 @@ impl EncryptionContext {
         }
     }
 }
+
+/*
+   Hex helpers for getting binary keys and ciphertext through the text based chat commands
+*/
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,295 @@
+use rand::RngCore;
+
+/*
+   Arithmetic modulo p = 2^255 - 19 and X25519 as per RFC 7748
+   https://www.rfc-editor.org/rfc/rfc7748
+
+   Field elements are 5 limbs of 51 bits so that limb products fit comfortably inside a u128
+*/
+pub const X25519_KEY_SIZE_BYTES: usize = 32;
+
+const MASK_51: u64 = (1 << 51) - 1;
+
+/*
+   (486662 - 2) / 4, the curve constant used by the ladder
+*/
+const A24: u64 = 121665;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FieldElement(pub(crate) [u64; 5]);
+
+impl FieldElement {
+    pub const ZERO: FieldElement = FieldElement([0, 0, 0, 0, 0]);
+    pub const ONE: FieldElement = FieldElement([1, 0, 0, 0, 0]);
+
+    /*
+       Little endian, the top bit is ignored as per the RFC
+    */
+    pub fn from_bytes(bytes: &[u8; 32]) -> FieldElement {
+        let load = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        FieldElement([
+            load(0) & MASK_51,
+            (load(6) >> 3) & MASK_51,
+            (load(12) >> 6) & MASK_51,
+            (load(19) >> 1) & MASK_51,
+            (load(24) >> 12) & MASK_51,
+        ])
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = Self::carry(self.0);
+
+        /*
+           Work out whether the value is still >= p, if it is subtract p by adding 19 and dropping bit 255
+        */
+        let mut quotient = (limbs[0] + 19) >> 51;
+        quotient = (limbs[1] + quotient) >> 51;
+        quotient = (limbs[2] + quotient) >> 51;
+        quotient = (limbs[3] + quotient) >> 51;
+        quotient = (limbs[4] + quotient) >> 51;
+
+        limbs[0] += 19 * quotient;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= MASK_51;
+        }
+        limbs[4] &= MASK_51;
+
+        let words = [
+            limbs[0] | (limbs[1] << 51),
+            (limbs[1] >> 13) | (limbs[2] << 38),
+            (limbs[2] >> 26) | (limbs[3] << 25),
+            (limbs[3] >> 39) | (limbs[4] << 12),
+        ];
+
+        let mut output = [0u8; 32];
+        for (i, word) in words.iter().enumerate() {
+            output[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+        output
+    }
+
+    /*
+       Pushes the bits above 51 in each limb up into the next one, wrapping the top limb back around times 19
+    */
+    fn carry(mut limbs: [u64; 5]) -> [u64; 5] {
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= MASK_51;
+        }
+        limbs[0] += 19 * (limbs[4] >> 51);
+        limbs[4] &= MASK_51;
+        limbs[1] += limbs[0] >> 51;
+        limbs[0] &= MASK_51;
+        limbs
+    }
+
+    pub fn add(&self, other: &FieldElement) -> FieldElement {
+        let mut limbs = [0u64; 5];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = self.0[i] + other.0[i];
+        }
+        FieldElement(Self::carry(limbs))
+    }
+
+    /*
+       Adds 4p before subtracting so no limb can go negative
+    */
+    pub fn sub(&self, other: &FieldElement) -> FieldElement {
+        const FOUR_P: [u64; 5] = [
+            0x1FFFFFFFFFFFB4,
+            0x1FFFFFFFFFFFFC,
+            0x1FFFFFFFFFFFFC,
+            0x1FFFFFFFFFFFFC,
+            0x1FFFFFFFFFFFFC,
+        ];
+        let mut limbs = [0u64; 5];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = (self.0[i] + FOUR_P[i]) - other.0[i];
+        }
+        FieldElement(Self::carry(limbs))
+    }
+
+    pub fn negate(&self) -> FieldElement {
+        FieldElement::ZERO.sub(self)
+    }
+
+    pub fn mul(&self, other: &FieldElement) -> FieldElement {
+        let a = self.0.map(|x| x as u128);
+        let b = other.0.map(|x| x as u128);
+        let b1 = b[1] * 19;
+        let b2 = b[2] * 19;
+        let b3 = b[3] * 19;
+        let b4 = b[4] * 19;
+
+        let r = [
+            a[0] * b[0] + a[1] * b4 + a[2] * b3 + a[3] * b2 + a[4] * b1,
+            a[0] * b[1] + a[1] * b[0] + a[2] * b4 + a[3] * b3 + a[4] * b2,
+            a[0] * b[2] + a[1] * b[1] + a[2] * b[0] + a[3] * b4 + a[4] * b3,
+            a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + a[4] * b4,
+            a[0] * b[4] + a[1] * b[3] + a[2] * b[2] + a[3] * b[1] + a[4] * b[0],
+        ];
+
+        Self::reduce_wide(r)
+    }
+
+    fn reduce_wide(mut r: [u128; 5]) -> FieldElement {
+        for i in 0..4 {
+            r[i + 1] += r[i] >> 51;
+            r[i] &= MASK_51 as u128;
+        }
+        r[0] += 19 * (r[4] >> 51);
+        r[4] &= MASK_51 as u128;
+        r[1] += r[0] >> 51;
+        r[0] &= MASK_51 as u128;
+
+        FieldElement(Self::carry(r.map(|x| x as u64)))
+    }
+
+    pub fn square(&self) -> FieldElement {
+        self.mul(self)
+    }
+
+    fn mul_small(&self, scalar: u64) -> FieldElement {
+        Self::reduce_wide(self.0.map(|x| x as u128 * scalar as u128))
+    }
+
+    /*
+       self^exponent where the exponent is little endian bytes, the exponents we use are all public constants
+    */
+    pub fn pow(&self, exponent: &[u8; 32]) -> FieldElement {
+        let mut result = FieldElement::ONE;
+        for i in (0..256).rev() {
+            result = result.square();
+            if (exponent[i / 8] >> (i % 8)) & 1 == 1 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    /*
+       Fermat's little theorem, x^(p - 2) = x^-1
+    */
+    pub fn invert(&self) -> FieldElement {
+        let mut exponent = [0xffu8; 32];
+        exponent[0] = 0xeb;
+        exponent[31] = 0x7f;
+        self.pow(&exponent)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.to_bytes() == [0u8; 32]
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    pub fn equals(&self, other: &FieldElement) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+
+    /*
+       Swaps a and b when swap is 1 without branching on it
+    */
+    pub fn conditional_swap(a: &mut FieldElement, b: &mut FieldElement, swap: u64) {
+        let mask = 0u64.wrapping_sub(swap);
+        for i in 0..5 {
+            let t = mask & (a.0[i] ^ b.0[i]);
+            a.0[i] ^= t;
+            b.0[i] ^= t;
+        }
+    }
+}
+
+fn clamp_scalar(scalar: &[u8; 32]) -> [u8; 32] {
+    let mut clamped = *scalar;
+    clamped[0] &= 248;
+    clamped[31] &= 127;
+    clamped[31] |= 64;
+    clamped
+}
+
+/*
+   Montgomery ladder straight out of section 5 of the RFC
+*/
+pub fn x25519(scalar: &[u8; 32], u_coordinate: &[u8; 32]) -> [u8; 32] {
+    let k = clamp_scalar(scalar);
+    let x_1 = FieldElement::from_bytes(u_coordinate);
+    let mut x_2 = FieldElement::ONE;
+    let mut z_2 = FieldElement::ZERO;
+    let mut x_3 = x_1;
+    let mut z_3 = FieldElement::ONE;
+    let mut swap = 0u64;
+
+    for t in (0..255).rev() {
+        let k_t = ((k[t / 8] >> (t % 8)) & 1) as u64;
+        swap ^= k_t;
+        FieldElement::conditional_swap(&mut x_2, &mut x_3, swap);
+        FieldElement::conditional_swap(&mut z_2, &mut z_3, swap);
+        swap = k_t;
+
+        let a = x_2.add(&z_2);
+        let aa = a.square();
+        let b = x_2.sub(&z_2);
+        let bb = b.square();
+        let e = aa.sub(&bb);
+        let c = x_3.add(&z_3);
+        let d = x_3.sub(&z_3);
+        let da = d.mul(&a);
+        let cb = c.mul(&b);
+
+        x_3 = da.add(&cb).square();
+        z_3 = x_1.mul(&da.sub(&cb).square());
+        x_2 = aa.mul(&bb);
+        z_2 = e.mul(&aa.add(&e.mul_small(A24)));
+    }
+
+    FieldElement::conditional_swap(&mut x_2, &mut x_3, swap);
+    FieldElement::conditional_swap(&mut z_2, &mut z_3, swap);
+
+    x_2.mul(&z_2.invert()).to_bytes()
+}
+
+pub fn x25519_base_point() -> [u8; 32] {
+    let mut base = [0u8; 32];
+    base[0] = 9;
+    base
+}
+
+/*
+   Key pair for Diffie-Hellman over Curve25519
+*/
+pub struct X25519KeyPair {
+    secret: [u8; X25519_KEY_SIZE_BYTES],
+    pub public: [u8; X25519_KEY_SIZE_BYTES],
+}
+
+impl X25519KeyPair {
+    pub fn generate() -> X25519KeyPair {
+        let mut secret = [0u8; X25519_KEY_SIZE_BYTES];
+        rand::rng().fill_bytes(&mut secret);
+        Self::from_secret(secret)
+    }
+
+    pub fn from_secret(secret: [u8; X25519_KEY_SIZE_BYTES]) -> X25519KeyPair {
+        let public = x25519(&secret, &x25519_base_point());
+        X25519KeyPair { secret, public }
+    }
+
+    /*
+       Returns None if the peer handed us a low order point, since the shared secret would be all zeros
+    */
+    pub fn diffie_hellman(
+        &self,
+        peer_public: &[u8; X25519_KEY_SIZE_BYTES],
+    ) -> Option<[u8; X25519_KEY_SIZE_BYTES]> {
+        let shared = x25519(&self.secret, peer_public);
+        if shared == [0u8; X25519_KEY_SIZE_BYTES] {
+            return None;
+        }
+        Some(shared)
+    }
+}
@@ -0,0 +1,137 @@
+use crate::cryptography::bignum::BigUint;
+
+/*
+   The little bit of ASN.1 DER the key formats need, definite lengths only
+*/
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_NULL: u8 = 0x05;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_SEQUENCE: u8 = 0x30;
+
+pub fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut output = vec![tag];
+    if contents.len() < 0x80 {
+        output.push(contents.len() as u8);
+    } else {
+        let length = contents.len().to_be_bytes();
+        let length = &length[length.iter().position(|x| *x != 0).unwrap_or(0)..];
+        output.push(0x80 | length.len() as u8);
+        output.extend_from_slice(length);
+    }
+    output.extend_from_slice(contents);
+    output
+}
+
+pub fn encode_sequence(parts: &[Vec<u8>]) -> Vec<u8> {
+    encode(TAG_SEQUENCE, &parts.concat())
+}
+
+/*
+   Non negative only, a zero byte goes in front when the top bit is set so it doesn't read as negative
+*/
+pub fn encode_integer(value: &BigUint) -> Vec<u8> {
+    let mut bytes = value.to_bytes_be();
+    if bytes.first().is_none_or(|x| x & 0x80 != 0) {
+        bytes.insert(0, 0);
+    }
+    encode(TAG_INTEGER, &bytes)
+}
+
+pub fn encode_bit_string(bytes: &[u8]) -> Vec<u8> {
+    let mut contents = vec![0u8];
+    contents.extend_from_slice(bytes);
+    encode(TAG_BIT_STRING, &contents)
+}
+
+pub fn encode_oid(arcs: &[u64]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    let mut push_arc = |mut arc: u64| {
+        let mut chunk = vec![(arc & 0x7f) as u8];
+        arc >>= 7;
+        while arc > 0 {
+            chunk.push(0x80 | (arc & 0x7f) as u8);
+            arc >>= 7;
+        }
+        contents.extend(chunk.iter().rev());
+    };
+    push_arc(arcs[0] * 40 + arcs[1]);
+    arcs[2..].iter().for_each(|x| push_arc(*x));
+    encode(TAG_OID, &contents)
+}
+
+pub struct DerReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    pub fn new(data: &'a [u8]) -> DerReader<'a> {
+        DerReader { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /*
+       Next element whatever it is, as its tag and contents. Lengths have to be in their shortest form, BER
+       leniency is how signature malleability creeps in
+    */
+    pub fn read_any(&mut self) -> Option<(u8, &'a [u8])> {
+        let tag = *self.data.first()?;
+        let first = *self.data.get(1)? as usize;
+        let (length, header) = if first < 0x80 {
+            (first, 2)
+        } else {
+            let count = first & 0x7f;
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let bytes = self.data.get(2..2 + count)?;
+            let length = bytes.iter().fold(0usize, |acc, x| (acc << 8) | *x as usize);
+            if bytes[0] == 0 || length < 0x80 {
+                return None;
+            }
+            (length, 2 + count)
+        };
+
+        let contents = self.data.get(header..header.checked_add(length)?)?;
+        self.data = &self.data[header + length..];
+        Some((tag, contents))
+    }
+
+    pub fn read(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.read_any()? {
+            (x, contents) if x == tag => Some(contents),
+            _ => None,
+        }
+    }
+
+    pub fn read_sequence(&mut self) -> Option<DerReader<'a>> {
+        self.read(TAG_SEQUENCE).map(DerReader::new)
+    }
+
+    pub fn read_integer(&mut self) -> Option<BigUint> {
+        let contents = self.read(TAG_INTEGER)?;
+        // Negative values and padding with a zero byte that isn't needed for the sign are both refused
+        match contents {
+            [] => return None,
+            [x, ..] if x & 0x80 != 0 => return None,
+            [0, x, ..] if x & 0x80 == 0 => return None,
+            _ => {}
+        }
+        Some(BigUint::from_bytes_be(contents))
+    }
+
+    pub fn read_bit_string(&mut self) -> Option<&'a [u8]> {
+        match self.read(TAG_BIT_STRING)?.split_first()? {
+            (0, bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
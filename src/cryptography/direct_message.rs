@@ -0,0 +1,158 @@
+use crate::cryptography::aes::{AESContext, AesMode, AesSize};
+use crate::cryptography::cryptography::{to_hex, Encryption};
+use crate::cryptography::curve25519::{X25519KeyPair, X25519_KEY_SIZE_BYTES};
+use crate::cryptography::sha256::{
+    constant_time_eq, hkdf, hmac_sha256, sha256, SHA256_OUTPUT_SIZE_BYTES,
+};
+
+/*
+   End to end encryption for direct messages. Both clients publish an X25519 public key through the server,
+   derive the same pairwise key from the Diffie-Hellman result and from then on the server only ever relays
+   frames it has no key for.
+
+   The server relaying the public keys could swap them for its own and sit in the middle, so each session has a
+   safety number worked out from both names and both public keys as that end saw them. The two people compare
+   it out of band (in person, over the phone), if it matches on both screens nobody swapped anything.
+
+   Frame layout is sequence number (8 bytes, big endian) | IV (16 bytes) | AES-256-CTR ciphertext | HMAC-SHA256
+   tag. The tag covers which end sent it and everything before it, each end numbers its frames from 0 and only
+   takes frames numbered past the last one it took, so frames can't be replayed, reordered or bounced back to
+   the end that sent them.
+*/
+const DIRECT_MESSAGE_INFO: &[u8] = b"kryptos direct message v1";
+const SAFETY_NUMBER_INFO: &[u8] = b"kryptos safety number v1";
+const SAFETY_NUMBER_BYTES: usize = 16;
+const SEQUENCE_SIZE_BYTES: usize = 8;
+const IV_SIZE_BYTES: usize = 16;
+
+pub struct DirectMessageSession {
+    encryption: AESContext,
+    mac_key: [u8; SHA256_OUTPUT_SIZE_BYTES],
+    safety_number: String,
+    /*
+       0 for the end whose name sorts first, 1 for the other
+    */
+    own_direction: u8,
+    next_send: u64,
+    next_receive: u64,
+}
+
+impl DirectMessageSession {
+    /*
+       Both ends sort the names and public keys so they land on the same key no matter who started it
+    */
+    pub fn new(
+        own_key: &X25519KeyPair,
+        own_name: &str,
+        peer_public: &[u8; X25519_KEY_SIZE_BYTES],
+        peer_name: &str,
+    ) -> Option<DirectMessageSession> {
+        let shared = own_key.diffie_hellman(peer_public)?;
+
+        let mut public_keys = [own_key.public, *peer_public];
+        public_keys.sort();
+        let salt = public_keys.concat();
+
+        let own_name = own_name.to_lowercase();
+        let peer_name = peer_name.to_lowercase();
+        let mut names = [own_name.clone(), peer_name.clone()];
+        names.sort();
+        let mut info = DIRECT_MESSAGE_INFO.to_vec();
+        for name in names.iter() {
+            info.push(0);
+            info.extend_from_slice(name.as_bytes());
+        }
+
+        let key_material = hkdf(&salt, &shared, &info, 64);
+        let mut mac_key = [0u8; SHA256_OUTPUT_SIZE_BYTES];
+        mac_key.copy_from_slice(&key_material[32..]);
+
+        let mut identities = [
+            (own_name.clone(), own_key.public),
+            (peer_name.clone(), *peer_public),
+        ];
+        identities.sort();
+
+        Some(DirectMessageSession {
+            encryption: AESContext::new(AesMode::CTR, AesSize::S256, Some(&key_material[..32])),
+            mac_key,
+            safety_number: safety_number(&identities),
+            own_direction: (own_name > peer_name) as u8,
+            next_send: 0,
+            next_receive: 0,
+        })
+    }
+
+    /*
+       The same on both ends unless someone swapped a public key on its way through the server
+    */
+    pub fn safety_number(&self) -> &str {
+        &self.safety_number
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut input = plaintext.to_vec();
+        let mut ciphertext = vec![0u8; input.len()];
+        self.encryption.encrypt(&mut input, &mut ciphertext);
+
+        let mut frame = self.next_send.to_be_bytes().to_vec();
+        frame.extend_from_slice(&ciphertext);
+        self.next_send += 1;
+
+        let tag = self.tag(self.own_direction, &frame);
+        frame.extend_from_slice(&tag);
+        frame
+    }
+
+    /*
+       Returns None when the frame was tampered with, was not meant for this session, came from this end or is
+       no newer than the last one opened
+    */
+    pub fn open(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        if frame.len() < SEQUENCE_SIZE_BYTES + IV_SIZE_BYTES + SHA256_OUTPUT_SIZE_BYTES {
+            return None;
+        }
+
+        let (body, tag) = frame.split_at(frame.len() - SHA256_OUTPUT_SIZE_BYTES);
+        if !constant_time_eq(&self.tag(1 - self.own_direction, body), tag) {
+            return None;
+        }
+
+        let (sequence, ciphertext) = body.split_at(SEQUENCE_SIZE_BYTES);
+        let sequence = u64::from_be_bytes(sequence.try_into().unwrap());
+        if sequence < self.next_receive {
+            return None;
+        }
+        self.next_receive = sequence.checked_add(1)?;
+
+        let mut input = ciphertext.to_vec();
+        let mut output = vec![0u8; input.len()];
+        self.encryption.decrypt(&mut input, &mut output);
+        Some(output)
+    }
+
+    fn tag(&self, direction: u8, body: &[u8]) -> [u8; SHA256_OUTPUT_SIZE_BYTES] {
+        let mut message = vec![direction];
+        message.extend_from_slice(body);
+        hmac_sha256(&self.mac_key, &message)
+    }
+}
+
+/*
+   First 16 bytes of SHA-256 over the label and each name with its public key, in name order, as 8 groups of
+   4 hex digits so it can be read out loud
+*/
+fn safety_number(identities: &[(String, [u8; X25519_KEY_SIZE_BYTES]); 2]) -> String {
+    let mut input = SAFETY_NUMBER_INFO.to_vec();
+    for (name, public) in identities {
+        input.push(0);
+        input.extend_from_slice(name.as_bytes());
+        input.push(0);
+        input.extend_from_slice(public);
+    }
+    sha256(&input)[..SAFETY_NUMBER_BYTES]
+        .chunks(2)
+        .map(to_hex)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,47 @@
+use crate::cryptography::cryptography::Encryption;
+
+/*
+   One cipher per direction. Connections send under one key and read under another, so a frame can't be
+   reflected back at the end that sent it and the two directions never share a keystream
+*/
+pub struct DuplexContext {
+    sending: Box<dyn Encryption>,
+    receiving: Box<dyn Encryption>,
+}
+
+impl DuplexContext {
+    pub fn new(sending: Box<dyn Encryption>, receiving: Box<dyn Encryption>) -> DuplexContext {
+        DuplexContext { sending, receiving }
+    }
+}
+
+impl Encryption for DuplexContext {
+    fn initialize_context(&mut self) {
+        self.sending.initialize_context();
+        self.receiving.initialize_context();
+    }
+
+    fn encrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        self.sending.encrypt(input, output);
+    }
+
+    fn decrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        self.receiving.decrypt(input, output);
+    }
+
+    /*
+       Both directions end up on the same key, only for callers that don't care which way a frame goes
+    */
+    fn set_key(&mut self, key: &[u8]) {
+        self.sending.set_key(key);
+        self.receiving.set_key(key);
+    }
+
+    fn get_key(&self) -> &[u8] {
+        self.sending.get_key()
+    }
+
+    fn rejected_last_frame(&self) -> bool {
+        self.receiving.rejected_last_frame()
+    }
+}
@@ -0,0 +1,383 @@
+use crate::cryptography::bignum::BigUint;
+use crate::cryptography::curve25519::FieldElement;
+use crate::cryptography::der::{
+    encode, encode_bit_string, encode_integer, encode_oid, encode_sequence, DerReader,
+    TAG_OCTET_STRING, TAG_OID,
+};
+use crate::cryptography::pem::{base64_encode, decode_pem, encode_pem};
+use crate::cryptography::sha256::sha256;
+use crate::cryptography::sha512::{Sha512, SHA512_OUTPUT_SIZE_BYTES};
+use rand::RngCore;
+
+/*
+   Ed25519 signatures as per RFC 8032 section 5.1
+   https://www.rfc-editor.org/rfc/rfc8032
+
+   Points live on the twisted Edwards curve -x^2 + y^2 = 1 + d x^2 y^2 over the same field as X25519, in extended
+   coordinates (X : Y : Z : T) with x = X / Z, y = Y / Z and x * y = T / Z. Scalar multiplication always does the
+   add and picks the result without branching, the arithmetic mod the group order goes through BigUint and is not
+   constant time
+*/
+pub const ED25519_PUBLIC_KEY_SIZE_BYTES: usize = 32;
+pub const ED25519_SEED_SIZE_BYTES: usize = 32;
+pub const ED25519_SIGNATURE_SIZE_BYTES: usize = 64;
+
+const ED25519_OID: [u64; 4] = [1, 3, 101, 112];
+pub const PKCS8_PRIVATE_LABEL: &str = "PRIVATE KEY";
+pub const SPKI_PUBLIC_LABEL: &str = "PUBLIC KEY";
+pub const SSH_KEY_TYPE: &str = "ssh-ed25519";
+
+/*
+   d = -121665 / 121666, 2d and sqrt(-1) = 2^((p - 1) / 4), little endian
+*/
+const D_BYTES: [u8; 32] = [
+    0xa3, 0x78, 0x59, 0x13, 0xca, 0x4d, 0xeb, 0x75, 0xab, 0xd8, 0x41, 0x41, 0x4d, 0x0a, 0x70, 0x00,
+    0x98, 0xe8, 0x79, 0x77, 0x79, 0x40, 0xc7, 0x8c, 0x73, 0xfe, 0x6f, 0x2b, 0xee, 0x6c, 0x03, 0x52,
+];
+const D2_BYTES: [u8; 32] = [
+    0x59, 0xf1, 0xb2, 0x26, 0x94, 0x9b, 0xd6, 0xeb, 0x56, 0xb1, 0x83, 0x82, 0x9a, 0x14, 0xe0, 0x00,
+    0x30, 0xd1, 0xf3, 0xee, 0xf2, 0x80, 0x8e, 0x19, 0xe7, 0xfc, 0xdf, 0x56, 0xdc, 0xd9, 0x06, 0x24,
+];
+const SQRT_MINUS_ONE_BYTES: [u8; 32] = [
+    0xb0, 0xa0, 0x0e, 0x4a, 0x27, 0x1b, 0xee, 0xc4, 0x78, 0xe4, 0x2f, 0xad, 0x06, 0x18, 0x43, 0x2f,
+    0xa7, 0xd7, 0xfb, 0x3d, 0x99, 0x00, 0x4d, 0x2b, 0x0b, 0xdf, 0xc1, 0x4f, 0x80, 0x24, 0x83, 0x2b,
+];
+
+/*
+   (p - 5) / 8, the exponent for the square root in decompression
+*/
+const SQRT_EXPONENT: [u8; 32] = [
+    0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f,
+];
+
+/*
+   The base point is y = 4/5 with x even, this is its encoding
+*/
+const BASE_POINT_BYTES: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+/*
+   Order of the base point, 2^252 + 27742317777372353535851937790883648493
+*/
+const GROUP_ORDER_HEX: &str = "1000000000000000000000000000000014def9dea2f79cd65812631a5cf5d3ed";
+
+#[derive(Clone, Copy)]
+struct EdwardsPoint {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+    t: FieldElement,
+}
+
+impl EdwardsPoint {
+    const IDENTITY: EdwardsPoint = EdwardsPoint {
+        x: FieldElement::ZERO,
+        y: FieldElement::ONE,
+        z: FieldElement::ONE,
+        t: FieldElement::ZERO,
+    };
+
+    fn base() -> EdwardsPoint {
+        EdwardsPoint::decompress(&BASE_POINT_BYTES).unwrap()
+    }
+
+    /*
+       add-2008-hwcd-3, complete for a = -1 so it doubles as well
+    */
+    fn add(&self, other: &EdwardsPoint) -> EdwardsPoint {
+        let d2 = FieldElement::from_bytes(&D2_BYTES);
+        let a = self.y.sub(&self.x).mul(&other.y.sub(&other.x));
+        let b = self.y.add(&self.x).mul(&other.y.add(&other.x));
+        let c = self.t.mul(&d2).mul(&other.t);
+        let d = self.z.add(&self.z).mul(&other.z);
+        let e = b.sub(&a);
+        let f = d.sub(&c);
+        let g = d.add(&c);
+        let h = b.add(&a);
+
+        EdwardsPoint {
+            x: e.mul(&f),
+            y: g.mul(&h),
+            z: f.mul(&g),
+            t: e.mul(&h),
+        }
+    }
+
+    /*
+       Double and always add, the scalar is little endian
+    */
+    fn multiply(&self, scalar: &[u8; 32]) -> EdwardsPoint {
+        let mut result = EdwardsPoint::IDENTITY;
+        for i in (0..256).rev() {
+            result = result.add(&result);
+            let mut sum = result.add(self);
+            let bit = ((scalar[i / 8] >> (i % 8)) & 1) as u64;
+            FieldElement::conditional_swap(&mut result.x, &mut sum.x, bit);
+            FieldElement::conditional_swap(&mut result.y, &mut sum.y, bit);
+            FieldElement::conditional_swap(&mut result.z, &mut sum.z, bit);
+            FieldElement::conditional_swap(&mut result.t, &mut sum.t, bit);
+        }
+        result
+    }
+
+    /*
+       y with the sign of x in the top bit
+    */
+    fn compress(&self) -> [u8; 32] {
+        let z_inverse = self.z.invert();
+        let x = self.x.mul(&z_inverse);
+        let mut bytes = self.y.mul(&z_inverse).to_bytes();
+        bytes[31] |= (x.is_negative() as u8) << 7;
+        bytes
+    }
+
+    /*
+       Section 5.1.3, None for encodings that aren't canonical or aren't on the curve
+    */
+    fn decompress(bytes: &[u8; 32]) -> Option<EdwardsPoint> {
+        let sign = bytes[31] >> 7;
+        let mut y_bytes = *bytes;
+        y_bytes[31] &= 0x7f;
+        let y = FieldElement::from_bytes(&y_bytes);
+        if y.to_bytes() != y_bytes {
+            return None;
+        }
+
+        let y_squared = y.square();
+        let u = y_squared.sub(&FieldElement::ONE);
+        let v = y_squared
+            .mul(&FieldElement::from_bytes(&D_BYTES))
+            .add(&FieldElement::ONE);
+
+        // x = u v^3 (u v^7)^((p - 5) / 8)
+        let v3 = v.square().mul(&v);
+        let v7 = v3.square().mul(&v);
+        let mut x = u.mul(&v3).mul(&u.mul(&v7).pow(&SQRT_EXPONENT));
+
+        let check = v.mul(&x.square());
+        if !check.equals(&u) {
+            if !check.equals(&u.negate()) {
+                return None;
+            }
+            x = x.mul(&FieldElement::from_bytes(&SQRT_MINUS_ONE_BYTES));
+        }
+
+        if x.is_zero() && sign == 1 {
+            return None;
+        }
+        if x.is_negative() as u8 != sign {
+            x = x.negate();
+        }
+
+        Some(EdwardsPoint {
+            x,
+            y,
+            z: FieldElement::ONE,
+            t: x.mul(&y),
+        })
+    }
+}
+
+fn group_order() -> BigUint {
+    BigUint::from_hex(GROUP_ORDER_HEX).unwrap()
+}
+
+/*
+   Little endian bytes of any length reduced mod the group order
+*/
+fn reduce_scalar(bytes: &[u8]) -> BigUint {
+    let big_endian: Vec<u8> = bytes.iter().rev().copied().collect();
+    BigUint::from_bytes_be(&big_endian).rem(&group_order())
+}
+
+/*
+   A reduced scalar back out as 32 little endian bytes
+*/
+fn scalar_bytes(scalar: &BigUint) -> [u8; 32] {
+    let mut bytes = scalar.to_bytes_be_padded(32).unwrap();
+    bytes.reverse();
+    bytes.try_into().unwrap()
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; SHA512_OUTPUT_SIZE_BYTES] {
+    let mut context = Sha512::new();
+    parts.iter().for_each(|x| context.update(x));
+    context.finalize()
+}
+
+pub struct Ed25519KeyPair {
+    seed: [u8; ED25519_SEED_SIZE_BYTES],
+    scalar: [u8; 32],
+    prefix: [u8; 32],
+    pub public: [u8; ED25519_PUBLIC_KEY_SIZE_BYTES],
+}
+
+impl Ed25519KeyPair {
+    pub fn generate() -> Ed25519KeyPair {
+        let mut seed = [0u8; ED25519_SEED_SIZE_BYTES];
+        rand::rng().fill_bytes(&mut seed);
+        Self::from_seed(seed)
+    }
+
+    /*
+       Section 5.1.5, the private key is the 32 byte seed and everything else is derived from it
+    */
+    pub fn from_seed(seed: [u8; ED25519_SEED_SIZE_BYTES]) -> Ed25519KeyPair {
+        let digest = hash(&[&seed]);
+        let mut scalar: [u8; 32] = digest[..32].try_into().unwrap();
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+
+        Ed25519KeyPair {
+            seed,
+            scalar,
+            prefix: digest[32..].try_into().unwrap(),
+            public: EdwardsPoint::base().multiply(&scalar).compress(),
+        }
+    }
+
+    pub fn seed(&self) -> &[u8; ED25519_SEED_SIZE_BYTES] {
+        &self.seed
+    }
+
+    /*
+       Section 5.1.6, deterministic so the same message always gets the same signature
+    */
+    pub fn sign(&self, message: &[u8]) -> [u8; ED25519_SIGNATURE_SIZE_BYTES] {
+        let r = reduce_scalar(&hash(&[&self.prefix, message]));
+        let big_r = EdwardsPoint::base().multiply(&scalar_bytes(&r)).compress();
+
+        let k = reduce_scalar(&hash(&[&big_r, &self.public, message]));
+        let s = r
+            .add(&k.mul(&reduce_scalar(&self.scalar)))
+            .rem(&group_order());
+
+        let mut signature = [0u8; ED25519_SIGNATURE_SIZE_BYTES];
+        signature[..32].copy_from_slice(&big_r);
+        signature[32..].copy_from_slice(&scalar_bytes(&s));
+        signature
+    }
+
+    /*
+       PKCS#8 as in RFC 8410, the seed wrapped in an extra OCTET STRING
+    */
+    pub fn to_pkcs8_pem(&self) -> String {
+        let der = encode_sequence(&[
+            encode_integer(&BigUint::zero()),
+            encode_sequence(&[encode_oid(&ED25519_OID)]),
+            encode(TAG_OCTET_STRING, &encode(TAG_OCTET_STRING, &self.seed)),
+        ]);
+        encode_pem(PKCS8_PRIVATE_LABEL, &der)
+    }
+
+    pub fn from_pkcs8_pem(text: &str) -> Option<Ed25519KeyPair> {
+        let (label, der) = decode_pem(text)?;
+        if label != PKCS8_PRIVATE_LABEL {
+            return None;
+        }
+        let mut outer = DerReader::new(&der);
+        let mut sequence = outer.read_sequence()?;
+        if !sequence.read_integer()?.is_zero() || !check_algorithm(&mut sequence) {
+            return None;
+        }
+        let seed = DerReader::new(sequence.read(TAG_OCTET_STRING)?).read(TAG_OCTET_STRING)?;
+        Some(Self::from_seed(seed.try_into().ok()?))
+    }
+}
+
+/*
+   SubjectPublicKeyInfo as in RFC 8410, what openssl pkey -pubout writes
+*/
+pub fn public_key_to_spki_pem(public: &[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]) -> String {
+    let der = encode_sequence(&[
+        encode_sequence(&[encode_oid(&ED25519_OID)]),
+        encode_bit_string(public),
+    ]);
+    encode_pem(SPKI_PUBLIC_LABEL, &der)
+}
+
+pub fn public_key_from_spki_pem(text: &str) -> Option<[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]> {
+    let (label, der) = decode_pem(text)?;
+    if label != SPKI_PUBLIC_LABEL {
+        return None;
+    }
+    let mut sequence = DerReader::new(&der).read_sequence()?;
+    if !check_algorithm(&mut sequence) {
+        return None;
+    }
+    sequence.read_bit_string()?.try_into().ok()
+}
+
+fn check_algorithm(sequence: &mut DerReader) -> bool {
+    sequence
+        .read_sequence()
+        .and_then(|mut x| x.read(TAG_OID))
+        .is_some_and(|x| encode_oid(&ED25519_OID)[2..] == *x)
+}
+
+/*
+   Section 5.1.7 without the cofactor, [S]B has to come out exactly as R + [k]A
+*/
+pub fn ed25519_verify(
+    public: &[u8; ED25519_PUBLIC_KEY_SIZE_BYTES],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    if signature.len() != ED25519_SIGNATURE_SIZE_BYTES {
+        return false;
+    }
+    let (big_r, s) = signature.split_at(32);
+    let s_value = reduce_scalar(s);
+    if scalar_bytes(&s_value) != s {
+        return false;
+    }
+
+    let a = match EdwardsPoint::decompress(public) {
+        Some(x) => x,
+        None => return false,
+    };
+    let r = match EdwardsPoint::decompress(big_r.try_into().unwrap()) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    let k = reduce_scalar(&hash(&[big_r, public, message]));
+    let left = EdwardsPoint::base().multiply(s.try_into().unwrap());
+    let right = r.add(&a.multiply(&scalar_bytes(&k)));
+    left.compress() == right.compress()
+}
+
+/*
+   Same as ssh-keygen -l, SHA-256 over the SSH wire encoding of the key, base64 without the padding
+*/
+pub fn fingerprint(public: &[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]) -> String {
+    let encoded = base64_encode(&sha256(&ssh_public_key_blob(public)));
+    format!("SHA256:{}", encoded.trim_end_matches('='))
+}
+
+/*
+   string "ssh-ed25519" then string key, each prefixed with a 4 byte big endian length as in RFC 8709
+*/
+pub fn ssh_public_key_blob(public: &[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    for part in [SSH_KEY_TYPE.as_bytes(), public] {
+        blob.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        blob.extend_from_slice(part);
+    }
+    blob
+}
+
+pub fn public_key_from_ssh_blob(blob: &[u8]) -> Option<[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]> {
+    let expected_prefix = ssh_public_key_blob(&[0u8; ED25519_PUBLIC_KEY_SIZE_BYTES]);
+    let prefix_length = expected_prefix.len() - ED25519_PUBLIC_KEY_SIZE_BYTES;
+    if blob.len() != expected_prefix.len()
+        || blob[..prefix_length] != expected_prefix[..prefix_length]
+    {
+        return None;
+    }
+    blob[prefix_length..].try_into().ok()
+}
@@ -1,5 +1,21 @@
+pub mod aead;
 pub mod aes;
+pub mod bignum;
 
 pub mod cryptography;
+pub mod curve25519;
+pub mod der;
+pub mod direct_message;
+pub mod duplex;
+pub mod ed25519;
+pub mod null_cipher;
+pub mod p256;
+pub mod pem;
+pub mod ratchet;
 pub mod rc4;
+pub mod rsa;
 mod salsa20;
+pub mod scrypt;
+pub mod sequence;
+pub mod sha256;
+pub mod sha512;
@@ -0,0 +1,26 @@
+use crate::cryptography::cryptography::Encryption;
+
+/*
+   Passes everything through untouched, for the plaintext telnet listener only. It is never offered in the
+   handshake, a connection only ends up with it by coming in on that listener
+*/
+pub struct NullCipher;
+
+impl Encryption for NullCipher {
+    fn initialize_context(&mut self) {}
+
+    fn encrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        output.clear();
+        output.extend_from_slice(input);
+    }
+
+    fn decrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        self.encrypt(input, output);
+    }
+
+    fn set_key(&mut self, _key: &[u8]) {}
+
+    fn get_key(&self) -> &[u8] {
+        &[]
+    }
+}
@@ -0,0 +1,674 @@
+use crate::cryptography::bignum::BigUint;
+use crate::cryptography::der::{encode_integer, encode_sequence, DerReader};
+use crate::cryptography::sha256::{hmac_sha256, sha256, SHA256_OUTPUT_SIZE_BYTES};
+use rand::RngCore;
+
+/*
+   NIST P-256 (secp256r1) as per FIPS 186-4 and SEC 1
+   https://www.secg.org/sec1-v2.pdf
+
+   Field elements and scalars are 4 little endian 64 bit limbs kept in Montgomery form, with the same code doing
+   the arithmetic mod p and mod the group order n. Everything that touches a secret, the field arithmetic, point
+   addition and scalar multiplication, runs the same instructions whatever the values are: the formulas are the
+   complete ones from Renes, Costello and Batina (https://eprint.iacr.org/2015/1060) so the point at infinity and
+   doubling need no special cases, and the ladder swaps points with masks rather than branching.
+
+   ECDSA uses SHA-256 and deterministic nonces from RFC 6979 https://www.rfc-editor.org/rfc/rfc6979
+*/
+pub const P256_SCALAR_SIZE_BYTES: usize = 32;
+pub const P256_UNCOMPRESSED_POINT_SIZE_BYTES: usize = 65;
+pub const P256_COMPRESSED_POINT_SIZE_BYTES: usize = 33;
+pub const P256_SIGNATURE_SIZE_BYTES: usize = 64;
+
+type Limbs = [u64; 4];
+
+/*
+   A modulus with what Montgomery multiplication needs to go with it
+*/
+struct Modulus {
+    value: Limbs,
+    /*
+       -m^-1 mod 2^64
+    */
+    inverse: u64,
+    /*
+       2^512 mod m, multiplying by it moves a value into Montgomery form
+    */
+    r_squared: Limbs,
+}
+
+/*
+   p = 2^256 - 2^224 + 2^192 + 2^96 - 1
+*/
+const FIELD: Modulus = Modulus {
+    value: [
+        0xffffffffffffffff,
+        0x00000000ffffffff,
+        0x0000000000000000,
+        0xffffffff00000001,
+    ],
+    inverse: 0x0000000000000001,
+    r_squared: [
+        0x0000000000000003,
+        0xfffffffbffffffff,
+        0xfffffffffffffffe,
+        0x00000004fffffffd,
+    ],
+};
+
+/*
+   n, the order of the base point
+*/
+const ORDER: Modulus = Modulus {
+    value: [
+        0xf3b9cac2fc632551,
+        0xbce6faada7179e84,
+        0xffffffffffffffff,
+        0xffffffff00000000,
+    ],
+    inverse: 0xccd1c8aaee00bc4f,
+    r_squared: [
+        0x83244c95be79eea2,
+        0x4699799c49bd6fa6,
+        0x2845b2392b6bec59,
+        0x66e12d94f3d95620,
+    ],
+};
+
+/*
+   Curve y^2 = x^3 - 3x + b, b and the base point big endian as in the standard
+*/
+const B_BYTES: [u8; 32] = [
+    0x5a, 0xc6, 0x35, 0xd8, 0xaa, 0x3a, 0x93, 0xe7, 0xb3, 0xeb, 0xbd, 0x55, 0x76, 0x98, 0x86, 0xbc,
+    0x65, 0x1d, 0x06, 0xb0, 0xcc, 0x53, 0xb0, 0xf6, 0x3b, 0xce, 0x3c, 0x3e, 0x27, 0xd2, 0x60, 0x4b,
+];
+const GX_BYTES: [u8; 32] = [
+    0x6b, 0x17, 0xd1, 0xf2, 0xe1, 0x2c, 0x42, 0x47, 0xf8, 0xbc, 0xe6, 0xe5, 0x63, 0xa4, 0x40, 0xf2,
+    0x77, 0x03, 0x7d, 0x81, 0x2d, 0xeb, 0x33, 0xa0, 0xf4, 0xa1, 0x39, 0x45, 0xd8, 0x98, 0xc2, 0x96,
+];
+const GY_BYTES: [u8; 32] = [
+    0x4f, 0xe3, 0x42, 0xe2, 0xfe, 0x1a, 0x7f, 0x9b, 0x8e, 0xe7, 0xeb, 0x4a, 0x7c, 0x0f, 0x9e, 0x16,
+    0x2b, 0xce, 0x33, 0x57, 0x6b, 0x31, 0x5e, 0xce, 0xcb, 0xb6, 0x40, 0x68, 0x37, 0xbf, 0x51, 0xf5,
+];
+
+/*
+   Picks b where mask is all ones and a where it is zero
+*/
+fn select(a: &Limbs, b: &Limbs, mask: u64) -> Limbs {
+    [0, 1, 2, 3].map(|i| a[i] ^ (mask & (a[i] ^ b[i])))
+}
+
+fn add_with_carry(a: &Limbs, b: &Limbs) -> (Limbs, u64) {
+    let mut result = [0u64; 4];
+    let mut carry = 0u64;
+    for i in 0..4 {
+        let sum = a[i] as u128 + b[i] as u128 + carry as u128;
+        result[i] = sum as u64;
+        carry = (sum >> 64) as u64;
+    }
+    (result, carry)
+}
+
+fn sub_with_borrow(a: &Limbs, b: &Limbs) -> (Limbs, u64) {
+    let mut result = [0u64; 4];
+    let mut borrow = 0u64;
+    for i in 0..4 {
+        let difference = (a[i] as u128).wrapping_sub(b[i] as u128 + borrow as u128);
+        result[i] = difference as u64;
+        borrow = ((difference >> 64) as u64) & 1;
+    }
+    (result, borrow)
+}
+
+fn limbs_from_bytes(bytes: &[u8; 32]) -> Limbs {
+    [0, 1, 2, 3].map(|i| u64::from_be_bytes(bytes[24 - i * 8..32 - i * 8].try_into().unwrap()))
+}
+
+fn limbs_to_bytes(limbs: &Limbs) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (i, limb) in limbs.iter().enumerate() {
+        bytes[24 - i * 8..32 - i * 8].copy_from_slice(&limb.to_be_bytes());
+    }
+    bytes
+}
+
+impl Modulus {
+    fn add(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (sum, carry) = add_with_carry(a, b);
+        let (reduced, borrow) = sub_with_borrow(&sum, &self.value);
+        // Keep the reduced value if the sum overflowed or didn't need to borrow to take m off
+        let keep_reduced = carry | (borrow ^ 1);
+        select(&sum, &reduced, 0u64.wrapping_sub(keep_reduced))
+    }
+
+    fn sub(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (difference, borrow) = sub_with_borrow(a, b);
+        let correction = select(&[0; 4], &self.value, 0u64.wrapping_sub(borrow));
+        add_with_carry(&difference, &correction).0
+    }
+
+    fn negate(&self, a: &Limbs) -> Limbs {
+        self.sub(&[0; 4], a)
+    }
+
+    /*
+       a * b * 2^-256 mod m, coarsely integrated operand scanning with a masked final subtraction
+    */
+    fn mul(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let mut t = [0u64; 6];
+        for b_limb in b {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let sum = t[j] as u128 + (a[j] as u128) * (*b_limb as u128) + carry;
+                t[j] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = t[4] as u128 + carry;
+            t[4] = sum as u64;
+            t[5] = (sum >> 64) as u64;
+
+            let m = t[0].wrapping_mul(self.inverse);
+            let mut carry = (t[0] as u128 + (m as u128) * (self.value[0] as u128)) >> 64;
+            for j in 1..4 {
+                let sum = t[j] as u128 + (m as u128) * (self.value[j] as u128) + carry;
+                t[j - 1] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = t[4] as u128 + carry;
+            t[3] = sum as u64;
+            t[4] = t[5] + (sum >> 64) as u64;
+        }
+
+        let result = [t[0], t[1], t[2], t[3]];
+        let (reduced, borrow) = sub_with_borrow(&result, &self.value);
+        let keep_reduced = t[4] | (borrow ^ 1);
+        select(&result, &reduced, 0u64.wrapping_sub(keep_reduced & 1))
+    }
+
+    fn square(&self, a: &Limbs) -> Limbs {
+        self.mul(a, a)
+    }
+
+    fn to_montgomery(&self, a: &Limbs) -> Limbs {
+        self.mul(a, &self.r_squared)
+    }
+
+    fn montgomery_reduce(&self, a: &Limbs) -> Limbs {
+        self.mul(a, &[1, 0, 0, 0])
+    }
+
+    fn one(&self) -> Limbs {
+        self.to_montgomery(&[1, 0, 0, 0])
+    }
+
+    /*
+       Big endian bytes into Montgomery form, None unless the value is already below the modulus
+    */
+    fn decode(&self, bytes: &[u8; 32]) -> Option<Limbs> {
+        let limbs = limbs_from_bytes(bytes);
+        match sub_with_borrow(&limbs, &self.value).1 {
+            1 => Some(self.to_montgomery(&limbs)),
+            _ => None,
+        }
+    }
+
+    /*
+       Any 256 bit value, reduced by taking the modulus off once, enough since both moduli are above 2^255
+    */
+    fn decode_reduced(&self, bytes: &[u8; 32]) -> Limbs {
+        let limbs = limbs_from_bytes(bytes);
+        let (reduced, borrow) = sub_with_borrow(&limbs, &self.value);
+        self.to_montgomery(&select(&limbs, &reduced, 0u64.wrapping_sub(borrow ^ 1)))
+    }
+
+    fn encode(&self, a: &Limbs) -> [u8; 32] {
+        limbs_to_bytes(&self.montgomery_reduce(a))
+    }
+
+    /*
+       a^exponent for an exponent that is public, only ever m - 2 and (p + 1) / 4 here
+    */
+    fn pow(&self, a: &Limbs, exponent: &Limbs) -> Limbs {
+        let mut result = self.one();
+        for i in (0..256).rev() {
+            result = self.square(&result);
+            if (exponent[i / 64] >> (i % 64)) & 1 == 1 {
+                result = self.mul(&result, a);
+            }
+        }
+        result
+    }
+
+    /*
+       Fermat's little theorem, a^(m - 2) = a^-1, zero stays zero
+    */
+    fn invert(&self, a: &Limbs) -> Limbs {
+        let exponent = sub_with_borrow(&self.value, &[2, 0, 0, 0]).0;
+        self.pow(a, &exponent)
+    }
+
+    fn is_zero(&self, a: &Limbs) -> bool {
+        a.iter().fold(0, |acc, x| acc | x) == 0
+    }
+}
+
+/*
+   Projective coordinates (X : Y : Z) with x = X / Z and y = Y / Z, the point at infinity is (0 : 1 : 0)
+*/
+#[derive(Clone, Copy, Debug)]
+struct ProjectivePoint {
+    x: Limbs,
+    y: Limbs,
+    z: Limbs,
+}
+
+impl ProjectivePoint {
+    fn identity() -> ProjectivePoint {
+        ProjectivePoint {
+            x: [0; 4],
+            y: FIELD.one(),
+            z: [0; 4],
+        }
+    }
+
+    fn base() -> ProjectivePoint {
+        ProjectivePoint::from_affine(
+            FIELD.decode(&GX_BYTES).unwrap(),
+            FIELD.decode(&GY_BYTES).unwrap(),
+        )
+    }
+
+    fn from_affine(x: Limbs, y: Limbs) -> ProjectivePoint {
+        ProjectivePoint {
+            x,
+            y,
+            z: FIELD.one(),
+        }
+    }
+
+    fn is_identity(&self) -> bool {
+        FIELD.is_zero(&self.z)
+    }
+
+    /*
+       Algorithm 4 of Renes, Costello and Batina, complete addition for a = -3. It doubles as well
+    */
+    fn add(&self, other: &ProjectivePoint) -> ProjectivePoint {
+        let f = &FIELD;
+        let b = f.decode(&B_BYTES).unwrap();
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&other.x, &other.y, &other.z);
+
+        let mut t0 = f.mul(x1, x2);
+        let mut t1 = f.mul(y1, y2);
+        let mut t2 = f.mul(z1, z2);
+        let mut t3 = f.add(x1, y1);
+        let mut t4 = f.add(x2, y2);
+        t3 = f.mul(&t3, &t4);
+        t4 = f.add(&t0, &t1);
+        t3 = f.sub(&t3, &t4);
+        t4 = f.add(y1, z1);
+        let mut x3 = f.add(y2, z2);
+        t4 = f.mul(&t4, &x3);
+        x3 = f.add(&t1, &t2);
+        t4 = f.sub(&t4, &x3);
+        x3 = f.add(x1, z1);
+        let mut y3 = f.add(x2, z2);
+        x3 = f.mul(&x3, &y3);
+        y3 = f.add(&t0, &t2);
+        y3 = f.sub(&x3, &y3);
+        let mut z3 = f.mul(&b, &t2);
+        x3 = f.sub(&y3, &z3);
+        z3 = f.add(&x3, &x3);
+        x3 = f.add(&x3, &z3);
+        z3 = f.sub(&t1, &x3);
+        x3 = f.add(&t1, &x3);
+        y3 = f.mul(&b, &y3);
+        t1 = f.add(&t2, &t2);
+        t2 = f.add(&t1, &t2);
+        y3 = f.sub(&y3, &t2);
+        y3 = f.sub(&y3, &t0);
+        t1 = f.add(&y3, &y3);
+        y3 = f.add(&t1, &y3);
+        t1 = f.add(&t0, &t0);
+        t0 = f.add(&t1, &t0);
+        t0 = f.sub(&t0, &t2);
+        t1 = f.mul(&t4, &y3);
+        t2 = f.mul(&t0, &y3);
+        y3 = f.mul(&x3, &z3);
+        y3 = f.add(&y3, &t2);
+        x3 = f.mul(&t3, &x3);
+        x3 = f.sub(&x3, &t1);
+        z3 = f.mul(&t4, &z3);
+        t1 = f.mul(&t3, &t0);
+        z3 = f.add(&z3, &t1);
+
+        ProjectivePoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    fn conditional_swap(a: &mut ProjectivePoint, b: &mut ProjectivePoint, swap: u64) {
+        let mask = 0u64.wrapping_sub(swap);
+        for (x, y) in [
+            (&mut a.x, &mut b.x),
+            (&mut a.y, &mut b.y),
+            (&mut a.z, &mut b.z),
+        ] {
+            let (new_x, new_y) = (select(x, y, mask), select(y, x, mask));
+            *x = new_x;
+            *y = new_y;
+        }
+    }
+
+    /*
+       Montgomery ladder over all 256 bits of the big endian scalar
+    */
+    fn multiply(&self, scalar: &[u8; 32]) -> ProjectivePoint {
+        let mut low = ProjectivePoint::identity();
+        let mut high = *self;
+        for i in (0..256).rev() {
+            let bit = ((scalar[31 - i / 8] >> (i % 8)) & 1) as u64;
+            ProjectivePoint::conditional_swap(&mut low, &mut high, bit);
+            high = low.add(&high);
+            low = low.add(&low);
+            ProjectivePoint::conditional_swap(&mut low, &mut high, bit);
+        }
+        low
+    }
+
+    /*
+       Affine x and y in Montgomery form, None for the point at infinity
+    */
+    fn to_affine(self) -> Option<(Limbs, Limbs)> {
+        if self.is_identity() {
+            return None;
+        }
+        let z_inverse = FIELD.invert(&self.z);
+        Some((
+            FIELD.mul(&self.x, &z_inverse),
+            FIELD.mul(&self.y, &z_inverse),
+        ))
+    }
+}
+
+/*
+   x^3 - 3x + b
+*/
+fn curve_right_side(x: &Limbs) -> Limbs {
+    let f = &FIELD;
+    let three_x = f.add(&f.add(x, x), x);
+    let x_cubed = f.mul(&f.square(x), x);
+    f.add(&f.sub(&x_cubed, &three_x), &f.decode(&B_BYTES).unwrap())
+}
+
+/*
+   A point on the curve other than the point at infinity, the only kind that can be encoded
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct P256Point {
+    x: Limbs,
+    y: Limbs,
+}
+
+impl P256Point {
+    pub fn generator() -> P256Point {
+        P256Point {
+            x: FIELD.decode(&GX_BYTES).unwrap(),
+            y: FIELD.decode(&GY_BYTES).unwrap(),
+        }
+    }
+
+    fn from_projective(point: ProjectivePoint) -> Option<P256Point> {
+        point.to_affine().map(|(x, y)| P256Point { x, y })
+    }
+
+    fn to_projective(self) -> ProjectivePoint {
+        ProjectivePoint::from_affine(self.x, self.y)
+    }
+
+    /*
+       SEC 1 section 2.3.4, uncompressed 04 | x | y or compressed 02 / 03 | x. The point has to be on the curve
+    */
+    pub fn from_sec1(bytes: &[u8]) -> Option<P256Point> {
+        let x = FIELD.decode(bytes.get(1..33)?.try_into().ok()?)?;
+        let right_side = curve_right_side(&x);
+
+        let y = match (bytes[0], bytes.len()) {
+            (0x04, P256_UNCOMPRESSED_POINT_SIZE_BYTES) => {
+                FIELD.decode(bytes[33..].try_into().unwrap())?
+            }
+            (0x02 | 0x03, P256_COMPRESSED_POINT_SIZE_BYTES) => {
+                // p = 3 mod 4 so a square root is a power away
+                let exponent = shift_right(&add_with_carry(&FIELD.value, &[1, 0, 0, 0]).0, 2);
+                let root = FIELD.pow(&right_side, &exponent);
+                let odd = FIELD.encode(&root)[31] & 1;
+                match odd == bytes[0] & 1 {
+                    true => root,
+                    false => FIELD.negate(&root),
+                }
+            }
+            _ => return None,
+        };
+
+        if FIELD.square(&y) != right_side {
+            return None;
+        }
+        Some(P256Point { x, y })
+    }
+
+    pub fn to_uncompressed(self) -> [u8; P256_UNCOMPRESSED_POINT_SIZE_BYTES] {
+        let mut bytes = [0u8; P256_UNCOMPRESSED_POINT_SIZE_BYTES];
+        bytes[0] = 0x04;
+        bytes[1..33].copy_from_slice(&FIELD.encode(&self.x));
+        bytes[33..].copy_from_slice(&FIELD.encode(&self.y));
+        bytes
+    }
+
+    pub fn to_compressed(self) -> [u8; P256_COMPRESSED_POINT_SIZE_BYTES] {
+        let mut bytes = [0u8; P256_COMPRESSED_POINT_SIZE_BYTES];
+        bytes[0] = 0x02 | (FIELD.encode(&self.y)[31] & 1);
+        bytes[1..].copy_from_slice(&FIELD.encode(&self.x));
+        bytes
+    }
+
+    /*
+       The affine x coordinate, big endian
+    */
+    pub fn x_bytes(&self) -> [u8; 32] {
+        FIELD.encode(&self.x)
+    }
+
+    /*
+       None if the result is the point at infinity, which only happens for multiples of n
+    */
+    pub fn multiply(&self, scalar: &[u8; 32]) -> Option<P256Point> {
+        P256Point::from_projective(self.to_projective().multiply(scalar))
+    }
+}
+
+fn shift_right(limbs: &Limbs, bits: u32) -> Limbs {
+    [0, 1, 2, 3].map(|i| {
+        let high = if i < 3 {
+            limbs[i + 1] << (64 - bits)
+        } else {
+            0
+        };
+        (limbs[i] >> bits) | high
+    })
+}
+
+/*
+   A private scalar in [1, n - 1] and the point it gives
+*/
+pub struct P256KeyPair {
+    secret: [u8; P256_SCALAR_SIZE_BYTES],
+    pub public: P256Point,
+}
+
+impl P256KeyPair {
+    /*
+       Rejection sampling, a 256 bit random value is out of range about once in 2^32 tries
+    */
+    pub fn generate() -> P256KeyPair {
+        loop {
+            let mut secret = [0u8; P256_SCALAR_SIZE_BYTES];
+            rand::rng().fill_bytes(&mut secret);
+            if let Some(x) = Self::from_secret(secret) {
+                return x;
+            }
+        }
+    }
+
+    pub fn from_secret(secret: [u8; P256_SCALAR_SIZE_BYTES]) -> Option<P256KeyPair> {
+        let scalar = ORDER.decode(&secret)?;
+        if ORDER.is_zero(&scalar) {
+            return None;
+        }
+        Some(P256KeyPair {
+            secret,
+            public: P256Point::generator().multiply(&secret)?,
+        })
+    }
+
+    pub fn secret(&self) -> &[u8; P256_SCALAR_SIZE_BYTES] {
+        &self.secret
+    }
+
+    /*
+       ECDH as in SEC 1 section 3.3.1, the shared secret is the x coordinate of our scalar times their point.
+       The peer point has already been checked to be on the curve when it was decoded
+    */
+    pub fn diffie_hellman(&self, peer_public: &P256Point) -> Option<[u8; 32]> {
+        peer_public.multiply(&self.secret).map(|x| x.x_bytes())
+    }
+
+    /*
+       ECDSA over SHA-256 of the message with the nonce from RFC 6979, r | s as 32 byte big endian values
+    */
+    pub fn sign(&self, message: &[u8]) -> [u8; P256_SIGNATURE_SIZE_BYTES] {
+        let hash = sha256(message);
+        let e = ORDER.decode_reduced(&hash);
+        let d = ORDER.decode(&self.secret).unwrap();
+        let mut nonces = Rfc6979::new(&self.secret, &limbs_to_bytes(&ORDER.montgomery_reduce(&e)));
+
+        loop {
+            let k_bytes = nonces.next_nonce();
+            let r = match P256Point::generator().multiply(&k_bytes) {
+                Some(x) => ORDER.decode_reduced(&x.x_bytes()),
+                None => continue,
+            };
+            let k = ORDER.decode(&k_bytes).unwrap();
+            let s = ORDER.mul(&ORDER.invert(&k), &ORDER.add(&e, &ORDER.mul(&r, &d)));
+            if ORDER.is_zero(&r) || ORDER.is_zero(&s) {
+                continue;
+            }
+
+            let mut signature = [0u8; P256_SIGNATURE_SIZE_BYTES];
+            signature[..32].copy_from_slice(&ORDER.encode(&r));
+            signature[32..].copy_from_slice(&ORDER.encode(&s));
+            return signature;
+        }
+    }
+}
+
+/*
+   HMAC_DRBG from RFC 6979 section 3.2 with SHA-256, where qlen and hlen are both 256 bits
+*/
+struct Rfc6979 {
+    k: [u8; SHA256_OUTPUT_SIZE_BYTES],
+    v: [u8; SHA256_OUTPUT_SIZE_BYTES],
+    started: bool,
+}
+
+impl Rfc6979 {
+    fn new(secret: &[u8; 32], hash: &[u8; 32]) -> Rfc6979 {
+        let mut state = Rfc6979 {
+            k: [0u8; SHA256_OUTPUT_SIZE_BYTES],
+            v: [1u8; SHA256_OUTPUT_SIZE_BYTES],
+            started: false,
+        };
+        for separator in [0u8, 1u8] {
+            state.k = hmac_sha256(
+                &state.k,
+                &[&state.v[..], &[separator], secret, hash].concat(),
+            );
+            state.v = hmac_sha256(&state.k, &state.v);
+        }
+        state
+    }
+
+    /*
+       Every call after the first one is the retry step from 3.2 h.3
+    */
+    fn next_nonce(&mut self) -> [u8; 32] {
+        loop {
+            if self.started {
+                self.k = hmac_sha256(&self.k, &[&self.v[..], &[0u8]].concat());
+                self.v = hmac_sha256(&self.k, &self.v);
+            }
+            self.started = true;
+
+            self.v = hmac_sha256(&self.k, &self.v);
+            if let Some(x) = ORDER.decode(&self.v) {
+                if !ORDER.is_zero(&x) {
+                    return self.v;
+                }
+            }
+        }
+    }
+}
+
+/*
+   ECDSA verification, SEC 1 section 4.1.4, against a raw r | s signature. Works on public values only
+*/
+pub fn ecdsa_verify(public: &P256Point, message: &[u8], signature: &[u8]) -> bool {
+    if signature.len() != P256_SIGNATURE_SIZE_BYTES {
+        return false;
+    }
+    let r = ORDER.decode(signature[..32].try_into().unwrap());
+    let s = ORDER.decode(signature[32..].try_into().unwrap());
+    let (r, s) = match (r, s) {
+        (Some(r), Some(s)) if !ORDER.is_zero(&r) && !ORDER.is_zero(&s) => (r, s),
+        _ => return false,
+    };
+
+    let e = ORDER.decode_reduced(&sha256(message));
+    let w = ORDER.invert(&s);
+    let u1 = ORDER.encode(&ORDER.mul(&e, &w));
+    let u2 = ORDER.encode(&ORDER.mul(&r, &w));
+
+    let sum = ProjectivePoint::base()
+        .multiply(&u1)
+        .add(&public.to_projective().multiply(&u2));
+    match P256Point::from_projective(sum) {
+        Some(x) => ORDER.decode_reduced(&x.x_bytes()) == r,
+        None => false,
+    }
+}
+
+/*
+   ECDSA-Sig-Value from RFC 3279, SEQUENCE { r INTEGER, s INTEGER }
+*/
+pub fn signature_to_der(signature: &[u8; P256_SIGNATURE_SIZE_BYTES]) -> Vec<u8> {
+    encode_sequence(&[
+        encode_integer(&BigUint::from_bytes_be(&signature[..32])),
+        encode_integer(&BigUint::from_bytes_be(&signature[32..])),
+    ])
+}
+
+/*
+   Strict DER only, anything with trailing bytes or values too big for 32 bytes is turned away
+*/
+pub fn signature_from_der(der: &[u8]) -> Option<[u8; P256_SIGNATURE_SIZE_BYTES]> {
+    let mut outer = DerReader::new(der);
+    let mut sequence = outer.read_sequence()?;
+    let r = sequence.read_integer()?.to_bytes_be_padded(32)?;
+    let s = sequence.read_integer()?.to_bytes_be_padded(32)?;
+    if !sequence.is_empty() || !outer.is_empty() {
+        return None;
+    }
+    [r, s].concat().try_into().ok()
+}
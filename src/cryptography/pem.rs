@@ -0,0 +1,78 @@
+/*
+   PEM armor, base64 of the DER between -----BEGIN label----- and -----END label----- lines
+*/
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const LINE_LENGTH: usize = 64;
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |acc, (index, x)| {
+            acc | (*x as u32) << (16 - index * 8)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(BASE64_ALPHABET[(group >> (18 - index * 6)) as usize & 0x3f] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/*
+   Whitespace is skipped, anything else that isn't base64 makes the whole thing invalid
+*/
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text: Vec<u8> = text.bytes().filter(|x| !x.is_ascii_whitespace()).collect();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut output = Vec::new();
+    for chunk in text.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|x| **x == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut group = 0u32;
+        for (index, byte) in chunk.iter().enumerate() {
+            let value = match BASE64_ALPHABET.iter().position(|x| x == byte) {
+                Some(x) => x as u32,
+                None if index >= 4 - padding => 0,
+                None => return None,
+            };
+            group |= value << (18 - index * 6);
+        }
+        output.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Some(output)
+}
+
+pub fn encode_pem(label: &str, der: &[u8]) -> String {
+    let body = base64_encode(der);
+    let mut output = format!("-----BEGIN {}-----\n", label);
+    for line in body.as_bytes().chunks(LINE_LENGTH) {
+        output.push_str(std::str::from_utf8(line).unwrap());
+        output.push('\n');
+    }
+    output.push_str(&format!("-----END {}-----\n", label));
+    output
+}
+
+/*
+   The first PEM block in the text as its label and DER contents
+*/
+pub fn decode_pem(text: &str) -> Option<(String, Vec<u8>)> {
+    let start = text.find("-----BEGIN ")?;
+    let rest = &text[start + "-----BEGIN ".len()..];
+    let label_end = rest.find("-----")?;
+    let label = &rest[..label_end];
+    let body = &rest[label_end + "-----".len()..];
+
+    let end_marker = format!("-----END {}-----", label);
+    let body = &body[..body.find(&end_marker)?];
+    Some((label.to_string(), base64_decode(body)?))
+}
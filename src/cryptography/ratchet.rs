@@ -0,0 +1,273 @@
+use crate::cryptography::cryptography::Encryption;
+use crate::cryptography::sha256::{hkdf, SHA256_OUTPUT_SIZE_BYTES};
+use std::collections::HashMap;
+use std::sync::atomic::{compiler_fence, Ordering};
+use tracing::warn;
+
+/*
+   Symmetric KDF chain ratchet, the same idea as the chains in Signal's double ratchet minus the Diffie-Hellman part.
+
+   Both ends start from the shared key and derive one chain per direction from it. Every frame steps the sending
+   chain once, HKDF-SHA256(salt = counter as 8 big endian bytes, chain key, "kryptos ratchet step") gives 64 bytes,
+   the first 32 are the next chain key and the last 32 the message key. The wrapped cipher is keyed with the message
+   key for that one frame and then both are wiped, so getting hold of the current state says nothing about frames
+   that were already sent.
+
+   Frame layout is counter (8 bytes, big endian) | ciphertext from the wrapped cipher
+
+   The counter lets the receiver notice frames that arrive out of order. Keys for frames that were skipped over
+   are kept, up to MAX_SKIPPED_MESSAGE_KEYS, so a late frame can still be read once. A frame whose key was already
+   used or thrown away is rejected.
+*/
+pub const COUNTER_SIZE_BYTES: usize = 8;
+pub const MESSAGE_KEY_SIZE_BYTES: usize = 32;
+pub const MAX_SKIPPED_MESSAGE_KEYS: usize = 256;
+const CHAIN_KEY_SIZE_BYTES: usize = SHA256_OUTPUT_SIZE_BYTES;
+const CLIENT_CHAIN_INFO: &[u8] = b"kryptos ratchet client to server";
+const SERVER_CHAIN_INFO: &[u8] = b"kryptos ratchet server to client";
+const STEP_INFO: &[u8] = b"kryptos ratchet step";
+
+/*
+   Which end of the connection we are, it picks which of the two chains we send on
+*/
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RatchetRole {
+    Client,
+    Server,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RatchetError {
+    Truncated,
+    /*
+       The frame's key was already used or dropped, either a replay or a frame that showed up too late
+    */
+    Replayed(u64),
+    TooFarAhead(u64),
+}
+
+impl RatchetError {
+    pub fn message(&self) -> String {
+        match self {
+            RatchetError::Truncated => "Frame is too short to hold a counter".to_string(),
+            RatchetError::Replayed(x) => format!("Frame {} was already received", x),
+            RatchetError::TooFarAhead(x) => format!("Frame {} skips too many frames", x),
+        }
+    }
+}
+
+struct Chain {
+    key: Vec<u8>,
+    counter: u64,
+}
+
+impl Chain {
+    fn new(shared_key: &[u8], info: &[u8]) -> Chain {
+        Chain {
+            key: hkdf(&[], shared_key, info, CHAIN_KEY_SIZE_BYTES),
+            counter: 0,
+        }
+    }
+
+    /*
+       Moves the chain on one step and returns the message key for the frame it just passed
+    */
+    fn step(&mut self) -> Vec<u8> {
+        let mut output = hkdf(
+            &self.counter.to_be_bytes(),
+            &self.key,
+            STEP_INFO,
+            CHAIN_KEY_SIZE_BYTES + MESSAGE_KEY_SIZE_BYTES,
+        );
+        let message_key = output.split_off(CHAIN_KEY_SIZE_BYTES);
+        wipe(&mut self.key);
+        self.key = output;
+        self.counter += 1;
+        message_key
+    }
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        wipe(&mut self.key);
+    }
+}
+
+pub struct RatchetContext {
+    inner: Box<dyn Encryption>,
+    role: RatchetRole,
+    sending: Chain,
+    receiving: Chain,
+    skipped: HashMap<u64, Vec<u8>>,
+    last_error: Option<RatchetError>,
+}
+
+impl RatchetContext {
+    /*
+       Wraps any of the ciphers, its own key is ignored from here on and replaced by a fresh one per frame
+    */
+    pub fn new<T: Encryption + 'static>(inner: T, role: RatchetRole, key: &[u8]) -> RatchetContext {
+        Self::from_boxed(Box::new(inner), role, key)
+    }
+
+    pub fn from_boxed(inner: Box<dyn Encryption>, role: RatchetRole, key: &[u8]) -> RatchetContext {
+        let (sending, receiving) = Self::chains(role, key);
+        RatchetContext {
+            inner,
+            role,
+            sending,
+            receiving,
+            skipped: HashMap::new(),
+            last_error: None,
+        }
+    }
+
+    fn chains(role: RatchetRole, key: &[u8]) -> (Chain, Chain) {
+        let client = Chain::new(key, CLIENT_CHAIN_INFO);
+        let server = Chain::new(key, SERVER_CHAIN_INFO);
+        match role {
+            RatchetRole::Client => (client, server),
+            RatchetRole::Server => (server, client),
+        }
+    }
+
+    /*
+       Why the last decrypt came back empty, if it did
+    */
+    pub fn last_error(&self) -> Option<&RatchetError> {
+        self.last_error.as_ref()
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let counter = self.sending.counter;
+        let mut message_key = self.sending.step();
+
+        let mut input = plaintext.to_vec();
+        let mut ciphertext = vec![0u8; input.len()];
+        self.with_message_key(&mut message_key, |inner| {
+            inner.encrypt(&mut input, &mut ciphertext)
+        });
+
+        let mut frame = counter.to_be_bytes().to_vec();
+        frame.extend_from_slice(&ciphertext);
+        frame
+    }
+
+    pub fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>, RatchetError> {
+        if frame.len() < COUNTER_SIZE_BYTES {
+            return Err(RatchetError::Truncated);
+        }
+        let (counter, ciphertext) = frame.split_at(COUNTER_SIZE_BYTES);
+        let counter = u64::from_be_bytes(counter.try_into().unwrap());
+
+        let mut message_key = self.message_key_for(counter)?;
+        if ciphertext.is_empty() {
+            wipe(&mut message_key);
+            return Ok(Vec::new());
+        }
+
+        let mut input = ciphertext.to_vec();
+        let mut plaintext = vec![0u8; input.len()];
+        self.with_message_key(&mut message_key, |inner| {
+            inner.decrypt(&mut input, &mut plaintext)
+        });
+        Ok(plaintext)
+    }
+
+    fn message_key_for(&mut self, counter: u64) -> Result<Vec<u8>, RatchetError> {
+        if counter < self.receiving.counter {
+            return self
+                .skipped
+                .remove(&counter)
+                .ok_or(RatchetError::Replayed(counter));
+        }
+
+        let skipping = counter - self.receiving.counter;
+        if skipping > (MAX_SKIPPED_MESSAGE_KEYS - self.skipped.len()) as u64 {
+            return Err(RatchetError::TooFarAhead(counter));
+        }
+
+        while self.receiving.counter < counter {
+            let skipped_counter = self.receiving.counter;
+            let message_key = self.receiving.step();
+            self.skipped.insert(skipped_counter, message_key);
+        }
+        Ok(self.receiving.step())
+    }
+
+    /*
+       Keys the wrapped cipher for exactly one operation then wipes the key from both places
+    */
+    fn with_message_key<F: FnOnce(&mut Box<dyn Encryption>)>(
+        &mut self,
+        message_key: &mut [u8],
+        operation: F,
+    ) {
+        self.inner.set_key(message_key);
+        operation(&mut self.inner);
+        wipe(message_key);
+        self.inner.set_key(&[0u8; MESSAGE_KEY_SIZE_BYTES]);
+    }
+}
+
+impl Drop for RatchetContext {
+    fn drop(&mut self) {
+        self.skipped.values_mut().for_each(|x| wipe(x));
+    }
+}
+
+impl Encryption for RatchetContext {
+    fn initialize_context(&mut self) {
+        self.inner.initialize_context();
+    }
+
+    fn encrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        *output = self.seal(input);
+    }
+
+    /*
+       Frames that can't be read leave the output empty, last_error says why
+    */
+    fn decrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        output.clear();
+        self.last_error = match self.open(input) {
+            Ok(x) => {
+                *output = x;
+                None
+            }
+            Err(e) => {
+                warn!(reason = %e.message(), "dropping ratchet frame");
+                Some(e)
+            }
+        };
+    }
+
+    /*
+       Starts both chains over from a new shared key
+    */
+    fn set_key(&mut self, key: &[u8]) {
+        let (sending, receiving) = Self::chains(self.role, key);
+        self.sending = sending;
+        self.receiving = receiving;
+        self.skipped.values_mut().for_each(|x| wipe(x));
+        self.skipped.clear();
+    }
+
+    fn get_key(&self) -> &[u8] {
+        &self.sending.key
+    }
+
+    fn rejected_last_frame(&self) -> bool {
+        self.last_error.is_some()
+    }
+}
+
+/*
+   Overwrites key material in a way the optimizer isn't allowed to skip just because the buffer is about to be freed
+*/
+pub fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
@@ -1,11 +1,20 @@
 use crate::cryptography::cryptography::Encryption;
 use rand::RngCore;
+use tracing::warn;
 
 pub const KEY_SIZE_BYTES: usize = 32;
-
+/*
+   The permutation is over every byte value, that is what keeps the keystream covering all 8 bits
+*/
+const STATE_SIZE: usize = 256;
+
+/*
+   One keystream per key. The key is scheduled once when it is set and i, j and S carry on from frame to frame, so
+   both ends have to see the same frames in the same order, which a TCP stream and one context per direction give us
+*/
 #[derive(Debug)]
 pub struct Rc4State {
-    s: [u8; KEY_SIZE_BYTES],
+    s: [u8; STATE_SIZE],
     i: usize,
     j: usize,
     key: Rc4Key,
@@ -26,17 +35,19 @@ impl Rc4State {
     /// Creates a new Rc4State object with a randomly generated key and default values for the s array, i, j
     pub fn new(key: Option<&[u8]>) -> Self {
         let mut new = Self {
-            s: [0; KEY_SIZE_BYTES],
+            s: [0; STATE_SIZE],
             i: 0,
             j: 0,
             key: Rc4Key::new([0; KEY_SIZE_BYTES]), // Initialize with a default key
         };
 
-        if (key.is_some()) {
-            let key = key.unwrap();
+        /*
+           Random key first so that a passed key which is the wrong size still doesn't leave us with all zeros
+        */
+        new.initialize();
+        if let Some(key) = key {
             new.set_key(key);
         }
-        new.initialize();
         new
     }
 
@@ -50,6 +61,7 @@ impl Rc4State {
         let mut key = [0u8; KEY_SIZE_BYTES];
         rand::rng().fill_bytes(&mut key); // Fixed to use a random generator
         self.key = Rc4Key::new(key);
+        self.key_scheduling();
     }
 
     /// key_scheduling sets up the S array (initial key stream) with initial values getting ready to begin the encryption process.
@@ -57,14 +69,14 @@ impl Rc4State {
         let key = &self.key.key;
 
         // Initialize the s array to the range [0..255]
-        for i in 0..KEY_SIZE_BYTES {
+        for i in 0..STATE_SIZE {
             self.s[i] = i as u8;
         }
 
         let mut j = 0;
 
-        for i in 0..KEY_SIZE_BYTES {
-            j = (j + self.s[i] as usize + key[i] as usize) % KEY_SIZE_BYTES;
+        for i in 0..STATE_SIZE {
+            j = (j + self.s[i] as usize + key[i % KEY_SIZE_BYTES] as usize) % STATE_SIZE;
             self.s.swap(i, j);
         }
 
@@ -72,14 +84,13 @@ impl Rc4State {
         self.j = 0;
     }
 
-    /// prga (pseudo-random generator algorithm) sets up the final keystream buffer with pseudo random bytes derived from the initial keystream generated in key_scheduling
+    /// prga (pseudo-random generator algorithm) fills the buffer with the next bytes of the keystream, picking up where the last call left off
     fn prga(&mut self, output_buffer: &mut [u8]) {
-        self.key_scheduling();
         for byte in output_buffer {
-            self.i = (self.i + 1) % KEY_SIZE_BYTES;
-            self.j = (self.j + self.s[self.i] as usize) % KEY_SIZE_BYTES;
+            self.i = (self.i + 1) % STATE_SIZE;
+            self.j = (self.j + self.s[self.i] as usize) % STATE_SIZE;
             self.s.swap(self.i, self.j);
-            let k = self.s[(self.s[self.i] as usize + self.s[self.j] as usize) % KEY_SIZE_BYTES];
+            let k = self.s[(self.s[self.i] as usize + self.s[self.j] as usize) % STATE_SIZE];
             *byte = k;
         }
     }
@@ -92,11 +103,7 @@ impl Encryption for Rc4State {
 
     fn encrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
         let mut keystream = vec![0u8; input.len()];
-
-        if output.len() < input.len() {
-            println!("RC4 encrypt: output buffer too short");
-            return;
-        }
+        output.resize(input.len(), 0);
 
         self.prga(&mut keystream);
 
@@ -111,7 +118,7 @@ impl Encryption for Rc4State {
 
     fn set_key(&mut self, key: &[u8]) {
         if key.len() != KEY_SIZE_BYTES {
-            println!("Key size is not correct! Expected {KEY_SIZE_BYTES}");
+            warn!("Key size is not correct! Expected {KEY_SIZE_BYTES}");
             return;
         }
 
@@ -119,6 +126,7 @@ impl Encryption for Rc4State {
             Ok(x) => x,
             Err(_) => self.key.key,
         };
+        self.key_scheduling();
     }
 
     fn get_key(&self) -> &[u8] {
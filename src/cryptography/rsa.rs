@@ -0,0 +1,566 @@
+use crate::cryptography::bignum::BigUint;
+use crate::cryptography::der::{
+    encode, encode_bit_string, encode_integer, encode_oid, encode_sequence, DerReader, TAG_NULL,
+    TAG_OCTET_STRING, TAG_OID,
+};
+use crate::cryptography::pem::{decode_pem, encode_pem};
+use crate::cryptography::sha256::{constant_time_eq, sha256, SHA256_OUTPUT_SIZE_BYTES};
+use rand::RngCore;
+
+/*
+   RSA as in RFC 8017, OAEP for encryption and PSS for signatures, both with SHA-256 and MGF1-SHA256.
+   PSS salts are as long as the hash.
+
+   Keys read and write as PKCS#1 (RSA PUBLIC KEY / RSA PRIVATE KEY), PKCS#8 (PRIVATE KEY) and, for public keys,
+   the X.509 SubjectPublicKeyInfo (PUBLIC KEY) everything else expects.
+
+   Like the bignum underneath it none of this is constant time
+*/
+pub const PUBLIC_EXPONENT: u64 = 65537;
+pub const MIN_KEY_BITS: usize = 1024;
+const HASH_LENGTH: usize = SHA256_OUTPUT_SIZE_BYTES;
+const SALT_LENGTH: usize = SHA256_OUTPUT_SIZE_BYTES;
+const SMALL_PRIME_LIMIT: u64 = 2048;
+/*
+   How far we walk up from a random starting point looking for a prime before picking a new one
+*/
+const MAX_PRIME_SEARCH: u64 = 1 << 16;
+const RSA_ENCRYPTION_OID: [u64; 7] = [1, 2, 840, 113549, 1, 1, 1];
+
+pub const PKCS1_PUBLIC_LABEL: &str = "RSA PUBLIC KEY";
+pub const PKCS1_PRIVATE_LABEL: &str = "RSA PRIVATE KEY";
+pub const PKCS8_PRIVATE_LABEL: &str = "PRIVATE KEY";
+pub const SPKI_PUBLIC_LABEL: &str = "PUBLIC KEY";
+
+#[derive(Debug, PartialEq)]
+pub enum RsaError {
+    KeyTooSmall,
+    MessageTooLong,
+    /*
+       Deliberately says nothing about which check failed, anything more is an oracle for the padding
+    */
+    DecryptionFailed,
+    InvalidKey,
+}
+
+impl RsaError {
+    pub fn message(&self) -> String {
+        match self {
+            RsaError::KeyTooSmall => format!("RSA keys must be at least {} bits", MIN_KEY_BITS),
+            RsaError::MessageTooLong => "Message is too long for this key".to_string(),
+            RsaError::DecryptionFailed => "Decryption failed".to_string(),
+            RsaError::InvalidKey => "Not a valid RSA key".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RsaPublicKey {
+    pub n: BigUint,
+    pub e: BigUint,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RsaPrivateKey {
+    pub n: BigUint,
+    pub e: BigUint,
+    pub d: BigUint,
+    pub p: BigUint,
+    pub q: BigUint,
+    dp: BigUint,
+    dq: BigUint,
+    qinv: BigUint,
+}
+
+fn small_primes() -> Vec<u64> {
+    (3..SMALL_PRIME_LIMIT)
+        .step_by(2)
+        .filter(|x| {
+            (3..)
+                .step_by(2)
+                .take_while(|y| y * y <= *x)
+                .all(|y| x % y != 0)
+        })
+        .collect()
+}
+
+/*
+   Rounds from FIPS 186-4 table C.3 for a 2^-100 chance of letting a composite through
+*/
+fn miller_rabin_rounds(bits: usize) -> usize {
+    match bits {
+        1536.. => 4,
+        1024.. => 5,
+        512.. => 7,
+        _ => 40,
+    }
+}
+
+pub fn is_probable_prime(candidate: &BigUint, rounds: usize) -> bool {
+    let two = BigUint::from_u64(2);
+    if *candidate < two {
+        return false;
+    }
+    for prime in [2u64].into_iter().chain(small_primes()) {
+        if candidate.rem_u64(prime) == 0 {
+            return *candidate == BigUint::from_u64(prime);
+        }
+    }
+
+    let minus_one = candidate.sub(&BigUint::one());
+    let shift = (0..minus_one.bits())
+        .take_while(|x| !minus_one.bit(*x))
+        .count();
+    let odd_part = minus_one.shr(shift);
+
+    'witness: for _ in 0..rounds {
+        let base = BigUint::random_range(&two, &minus_one);
+        let mut x = base.mod_pow(&odd_part, candidate);
+        if x.is_one() || x == minus_one {
+            continue;
+        }
+        for _ in 1..shift {
+            x = x.mul(&x).rem(candidate);
+            if x == minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/*
+   Random prime of exactly the given size with the top two bits set, so two of them multiply out to the full size.
+   Candidates that a small prime divides are skipped without running Miller-Rabin
+*/
+pub fn generate_prime(bits: usize) -> BigUint {
+    let primes = small_primes();
+    loop {
+        let mut start = BigUint::random_bits(bits - 2);
+        start = start
+            .add(&BigUint::from_u64(3).shl(bits - 2))
+            .shr(1)
+            .shl(1)
+            .add(&BigUint::one());
+        let residues: Vec<u64> = primes.iter().map(|x| start.rem_u64(*x)).collect();
+
+        for offset in (0..MAX_PRIME_SEARCH).step_by(2) {
+            if primes
+                .iter()
+                .zip(&residues)
+                .any(|(prime, residue)| (residue + offset) % prime == 0)
+            {
+                continue;
+            }
+            let candidate = start.add(&BigUint::from_u64(offset));
+            if candidate.bits() != bits {
+                break;
+            }
+            if is_probable_prime(&candidate, miller_rabin_rounds(bits)) {
+                return candidate;
+            }
+        }
+    }
+}
+
+/*
+   MGF1 from RFC 8017 appendix B.2.1 with SHA-256
+*/
+fn mgf1(seed: &[u8], length: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(length + HASH_LENGTH);
+    let mut counter = 0u32;
+    while output.len() < length {
+        let mut input = seed.to_vec();
+        input.extend_from_slice(&counter.to_be_bytes());
+        output.extend_from_slice(&sha256(&input));
+        counter += 1;
+    }
+    output.truncate(length);
+    output
+}
+
+/*
+   XORs MGF1(seed) over the whole of target
+*/
+fn apply_mask(target: &mut [u8], seed: &[u8]) {
+    let mask = mgf1(seed, target.len());
+    target.iter_mut().zip(mask).for_each(|(x, y)| *x ^= y);
+}
+
+fn algorithm_identifier() -> Vec<u8> {
+    encode_sequence(&[encode_oid(&RSA_ENCRYPTION_OID), encode(TAG_NULL, &[])])
+}
+
+impl RsaPublicKey {
+    pub fn size_bytes(&self) -> usize {
+        self.n.bits().div_ceil(8)
+    }
+
+    pub fn max_oaep_message_length(&self) -> usize {
+        self.size_bytes().saturating_sub(2 * HASH_LENGTH + 2)
+    }
+
+    /*
+       RSAES-OAEP-ENCRYPT, RFC 8017 section 7.1.1
+    */
+    pub fn encrypt_oaep(&self, message: &[u8], label: &[u8]) -> Result<Vec<u8>, RsaError> {
+        let k = self.size_bytes();
+        if message.len() > self.max_oaep_message_length() {
+            return Err(RsaError::MessageTooLong);
+        }
+
+        let mut data_block = sha256(label).to_vec();
+        data_block.resize(k - message.len() - HASH_LENGTH - 2, 0);
+        data_block.push(1);
+        data_block.extend_from_slice(message);
+
+        let mut seed = [0u8; HASH_LENGTH];
+        rand::rng().fill_bytes(&mut seed);
+        apply_mask(&mut data_block, &seed);
+        apply_mask(&mut seed, &data_block);
+
+        let mut encoded = vec![0u8];
+        encoded.extend_from_slice(&seed);
+        encoded.extend_from_slice(&data_block);
+
+        let ciphertext = BigUint::from_bytes_be(&encoded).mod_pow(&self.e, &self.n);
+        Ok(ciphertext.to_bytes_be_padded(k).unwrap())
+    }
+
+    /*
+       RSASSA-PSS-VERIFY, RFC 8017 section 8.1.2
+    */
+    pub fn verify_pss(&self, message: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != self.size_bytes() {
+            return false;
+        }
+        let signature = BigUint::from_bytes_be(signature);
+        if signature >= self.n {
+            return false;
+        }
+
+        let em_bits = self.n.bits() - 1;
+        let em_length = em_bits.div_ceil(8);
+        let encoded = match signature
+            .mod_pow(&self.e, &self.n)
+            .to_bytes_be_padded(em_length)
+        {
+            Some(x) => x,
+            None => return false,
+        };
+        if em_length < HASH_LENGTH + SALT_LENGTH + 2 || encoded[em_length - 1] != 0xbc {
+            return false;
+        }
+
+        let (masked_block, rest) = encoded.split_at(em_length - HASH_LENGTH - 1);
+        let hash = &rest[..HASH_LENGTH];
+        let top_mask = 0xffu8 >> (8 * em_length - em_bits);
+        if masked_block[0] & !top_mask != 0 {
+            return false;
+        }
+
+        let mut data_block = masked_block.to_vec();
+        apply_mask(&mut data_block, hash);
+        data_block[0] &= top_mask;
+
+        let padding_length = em_length - HASH_LENGTH - SALT_LENGTH - 2;
+        if data_block[..padding_length].iter().any(|x| *x != 0) || data_block[padding_length] != 1 {
+            return false;
+        }
+
+        let salt = &data_block[padding_length + 1..];
+        constant_time_eq(&pss_hash(message, salt), hash)
+    }
+
+    pub fn to_pkcs1_der(&self) -> Vec<u8> {
+        encode_sequence(&[encode_integer(&self.n), encode_integer(&self.e)])
+    }
+
+    pub fn to_spki_der(&self) -> Vec<u8> {
+        encode_sequence(&[
+            algorithm_identifier(),
+            encode_bit_string(&self.to_pkcs1_der()),
+        ])
+    }
+
+    pub fn to_pkcs1_pem(&self) -> String {
+        encode_pem(PKCS1_PUBLIC_LABEL, &self.to_pkcs1_der())
+    }
+
+    pub fn to_spki_pem(&self) -> String {
+        encode_pem(SPKI_PUBLIC_LABEL, &self.to_spki_der())
+    }
+
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<RsaPublicKey, RsaError> {
+        let mut outer = DerReader::new(der);
+        let mut sequence = outer.read_sequence().ok_or(RsaError::InvalidKey)?;
+        let n = sequence.read_integer().ok_or(RsaError::InvalidKey)?;
+        let e = sequence.read_integer().ok_or(RsaError::InvalidKey)?;
+        if !sequence.is_empty() || !outer.is_empty() || n.is_even() || e.is_even() {
+            return Err(RsaError::InvalidKey);
+        }
+        Ok(RsaPublicKey { n, e })
+    }
+
+    pub fn from_spki_der(der: &[u8]) -> Result<RsaPublicKey, RsaError> {
+        let mut sequence = DerReader::new(der)
+            .read_sequence()
+            .ok_or(RsaError::InvalidKey)?;
+        check_algorithm(&mut sequence)?;
+        let key = sequence.read_bit_string().ok_or(RsaError::InvalidKey)?;
+        RsaPublicKey::from_pkcs1_der(key)
+    }
+
+    /*
+       Either PEM public key format
+    */
+    pub fn from_pem(text: &str) -> Result<RsaPublicKey, RsaError> {
+        match decode_pem(text).ok_or(RsaError::InvalidKey)? {
+            (label, der) if label == PKCS1_PUBLIC_LABEL => RsaPublicKey::from_pkcs1_der(&der),
+            (label, der) if label == SPKI_PUBLIC_LABEL => RsaPublicKey::from_spki_der(&der),
+            _ => Err(RsaError::InvalidKey),
+        }
+    }
+}
+
+fn check_algorithm(sequence: &mut DerReader) -> Result<(), RsaError> {
+    let mut algorithm = sequence.read_sequence().ok_or(RsaError::InvalidKey)?;
+    let oid = algorithm.read(TAG_OID).ok_or(RsaError::InvalidKey)?;
+    if encode_oid(&RSA_ENCRYPTION_OID)[2..] != *oid {
+        return Err(RsaError::InvalidKey);
+    }
+    Ok(())
+}
+
+/*
+   H = SHA-256(8 zero bytes | SHA-256(message) | salt)
+*/
+fn pss_hash(message: &[u8], salt: &[u8]) -> [u8; HASH_LENGTH] {
+    let mut input = vec![0u8; 8];
+    input.extend_from_slice(&sha256(message));
+    input.extend_from_slice(salt);
+    sha256(&input)
+}
+
+impl RsaPrivateKey {
+    /*
+       New key with e = 65537 and a modulus of exactly the given size, 2048 or 4096 in practice
+    */
+    pub fn generate(bits: usize) -> Result<RsaPrivateKey, RsaError> {
+        if bits < MIN_KEY_BITS {
+            return Err(RsaError::KeyTooSmall);
+        }
+        let e = BigUint::from_u64(PUBLIC_EXPONENT);
+
+        loop {
+            let p = generate_prime(bits - bits / 2);
+            let q = generate_prime(bits / 2);
+            if p == q {
+                continue;
+            }
+            match RsaPrivateKey::from_primes(p, q, e.clone()) {
+                Ok(x) if x.n.bits() == bits => return Ok(x),
+                _ => continue,
+            }
+        }
+    }
+
+    /*
+       Works out the rest of the key from the two primes, d is taken mod lcm(p - 1, q - 1)
+    */
+    pub fn from_primes(p: BigUint, q: BigUint, e: BigUint) -> Result<RsaPrivateKey, RsaError> {
+        let one = BigUint::one();
+        let p_minus_one = p.sub(&one);
+        let q_minus_one = q.sub(&one);
+        let gcd = p_minus_one.gcd(&q_minus_one);
+        let lambda = p_minus_one.mul(&q_minus_one).div_rem(&gcd).0;
+
+        let d = e.mod_inverse(&lambda).ok_or(RsaError::InvalidKey)?;
+        RsaPrivateKey::from_components(p.mul(&q), e, d, p, q)
+    }
+
+    pub fn from_components(
+        n: BigUint,
+        e: BigUint,
+        d: BigUint,
+        p: BigUint,
+        q: BigUint,
+    ) -> Result<RsaPrivateKey, RsaError> {
+        let one = BigUint::one();
+        if p.mul(&q) != n || p.is_one() || q.is_one() {
+            return Err(RsaError::InvalidKey);
+        }
+        Ok(RsaPrivateKey {
+            dp: d.rem(&p.sub(&one)),
+            dq: d.rem(&q.sub(&one)),
+            qinv: q.mod_inverse(&p).ok_or(RsaError::InvalidKey)?,
+            n,
+            e,
+            d,
+            p,
+            q,
+        })
+    }
+
+    pub fn public_key(&self) -> RsaPublicKey {
+        RsaPublicKey {
+            n: self.n.clone(),
+            e: self.e.clone(),
+        }
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.n.bits().div_ceil(8)
+    }
+
+    /*
+       m = c^d mod n the fast way, through the Chinese remainder theorem
+    */
+    fn raw_private(&self, input: &BigUint) -> BigUint {
+        let m1 = input.mod_pow(&self.dp, &self.p);
+        let m2 = input.mod_pow(&self.dq, &self.q);
+        let difference = m1.add(&self.p).sub(&m2.rem(&self.p));
+        let h = self.qinv.mul(&difference).rem(&self.p);
+        m2.add(&h.mul(&self.q))
+    }
+
+    /*
+       RSAES-OAEP-DECRYPT, RFC 8017 section 7.1.2. Every check runs before any of them is looked at
+    */
+    pub fn decrypt_oaep(&self, ciphertext: &[u8], label: &[u8]) -> Result<Vec<u8>, RsaError> {
+        let k = self.size_bytes();
+        if ciphertext.len() != k || k < 2 * HASH_LENGTH + 2 {
+            return Err(RsaError::DecryptionFailed);
+        }
+        let ciphertext = BigUint::from_bytes_be(ciphertext);
+        if ciphertext >= self.n {
+            return Err(RsaError::DecryptionFailed);
+        }
+
+        let encoded = self.raw_private(&ciphertext).to_bytes_be_padded(k).unwrap();
+        let mut seed = encoded[1..1 + HASH_LENGTH].to_vec();
+        let mut data_block = encoded[1 + HASH_LENGTH..].to_vec();
+        apply_mask(&mut seed, &data_block);
+        apply_mask(&mut data_block, &seed);
+
+        let label_matches = constant_time_eq(&data_block[..HASH_LENGTH], &sha256(label));
+        let mut separator = 0;
+        let mut padding_valid = true;
+        for (index, byte) in data_block.iter().enumerate().skip(HASH_LENGTH) {
+            let searching = separator == 0;
+            if searching && *byte == 1 {
+                separator = index;
+            }
+            padding_valid &= !searching || *byte == 0 || *byte == 1;
+        }
+
+        if !(label_matches && padding_valid && separator != 0 && encoded[0] == 0) {
+            return Err(RsaError::DecryptionFailed);
+        }
+        Ok(data_block[separator + 1..].to_vec())
+    }
+
+    /*
+       RSASSA-PSS-SIGN, RFC 8017 section 8.1.1
+    */
+    pub fn sign_pss(&self, message: &[u8]) -> Vec<u8> {
+        let em_bits = self.n.bits() - 1;
+        let em_length = em_bits.div_ceil(8);
+
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::rng().fill_bytes(&mut salt);
+        let hash = pss_hash(message, &salt);
+
+        let mut data_block = vec![0u8; em_length - SALT_LENGTH - HASH_LENGTH - 2];
+        data_block.push(1);
+        data_block.extend_from_slice(&salt);
+        apply_mask(&mut data_block, &hash);
+        data_block[0] &= 0xffu8 >> (8 * em_length - em_bits);
+
+        let mut encoded = data_block;
+        encoded.extend_from_slice(&hash);
+        encoded.push(0xbc);
+
+        self.raw_private(&BigUint::from_bytes_be(&encoded))
+            .to_bytes_be_padded(self.size_bytes())
+            .unwrap()
+    }
+
+    pub fn to_pkcs1_der(&self) -> Vec<u8> {
+        encode_sequence(&[
+            encode_integer(&BigUint::zero()),
+            encode_integer(&self.n),
+            encode_integer(&self.e),
+            encode_integer(&self.d),
+            encode_integer(&self.p),
+            encode_integer(&self.q),
+            encode_integer(&self.dp),
+            encode_integer(&self.dq),
+            encode_integer(&self.qinv),
+        ])
+    }
+
+    pub fn to_pkcs8_der(&self) -> Vec<u8> {
+        encode_sequence(&[
+            encode_integer(&BigUint::zero()),
+            algorithm_identifier(),
+            encode(TAG_OCTET_STRING, &self.to_pkcs1_der()),
+        ])
+    }
+
+    pub fn to_pkcs1_pem(&self) -> String {
+        encode_pem(PKCS1_PRIVATE_LABEL, &self.to_pkcs1_der())
+    }
+
+    pub fn to_pkcs8_pem(&self) -> String {
+        encode_pem(PKCS8_PRIVATE_LABEL, &self.to_pkcs8_der())
+    }
+
+    /*
+       Only the two prime form, the CRT values in the file are recomputed rather than trusted
+    */
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<RsaPrivateKey, RsaError> {
+        let mut outer = DerReader::new(der);
+        let mut sequence = outer.read_sequence().ok_or(RsaError::InvalidKey)?;
+        let mut values = Vec::new();
+        for _ in 0..9 {
+            values.push(sequence.read_integer().ok_or(RsaError::InvalidKey)?);
+        }
+        if !values[0].is_zero() || !sequence.is_empty() || !outer.is_empty() {
+            return Err(RsaError::InvalidKey);
+        }
+        let mut values = values.into_iter().skip(1);
+        let mut next = || values.next().unwrap();
+        RsaPrivateKey::from_components(next(), next(), next(), next(), next())
+    }
+
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<RsaPrivateKey, RsaError> {
+        let mut sequence = DerReader::new(der)
+            .read_sequence()
+            .ok_or(RsaError::InvalidKey)?;
+        if !sequence
+            .read_integer()
+            .ok_or(RsaError::InvalidKey)?
+            .is_zero()
+        {
+            return Err(RsaError::InvalidKey);
+        }
+        check_algorithm(&mut sequence)?;
+        let key = sequence
+            .read(TAG_OCTET_STRING)
+            .ok_or(RsaError::InvalidKey)?;
+        RsaPrivateKey::from_pkcs1_der(key)
+    }
+
+    /*
+       Either PEM private key format
+    */
+    pub fn from_pem(text: &str) -> Result<RsaPrivateKey, RsaError> {
+        match decode_pem(text).ok_or(RsaError::InvalidKey)? {
+            (label, der) if label == PKCS1_PRIVATE_LABEL => RsaPrivateKey::from_pkcs1_der(&der),
+            (label, der) if label == PKCS8_PRIVATE_LABEL => RsaPrivateKey::from_pkcs8_der(&der),
+            _ => Err(RsaError::InvalidKey),
+        }
+    }
+}
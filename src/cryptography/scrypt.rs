@@ -0,0 +1,189 @@
+use crate::cryptography::sha256::pbkdf2_hmac_sha256;
+
+/*
+   scrypt as per RFC 7914, a memory hard KDF for hashing passwords
+   https://www.rfc-editor.org/rfc/rfc7914
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScryptParams {
+    pub log_n: u8, // N = 2^log_n, the cost parameter
+    pub r: u32,    // block size
+    pub p: u32,    // parallelisation
+}
+
+/*
+   N = 2^15, r = 8 works out to 32 MiB per hash which is what the RFC suggests for interactive logins
+*/
+pub const DEFAULT_SCRYPT_PARAMS: ScryptParams = ScryptParams {
+    log_n: 15,
+    r: 8,
+    p: 1,
+};
+
+/*
+   Parameters come out of files we don't fully trust (accounts, transcript headers), so they are bounded before
+   anything is allocated. 2^24 is far past anything sensible for a login and 1 GiB covers the RFC's biggest vector
+*/
+pub const MAX_SCRYPT_LOG_N: u8 = 24;
+pub const MAX_SCRYPT_MEMORY_BYTES: u64 = 1 << 30;
+
+const SALSA_BLOCK_WORDS: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum ScryptError {
+    CostOutOfRange(u8),
+    ZeroParameter,
+    TooMuchMemory,
+}
+
+impl ScryptError {
+    pub fn message(&self) -> String {
+        match self {
+            ScryptError::CostOutOfRange(log_n) => format!(
+                "scrypt cost 2^{} is out of range, it has to be between 2^1 and 2^{}",
+                log_n, MAX_SCRYPT_LOG_N
+            ),
+            ScryptError::ZeroParameter => "scrypt r and p have to be at least 1".to_string(),
+            ScryptError::TooMuchMemory => format!(
+                "scrypt parameters would need more than {} MiB",
+                MAX_SCRYPT_MEMORY_BYTES >> 20
+            ),
+        }
+    }
+}
+
+impl ScryptParams {
+    /*
+       Memory is 128 * r * N for ROMix and 128 * r * p for the blocks PBKDF2 hands it, both have to stay bounded
+    */
+    pub fn validate(&self) -> Result<(), ScryptError> {
+        if self.log_n < 1 || self.log_n > MAX_SCRYPT_LOG_N {
+            return Err(ScryptError::CostOutOfRange(self.log_n));
+        }
+        if self.r == 0 || self.p == 0 {
+            return Err(ScryptError::ZeroParameter);
+        }
+
+        let block_bytes = 128 * self.r as u64;
+        let too_big = |count: u64| {
+            block_bytes
+                .checked_mul(count)
+                .is_none_or(|x| x > MAX_SCRYPT_MEMORY_BYTES)
+        };
+        if too_big(1 << self.log_n) || too_big(self.p as u64) {
+            return Err(ScryptError::TooMuchMemory);
+        }
+        Ok(())
+    }
+}
+
+/*
+   Salsa20/8 core, 8 rounds rather than the usual 20 and no keystream, just the hash function
+*/
+fn salsa20_8(block: &mut [u32; SALSA_BLOCK_WORDS]) {
+    let mut x = *block;
+
+    macro_rules! quarter_round {
+        ($a:expr, $b:expr, $c:expr, $d:expr) => {
+            x[$b] ^= x[$a].wrapping_add(x[$d]).rotate_left(7);
+            x[$c] ^= x[$b].wrapping_add(x[$a]).rotate_left(9);
+            x[$d] ^= x[$c].wrapping_add(x[$b]).rotate_left(13);
+            x[$a] ^= x[$d].wrapping_add(x[$c]).rotate_left(18);
+        };
+    }
+
+    for _ in 0..4 {
+        // Columns
+        quarter_round!(0, 4, 8, 12);
+        quarter_round!(5, 9, 13, 1);
+        quarter_round!(10, 14, 2, 6);
+        quarter_round!(15, 3, 7, 11);
+        // Rows
+        quarter_round!(0, 1, 2, 3);
+        quarter_round!(5, 6, 7, 4);
+        quarter_round!(10, 11, 8, 9);
+        quarter_round!(15, 12, 13, 14);
+    }
+
+    for (word, mixed) in block.iter_mut().zip(x.iter()) {
+        *word = word.wrapping_add(*mixed);
+    }
+}
+
+/*
+   BlockMix, input and output are 2r blocks of 16 words. Even blocks go to the front half of the output and odd
+   blocks to the back half
+*/
+fn block_mix(input: &[u32], output: &mut [u32], r: usize) {
+    let mut x = [0u32; SALSA_BLOCK_WORDS];
+    x.copy_from_slice(&input[(2 * r - 1) * SALSA_BLOCK_WORDS..]);
+
+    for i in 0..2 * r {
+        for (j, word) in x.iter_mut().enumerate() {
+            *word ^= input[i * SALSA_BLOCK_WORDS + j];
+        }
+        salsa20_8(&mut x);
+
+        let destination = (i / 2 + (i % 2) * r) * SALSA_BLOCK_WORDS;
+        output[destination..destination + SALSA_BLOCK_WORDS].copy_from_slice(&x);
+    }
+}
+
+/*
+   ROMix, this is where all the memory goes. V holds N copies of the block which are then read back in a data
+   dependent order
+*/
+fn ro_mix(block: &mut [u32], n: usize, r: usize) {
+    let block_words = 32 * r;
+    let mut v = vec![0u32; n * block_words];
+    let mut x = block.to_vec();
+    let mut y = vec![0u32; block_words];
+
+    for i in 0..n {
+        v[i * block_words..(i + 1) * block_words].copy_from_slice(&x);
+        block_mix(&x, &mut y, r);
+        std::mem::swap(&mut x, &mut y);
+    }
+
+    for _ in 0..n {
+        // Integerify, N is a power of 2 so the low word of the last 64 byte block is enough
+        let j = x[(2 * r - 1) * SALSA_BLOCK_WORDS] as usize & (n - 1);
+        for (k, word) in x.iter_mut().enumerate() {
+            *word ^= v[j * block_words + k];
+        }
+        block_mix(&x, &mut y, r);
+        std::mem::swap(&mut x, &mut y);
+    }
+
+    block.copy_from_slice(&x);
+}
+
+pub fn scrypt(
+    password: &[u8],
+    salt: &[u8],
+    params: ScryptParams,
+    length: usize,
+) -> Result<Vec<u8>, ScryptError> {
+    params.validate()?;
+    let n = 1usize << params.log_n;
+    let r = params.r as usize;
+    let p = params.p as usize;
+    let block_bytes = 128 * r;
+
+    let mut blocks = pbkdf2_hmac_sha256(password, salt, 1, p * block_bytes);
+
+    for chunk in blocks.chunks_exact_mut(block_bytes) {
+        let mut words: Vec<u32> = chunk
+            .chunks_exact(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+
+        ro_mix(&mut words, n, r);
+
+        for (bytes, word) in chunk.chunks_exact_mut(4).zip(words.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    Ok(pbkdf2_hmac_sha256(password, &blocks, 1, length))
+}
@@ -0,0 +1,222 @@
+use crate::cryptography::cryptography::Encryption;
+use crate::cryptography::sha256::{constant_time_eq, hkdf, hmac_sha256, SHA256_OUTPUT_SIZE_BYTES};
+use tracing::warn;
+
+/*
+   Replay protection. Every frame's plaintext starts with a sequence number, 16 lowercase hex digits, and the
+   receiver only accepts each number once. The number sits inside the encryption, and every frame carries an
+   HMAC-SHA256 tag over the whole ciphertext, so a number can't be changed without the frame being dropped. Each
+   direction has its own MAC key, HKDF-SHA256(salt = empty, that direction's key, "kryptos sequence mac"), and the
+   tag is checked before anything is decrypted.
+
+   Frame plaintext is sequence (16 hex digits) | payload
+   Frame on the wire is ciphertext | tag (32 bytes)
+
+   Which numbers are still acceptable is tracked with a sliding window over the highest number seen so far, the same
+   bitmap scheme IPsec and DTLS use. Over TCP nothing is ever reordered so connections use a window of one, anything
+   at or behind the newest frame is a replay. A wider window lets a datagram transport accept limited reordering.
+*/
+pub const SEQUENCE_SIZE_CHARS: usize = 16;
+pub const MAX_REPLAY_WINDOW: u64 = 64;
+pub const STREAM_REPLAY_WINDOW: u64 = 1;
+pub const SEQUENCE_TAG_BYTES: usize = SHA256_OUTPUT_SIZE_BYTES;
+const MAC_INFO: &[u8] = b"kryptos sequence mac";
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    /*
+       The tag didn't match, the frame was changed, cut short or made under another key
+    */
+    Forged,
+    Malformed,
+    Duplicate(u64),
+    TooOld(u64),
+}
+
+impl ReplayError {
+    pub fn message(&self) -> String {
+        match self {
+            ReplayError::Forged => "Frame failed authentication".to_string(),
+            ReplayError::Malformed => "Frame does not start with a sequence number".to_string(),
+            ReplayError::Duplicate(x) => format!("Frame {} was already received", x),
+            ReplayError::TooOld(x) => format!("Frame {} is older than the replay window", x),
+        }
+    }
+}
+
+pub struct ReplayWindow {
+    width: u64,
+    /*
+       Highest sequence number accepted so far, None until the first frame
+    */
+    highest: Option<u64>,
+    /*
+       Bit n is set when highest - n has been accepted
+    */
+    seen: u64,
+}
+
+impl ReplayWindow {
+    pub fn new(width: u64) -> ReplayWindow {
+        ReplayWindow {
+            width: width.clamp(1, MAX_REPLAY_WINDOW),
+            highest: None,
+            seen: 0,
+        }
+    }
+
+    /*
+       Accepts the number and remembers it, or says why it has to be dropped
+    */
+    pub fn check(&mut self, sequence: u64) -> Result<(), ReplayError> {
+        let highest = match self.highest {
+            Some(x) => x,
+            None => {
+                self.highest = Some(sequence);
+                self.seen = 1;
+                return Ok(());
+            }
+        };
+
+        if sequence > highest {
+            let shift = sequence - highest;
+            self.seen = if shift >= MAX_REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = Some(sequence);
+            return Ok(());
+        }
+
+        let behind = highest - sequence;
+        if behind >= self.width {
+            return Err(ReplayError::TooOld(sequence));
+        }
+        if self.seen & (1 << behind) != 0 {
+            return Err(ReplayError::Duplicate(sequence));
+        }
+        self.seen |= 1 << behind;
+        Ok(())
+    }
+}
+
+/*
+   Wraps any of the ciphers (or the ratchet) and numbers and authenticates what goes through it
+*/
+pub struct SequencedContext {
+    inner: Box<dyn Encryption>,
+    next_sequence: u64,
+    window: ReplayWindow,
+    sending_mac_key: Vec<u8>,
+    receiving_mac_key: Vec<u8>,
+    rejected: bool,
+}
+
+impl SequencedContext {
+    /*
+       The keys are the ones each direction is encrypted under, the MAC keys are derived from them
+    */
+    pub fn new(
+        inner: Box<dyn Encryption>,
+        window: u64,
+        sending_key: &[u8],
+        receiving_key: &[u8],
+    ) -> SequencedContext {
+        SequencedContext {
+            inner,
+            next_sequence: 0,
+            window: ReplayWindow::new(window),
+            sending_mac_key: hkdf(&[], sending_key, MAC_INFO, SHA256_OUTPUT_SIZE_BYTES),
+            receiving_mac_key: hkdf(&[], receiving_key, MAC_INFO, SHA256_OUTPUT_SIZE_BYTES),
+            rejected: false,
+        }
+    }
+
+    /*
+       The ciphertext if the tag on the end of the frame is right
+    */
+    fn check_tag<'a>(&self, frame: &'a [u8]) -> Result<&'a [u8], ReplayError> {
+        if frame.len() < SEQUENCE_TAG_BYTES {
+            return Err(ReplayError::Forged);
+        }
+        let (ciphertext, tag) = frame.split_at(frame.len() - SEQUENCE_TAG_BYTES);
+        if !constant_time_eq(&hmac_sha256(&self.receiving_mac_key, ciphertext), tag) {
+            return Err(ReplayError::Forged);
+        }
+        Ok(ciphertext)
+    }
+
+    fn strip_sequence(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, ReplayError> {
+        let sequence = plaintext
+            .get(..SEQUENCE_SIZE_CHARS)
+            .filter(|x| {
+                x.iter()
+                    .all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(c))
+            })
+            .and_then(|x| u64::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok())
+            .ok_or(ReplayError::Malformed)?;
+
+        self.window.check(sequence)?;
+        Ok(plaintext[SEQUENCE_SIZE_CHARS..].to_vec())
+    }
+}
+
+impl Encryption for SequencedContext {
+    fn initialize_context(&mut self) {
+        self.inner.initialize_context();
+    }
+
+    fn encrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        let mut numbered = format!("{:016x}", self.next_sequence).into_bytes();
+        numbered.extend_from_slice(input);
+        self.next_sequence += 1;
+
+        output.resize(numbered.len(), 0);
+        self.inner.encrypt(&mut numbered, output);
+        let tag = hmac_sha256(&self.sending_mac_key, output);
+        output.extend_from_slice(&tag);
+    }
+
+    /*
+       Frames that are forged, replayed, too old or not numbered at all leave the output empty
+    */
+    fn decrypt(&mut self, input: &mut Vec<u8>, output: &mut Vec<u8>) {
+        let mut ciphertext = match self.check_tag(input) {
+            Ok(x) => x.to_vec(),
+            Err(e) => {
+                warn!(reason = %e.message(), "dropping frame");
+                output.clear();
+                self.rejected = true;
+                return;
+            }
+        };
+        self.inner.decrypt(&mut ciphertext, output);
+        self.rejected = self.inner.rejected_last_frame();
+        if self.rejected {
+            return;
+        }
+
+        match self.strip_sequence(output) {
+            Ok(x) => *output = x,
+            Err(e) => {
+                warn!(reason = %e.message(), "dropping frame");
+                output.clear();
+                self.rejected = true;
+            }
+        }
+    }
+
+    fn set_key(&mut self, key: &[u8]) {
+        self.inner.set_key(key);
+    }
+
+    fn get_key(&self) -> &[u8] {
+        self.inner.get_key()
+    }
+
+    fn rejected_last_frame(&self) -> bool {
+        self.rejected
+    }
+}
@@ -0,0 +1,247 @@
+/*
+   SHA-256 as per FIPS 180-4
+   https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf
+*/
+pub const SHA256_BLOCK_SIZE_BYTES: usize = 64;
+pub const SHA256_OUTPUT_SIZE_BYTES: usize = 32;
+
+const INITIAL_HASH: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/*
+   First 32 bits of the fractional parts of the cube roots of the first 64 primes
+*/
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/*
+   Streaming context so callers can hash things piece by piece without gluing buffers together first
+*/
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; SHA256_BLOCK_SIZE_BYTES],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_HASH,
+            buffer: [0; SHA256_BLOCK_SIZE_BYTES],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+        if self.buffer_len > 0 {
+            let take = (SHA256_BLOCK_SIZE_BYTES - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+
+            if self.buffer_len < SHA256_BLOCK_SIZE_BYTES {
+                return;
+            }
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffer_len = 0;
+        }
+
+        let mut blocks = data.chunks_exact(SHA256_BLOCK_SIZE_BYTES);
+        for block in &mut blocks {
+            self.compress(block.try_into().unwrap());
+        }
+
+        let remainder = blocks.remainder();
+        self.buffer[..remainder.len()].copy_from_slice(remainder);
+        self.buffer_len = remainder.len();
+    }
+
+    pub fn finalize(mut self) -> [u8; SHA256_OUTPUT_SIZE_BYTES] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        /*
+           Append the 1 bit, pad with zeros until we are 8 bytes short of a block, then the message length in bits
+        */
+        let mut padding = vec![0x80u8];
+        let used = (self.buffer_len + 1) % SHA256_BLOCK_SIZE_BYTES;
+        let zeros = (SHA256_BLOCK_SIZE_BYTES + 56 - used) % SHA256_BLOCK_SIZE_BYTES;
+        padding.resize(1 + zeros, 0);
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+
+        let total_len = self.total_len;
+        self.update(&padding);
+        self.total_len = total_len;
+
+        let mut output = [0u8; SHA256_OUTPUT_SIZE_BYTES];
+        for (i, word) in self.state.iter().enumerate() {
+            output[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        output
+    }
+
+    fn compress(&mut self, block: &[u8; SHA256_BLOCK_SIZE_BYTES]) {
+        let mut schedule = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            schedule[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+
+        for i in 16..64 {
+            let s0 = schedule[i - 15].rotate_right(7)
+                ^ schedule[i - 15].rotate_right(18)
+                ^ (schedule[i - 15] >> 3);
+            let s1 = schedule[i - 2].rotate_right(17)
+                ^ schedule[i - 2].rotate_right(19)
+                ^ (schedule[i - 2] >> 10);
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(schedule[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (word, new) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(new);
+        }
+    }
+}
+
+pub fn sha256(data: &[u8]) -> [u8; SHA256_OUTPUT_SIZE_BYTES] {
+    let mut context = Sha256::new();
+    context.update(data);
+    context.finalize()
+}
+
+/*
+   HMAC as per RFC 2104
+*/
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; SHA256_OUTPUT_SIZE_BYTES] {
+    let mut block_key = [0u8; SHA256_BLOCK_SIZE_BYTES];
+    if key.len() > SHA256_BLOCK_SIZE_BYTES {
+        block_key[..SHA256_OUTPUT_SIZE_BYTES].copy_from_slice(&sha256(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block_key.map(|x| x ^ 0x36));
+    inner.update(message);
+    let inner_hash = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(&block_key.map(|x| x ^ 0x5c));
+    outer.update(&inner_hash);
+    outer.finalize()
+}
+
+/*
+   HKDF as per RFC 5869, split into the two halves so callers that keep a chain key around can skip the extract step
+*/
+pub fn hkdf_extract(salt: &[u8], input_key_material: &[u8]) -> [u8; SHA256_OUTPUT_SIZE_BYTES] {
+    hmac_sha256(salt, input_key_material)
+}
+
+pub fn hkdf_expand(pseudo_random_key: &[u8], info: &[u8], length: usize) -> Vec<u8> {
+    assert!(length <= 255 * SHA256_OUTPUT_SIZE_BYTES);
+
+    let mut output = Vec::with_capacity(length);
+    let mut previous: Vec<u8> = Vec::new();
+    let mut counter = 1u8;
+
+    while output.len() < length {
+        let mut message = previous.clone();
+        message.extend_from_slice(info);
+        message.push(counter);
+        previous = hmac_sha256(pseudo_random_key, &message).to_vec();
+        output.extend_from_slice(&previous);
+        counter = counter.wrapping_add(1);
+    }
+
+    output.truncate(length);
+    output
+}
+
+pub fn hkdf(salt: &[u8], input_key_material: &[u8], info: &[u8], length: usize) -> Vec<u8> {
+    hkdf_expand(&hkdf_extract(salt, input_key_material), info, length)
+}
+
+/*
+   PBKDF2 as per RFC 8018 with HMAC-SHA256 as the PRF
+*/
+pub fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32, length: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(length);
+    let mut block_index = 1u32;
+
+    while output.len() < length {
+        let mut message = salt.to_vec();
+        message.extend_from_slice(&block_index.to_be_bytes());
+
+        let mut u = hmac_sha256(password, &message);
+        let mut block = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &u);
+            for (x, y) in block.iter_mut().zip(u.iter()) {
+                *x ^= y;
+            }
+        }
+
+        output.extend_from_slice(&block);
+        block_index += 1;
+    }
+
+    output.truncate(length);
+    output
+}
+
+/*
+   Compare without bailing out on the first difference so MAC checks don't leak how much of the tag was right
+*/
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,113 @@
+use crate::cryptography::rc4::KEY_SIZE_BYTES;
+use rand::distr::Alphanumeric;
+use rand::Rng;
+/*
+   Declare submodules. They stay public so the binaries, tests and fuzzers can reach into them, but they are free
+   to move around between releases, other crates should stick to cipher, protocol and server below
+*/
+#[doc(hidden)]
+pub mod arg_handling;
+#[doc(hidden)]
+pub mod cryptography;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod telnet;
+mod tests;
+
+/*
+   The ciphers and the context that wraps them, enough to encrypt and decrypt frames the way the server does
+*/
+pub mod cipher {
+    pub use crate::arg_handling::arg_handling::arg_handling::{EncryptionInfo, KeySize};
+    pub use crate::cryptography::aead::{CtrHmacAead, AEAD_OVERHEAD_BYTES};
+    pub use crate::cryptography::aes::{AESContext, AesMode, AesSize, AES_BLOCK_LENGTH_BYTES};
+    pub use crate::cryptography::cryptography::{from_hex, to_hex, Encryption, EncryptionContext};
+    pub use crate::cryptography::duplex::DuplexContext;
+    pub use crate::cryptography::null_cipher::NullCipher;
+    pub use crate::cryptography::ratchet::{RatchetContext, RatchetError, RatchetRole};
+    pub use crate::cryptography::rc4::{Rc4Key, Rc4State, KEY_SIZE_BYTES as RC4_KEY_SIZE_BYTES};
+    pub use crate::cryptography::sequence::{ReplayError, SequencedContext, MAX_REPLAY_WINDOW};
+    pub use crate::telnet::create_encryption_context;
+}
+
+/*
+   What goes over the wire: the handshake, host key pinning, key rotation, room keys and the telnet layer
+*/
+pub mod protocol {
+    pub use crate::cryptography::ed25519::{fingerprint, Ed25519KeyPair};
+    pub use crate::cryptography::rsa::{RsaPrivateKey, RsaPublicKey};
+    pub use crate::telnet::commands::ROOM_KEY_SWITCH;
+    pub use crate::telnet::framing::{
+        encode_frame, write_frame, FrameError, FrameReader, FRAME_HEADER_BYTES, MAX_FRAME_BYTES,
+    };
+    pub use crate::telnet::handshake::{
+        client_handshake, client_handshake_with_rsa, negotiate, parse_hello, server_handshake,
+        traffic_key, CipherSuite, ClientHandshake, HandshakeError, ServerHandshake, ServerKeys,
+        PROTOCOL, PROTOCOL_VERSION, SUITE_PREFERENCE,
+    };
+    pub use crate::telnet::history::END_OF_HISTORY;
+    pub use crate::telnet::known_hosts::{HostKeyStatus, KnownHosts, DEFAULT_KNOWN_HOSTS_FILE};
+    pub use crate::telnet::rekey::{
+        derive_next_key, parse_rekey_announce, REKEY_ACK, REKEY_ANNOUNCE,
+    };
+    pub use crate::telnet::rooms::{normalize_room_name, RoomEncryption, LOBBY};
+    pub use crate::telnet::{escape_iac, frame_text, TelnetOptions, SHUTDOWN_MESSAGE};
+    pub use crate::{GREETING, SUCCESS_STRING};
+}
+
+/*
+   Everything needed to run a server, the kryptos-server binary is just argument parsing on top of this
+*/
+pub mod server {
+    pub use crate::arg_handling::arg_handling::arg_handling::{
+        parse_arguments, parse_encryption_type, parse_key_size, parse_room, parse_suites,
+        parse_switch, KryptosConfig, RoomConfig,
+    };
+    pub use crate::generate_session_token;
+    pub use crate::logging::logging::{init_logging, LogFormat};
+    pub use crate::telnet::accounts::{user_command, AccountStore};
+    pub use crate::telnet::handshake::{load_host_key, load_rsa_key, ServerKeys};
+    pub use crate::telnet::history::RoomHistory;
+    pub use crate::telnet::rekey::{rotate_keys, spawn_rekey_thread};
+    pub use crate::telnet::rooms::RoomRegistry;
+    pub use crate::telnet::sessions::SessionRegistry;
+    pub use crate::telnet::transcript::{log_command, read_transcript, transcript_key, Transcript};
+    pub use crate::telnet::usernames::UsernamePolicy;
+    pub use crate::telnet::{
+        open_insecure_telnet_connection, open_telnet_connection, shutdown_server,
+        spawn_server_thread, Connection, ConnectionPool, Server, ServerFunctions, ServerState,
+        TelnetServerConnection,
+    };
+}
+
+/*
+   The in-process server and scripted clients the end to end tests use, for the benches and for bots that want to
+   test against a real server. Only there with the test-support feature
+*/
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support {
+    pub use crate::tests::harness::{TestClient, TestServer};
+}
+
+pub static PORT: u64 = 6969;
+
+pub const ERROR: i32 = 1;
+pub const SUCCESS: i32 = 0;
+pub const GREETING: &'static str = "Welcome to the server, what will your username be? :";
+pub const SUCCESS_STRING: &'static str = "Username is valid, joining session\n";
+
+/*
+   Random alphanumeric string, used for the generated session key and for the per user resume tokens
+*/
+pub fn generate_session_token() -> String {
+    // Generate a random alphanumeric string
+    let len = KEY_SIZE_BYTES;
+    let random_string: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect();
+
+    random_string
+}
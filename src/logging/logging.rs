@@ -0,0 +1,46 @@
+use tracing_subscriber::EnvFilter;
+
+/*
+   Filtering follows RUST_LOG (e.g. RUST_LOG=debug or RUST_LOG=kryptos::telnet=trace), info by default
+*/
+const DEFAULT_LOG_FILTER: &str = "info";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub fn parse_log_format(format: &str) -> Option<LogFormat> {
+    match format {
+        "text" => Some(LogFormat::Text),
+        "json" => Some(LogFormat::Json),
+        _ => None,
+    }
+}
+
+/*
+   Every connection thread runs inside a "connection" span carrying conn_id, peer and username, so all of those
+   show up on each event logged for it. The JSON format flattens them into the event for log shippers.
+*/
+pub fn init_logging(format: LogFormat) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Could not set up logging: {}", e);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::cryptography::cryptography::{from_hex, to_hex};
+use crate::cryptography::scrypt::{scrypt, ScryptParams, DEFAULT_SCRYPT_PARAMS};
+use crate::cryptography::sha256::constant_time_eq;
+use crate::telnet::usernames::UsernamePolicy;
+use crate::{ERROR, SUCCESS};
+use rand::RngCore;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tracing::warn;
+
+pub const DEFAULT_ACCOUNTS_FILE: &str = "kryptos.accounts";
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+const SALT_SIZE_BYTES: usize = 16;
+const HASH_SIZE_BYTES: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum AccountError {
+    AlreadyExists,
+    NotFound,
+    PasswordTooShort,
+    Io(String),
+}
+
+impl AccountError {
+    pub fn message(&self) -> String {
+        match self {
+            AccountError::AlreadyExists => "That account already exists".to_string(),
+            AccountError::NotFound => "There is no account with that name".to_string(),
+            AccountError::PasswordTooShort => format!(
+                "Passwords must be at least {} characters long",
+                MIN_PASSWORD_LENGTH
+            ),
+            AccountError::Io(e) => format!("Could not save the account file: {}", e),
+        }
+    }
+}
+
+/*
+   Only the salt and the scrypt output are kept, along with the parameters used so they can be raised later
+   without invalidating existing accounts
+*/
+#[derive(Clone)]
+struct Account {
+    name: String,
+    params: ScryptParams,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl Account {
+    fn new(name: &str, password: &str, params: ScryptParams) -> Account {
+        let mut salt = vec![0u8; SALT_SIZE_BYTES];
+        rand::rng().fill_bytes(&mut salt);
+        // The store checks its parameters when it is opened
+        let hash = scrypt(password.as_bytes(), &salt, params, HASH_SIZE_BYTES)
+            .expect("account store has invalid scrypt parameters");
+        Account {
+            name: name.to_string(),
+            params,
+            salt,
+            hash,
+        }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match scrypt(
+            password.as_bytes(),
+            &self.salt,
+            self.params,
+            HASH_SIZE_BYTES,
+        ) {
+            Ok(hash) => constant_time_eq(&hash, &self.hash),
+            Err(_) => false,
+        }
+    }
+
+    /*
+       name:log_n:r:p:salt:hash, colons can't show up in a valid username so no escaping is needed
+    */
+    fn to_line(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}",
+            self.name,
+            self.params.log_n,
+            self.params.r,
+            self.params.p,
+            to_hex(&self.salt),
+            to_hex(&self.hash)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Account> {
+        let parts: Vec<&str> = line.trim().split(':').collect();
+        if parts.len() != 6 {
+            return None;
+        }
+
+        /*
+           Anyone who can edit the file could otherwise make a login allocate until the process dies
+        */
+        let params = ScryptParams {
+            log_n: parts[1].parse().ok()?,
+            r: parts[2].parse().ok()?,
+            p: parts[3].parse().ok()?,
+        };
+        params.validate().ok()?;
+
+        Some(Account {
+            name: parts[0].to_string(),
+            params,
+            salt: from_hex(parts[4])?,
+            hash: from_hex(parts[5])?,
+        })
+    }
+}
+
+/*
+   Flat file of accounts, read once on startup and rewritten in full on every change. Keyed on the lowercase
+   name so ownership lines up with the case insensitive username uniqueness
+*/
+pub struct AccountStore {
+    path: PathBuf,
+    params: ScryptParams,
+    accounts: RwLock<BTreeMap<String, Account>>,
+    admins: Vec<String>,
+}
+
+impl AccountStore {
+    pub fn open(path: &str) -> io::Result<AccountStore> {
+        Self::open_with_params(path, DEFAULT_SCRYPT_PARAMS)
+    }
+
+    pub fn open_with_params(path: &str, params: ScryptParams) -> io::Result<AccountStore> {
+        params
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.message()))?;
+        let mut accounts = BTreeMap::new();
+
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                for (number, line) in contents.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match Account::from_line(line) {
+                        Some(x) => {
+                            accounts.insert(x.name.to_lowercase(), x);
+                        }
+                        None => warn!(line = number + 1, path, "skipping malformed account"),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(AccountStore {
+            path: PathBuf::from(path),
+            params,
+            accounts: RwLock::new(accounts),
+            admins: Vec::new(),
+        })
+    }
+
+    /*
+       Names allowed to run admin commands, only once they are registered so a guest can't just pick the name
+    */
+    pub fn with_admins(mut self, admins: &[String]) -> AccountStore {
+        self.admins = admins.iter().map(|x| x.to_lowercase()).collect();
+        self
+    }
+
+    pub fn is_admin(&self, name: &str) -> bool {
+        self.admins.contains(&name.to_lowercase()) && self.exists(name)
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.accounts
+            .read()
+            .unwrap()
+            .contains_key(&name.to_lowercase())
+    }
+
+    /*
+       The hash is deliberately slow so it is checked on a copy rather than while holding the lock
+    */
+    pub fn verify(&self, name: &str, password: &str) -> bool {
+        let account = self
+            .accounts
+            .read()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .cloned();
+        match account {
+            Some(x) => x.verify(password),
+            None => false,
+        }
+    }
+
+    pub fn add(&self, name: &str, password: &str) -> Result<(), AccountError> {
+        if password.len() < MIN_PASSWORD_LENGTH {
+            return Err(AccountError::PasswordTooShort);
+        }
+
+        let account = Account::new(name, password, self.params);
+        let mut accounts = self.accounts.write().unwrap();
+        if accounts.contains_key(&name.to_lowercase()) {
+            return Err(AccountError::AlreadyExists);
+        }
+        accounts.insert(name.to_lowercase(), account);
+        self.save(&accounts)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), AccountError> {
+        let mut accounts = self.accounts.write().unwrap();
+        if accounts.remove(&name.to_lowercase()).is_none() {
+            return Err(AccountError::NotFound);
+        }
+        self.save(&accounts)
+    }
+
+    pub fn set_password(&self, name: &str, password: &str) -> Result<(), AccountError> {
+        if password.len() < MIN_PASSWORD_LENGTH {
+            return Err(AccountError::PasswordTooShort);
+        }
+
+        let mut accounts = self.accounts.write().unwrap();
+        let account = match accounts.get_mut(&name.to_lowercase()) {
+            Some(x) => x,
+            None => return Err(AccountError::NotFound),
+        };
+        *account = Account::new(&account.name, password, self.params);
+        self.save(&accounts)
+    }
+
+    /*
+       Write to a temporary file and rename it over the old one so a crash can't leave a half written store
+    */
+    fn save(&self, accounts: &BTreeMap<String, Account>) -> Result<(), AccountError> {
+        let mut contents = String::new();
+        for account in accounts.values() {
+            contents.push_str(&account.to_line());
+            contents.push('\n');
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .map_err(|e| AccountError::Io(e.to_string()))
+    }
+}
+
+pub fn read_password(prompt: &str) -> String {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    let mut password = String::new();
+    io::stdin().read_line(&mut password).unwrap();
+    password.trim_end_matches(['\r', '\n']).to_string()
+}
+
+/*
+   kryptos-server user add|del|passwd name [accounts-file]
+*/
+pub fn user_command(args: &[String]) -> i32 {
+    if args.len() < 2 || args.len() > 3 {
+        println!("Usage: kryptos-server user add|del|passwd name (optional accounts file, {DEFAULT_ACCOUNTS_FILE} otherwise)");
+        return ERROR;
+    }
+
+    let name = &args[1];
+    let path = args
+        .get(2)
+        .map(|x| x.as_str())
+        .unwrap_or(DEFAULT_ACCOUNTS_FILE);
+    let store = match AccountStore::open(path) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Could not open {}: {}", path, e);
+            return ERROR;
+        }
+    };
+
+    let result = match args[0].as_str() {
+        "add" => {
+            if let Err(e) = UsernamePolicy::default().validate(name) {
+                eprintln!("{}", e.message());
+                return ERROR;
+            }
+            let password = read_password("Password: ");
+            store.add(name, &password)
+        }
+        "del" => store.remove(name),
+        "passwd" => {
+            if !store.exists(name) {
+                Err(AccountError::NotFound)
+            } else {
+                let password = read_password("New password: ");
+                store.set_password(name, &password)
+            }
+        }
+        _ => {
+            eprintln!(
+                "Unknown user command {}, expected add, del or passwd",
+                args[0]
+            );
+            return ERROR;
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            println!("Done.");
+            SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e.message());
+            ERROR
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::cryptography::cryptography::from_hex;
+use crate::telnet::rekey::rotate_keys;
+use crate::telnet::rooms::{normalize_room_name, LOBBY};
+use crate::telnet::{
+    broadcast_to_room, find_connection_by_name, replay_history, Connection, Server, ServerFunctions,
+};
+use std::sync::Arc;
+
+/*
+   Ends the confirmation for a room with a different key, clients switch keys once they see it
+*/
+pub const ROOM_KEY_SWITCH: &str = ", switching to the room key\n";
+const UNKNOWN_COMMAND: &str = "Unknown command. Available commands: /join #room, /part, /list, /msg user message, /key user public-key, /emsg user ciphertext, /register password, /passwd old-password new-password, /rekey (admins only)\n";
+
+/*
+   Sends a message to just this connection
+*/
+pub fn reply(connection: &Connection, message: &str) {
+    let mut conn = connection.write().unwrap();
+    conn.write_from_passed_buffer(&mut message.as_bytes().to_vec());
+}
+
+/*
+   Anything a user sends that starts with a / ends up here instead of being broadcast.
+   The caller must not be holding the lock on the connection.
+*/
+pub fn handle_command(connection: &Connection, server: &Server, line: &str) {
+    let mut parts = line.splitn(2, ' ');
+    let command = parts.next().unwrap_or("");
+    let argument = parts.next().unwrap_or("").trim();
+
+    match command {
+        "/join" => match normalize_room_name(argument) {
+            Some(room) => {
+                if change_room(connection, server, &room) {
+                    replay_history(connection, &room, server);
+                }
+            }
+            None => reply(
+                connection,
+                "Room names are 1-24 letters, digits, - or _. Usage: /join #room\n",
+            ),
+        },
+        "/part" => {
+            if change_room(connection, server, LOBBY) {
+                replay_history(connection, LOBBY, server);
+            }
+        }
+        "/list" => {
+            let mut message = String::from("Rooms:\n");
+            for line in server.rooms.list() {
+                message.push_str(&line);
+                message.push('\n');
+            }
+            reply(connection, &message);
+        }
+        "/msg" | "/key" | "/emsg" => direct_message(connection, server, command, argument),
+        "/register" => register(connection, server, argument),
+        "/passwd" => change_password(connection, server, argument),
+        "/rekey" => {
+            let name = connection.read().unwrap().name.clone();
+            if server.accounts.as_ref().is_some_and(|x| x.is_admin(&name)) {
+                rotate_keys(server, "admin command");
+            } else {
+                reply(connection, "Only admins can rotate the keys\n");
+            }
+        }
+        _ => reply(connection, UNKNOWN_COMMAND),
+    }
+}
+
+/*
+   /msg is relayed in the clear as far as the server is concerned (it is still encrypted on the wire to each client).
+   /key and /emsg carry the end to end exchange, the server checks they are hex and passes them on untouched,
+   it never has the pairwise key so it cannot read them. Nothing here vouches for a /key, a server that swapped
+   them would be found out by the safety numbers the two clients show, see direct_message.rs
+*/
+fn direct_message(connection: &Connection, server: &Server, command: &str, argument: &str) {
+    let mut parts = argument.splitn(2, ' ');
+    let target_name = parts.next().unwrap_or("");
+    let payload = parts.next().unwrap_or("").trim();
+
+    if target_name.is_empty() || payload.is_empty() {
+        reply(connection, &format!("Usage: {} user message\n", command));
+        return;
+    }
+
+    if command != "/msg" && from_hex(payload).is_none() {
+        reply(
+            connection,
+            &format!("{} expects a hex encoded payload\n", command),
+        );
+        return;
+    }
+
+    let target = match find_connection_by_name(target_name, server) {
+        Some(x) => x,
+        None => {
+            reply(
+                connection,
+                &format!("No user named {} is online\n", target_name),
+            );
+            return;
+        }
+    };
+
+    if Arc::ptr_eq(&target, connection) {
+        reply(connection, "You can't message yourself\n");
+        return;
+    }
+
+    let sender = connection.read().unwrap().display_name();
+    let message = match command {
+        "/msg" => format!("[dm] {}: {}\n", sender, payload),
+        "/key" => format!("[key] {} {}\n", sender, payload),
+        _ => format!("[emsg] {} {}\n", sender, payload),
+    };
+    reply(&target, &message);
+
+    if command == "/msg" {
+        reply(
+            connection,
+            &format!("[dm to {}] {}\n", target_name, payload),
+        );
+    }
+}
+
+/*
+   Registers the name the connection is using, from then on joining under it takes the password
+*/
+fn register(connection: &Connection, server: &Server, password: &str) {
+    let accounts = match &server.accounts {
+        Some(x) => x,
+        None => {
+            reply(connection, "Accounts are not enabled on this server\n");
+            return;
+        }
+    };
+
+    let name = connection.read().unwrap().name.clone();
+    match accounts.add(&name, password) {
+        Ok(()) => reply(connection, &format!("{} is now registered to you\n", name)),
+        Err(e) => reply(connection, &format!("{}\n", e.message())),
+    }
+}
+
+fn change_password(connection: &Connection, server: &Server, argument: &str) {
+    let accounts = match &server.accounts {
+        Some(x) => x,
+        None => {
+            reply(connection, "Accounts are not enabled on this server\n");
+            return;
+        }
+    };
+
+    let mut parts = argument.split_whitespace();
+    let (old, new) = match (parts.next(), parts.next()) {
+        (Some(old), Some(new)) => (old, new),
+        _ => {
+            reply(connection, "Usage: /passwd old-password new-password\n");
+            return;
+        }
+    };
+
+    let name = connection.read().unwrap().name.clone();
+    if !accounts.verify(&name, old) {
+        reply(connection, "Wrong password\n");
+        return;
+    }
+
+    match accounts.set_password(&name, new) {
+        Ok(()) => reply(connection, "Password changed\n"),
+        Err(e) => reply(connection, &format!("{}\n", e.message())),
+    }
+}
+
+/*
+   Moves the connection into another room, scoping the leave and join broadcasts to the rooms involved.
+   If the new room has a different cipher or key the confirmation is sent under the old one and everything
+   after it under the new one. The client answers with REKEY_ACK under the old key and switches over, so as
+   with a rekey, what it typed before it saw the confirmation is still read under the old key.
+   Returns false if the connection was already in the room.
+*/
+pub fn change_room(connection: &Connection, server: &Server, room: &str) -> bool {
+    let (connection_id, name, insecure) = {
+        let conn = connection.read().unwrap();
+        (conn.connection_id, conn.display_name(), conn.insecure)
+    };
+
+    /*
+       A room with its own key is private to whoever has that key, plaintext users would leak it all
+    */
+    if insecure && server.rooms.has_own_encryption(room) {
+        reply(
+            connection,
+            &format!(
+                "#{} is encrypted with its own key, unencrypted connections can't join it\n",
+                room
+            ),
+        );
+        return false;
+    }
+
+    let current = server.rooms.room_of(connection_id);
+    if current.as_deref() == Some(room) {
+        reply(connection, &format!("You are already in #{}\n", room));
+        return false;
+    }
+
+    /*
+       Connections that aren't in a room yet are still on the key they connected with, the lobby one
+    */
+    let _keys = server.rooms.lock_keys();
+    let epoch = server.rooms.epoch();
+    let previous_encryption = connection.write().unwrap().encryption_for(
+        &server.rooms,
+        current.as_deref().unwrap_or(LOBBY),
+        epoch,
+    );
+    let previous = server.rooms.join(room, connection_id);
+
+    if let Some(previous) = previous {
+        let mut message = format!("{} has left #{}\n", name, previous).into_bytes();
+        broadcast_to_room(&mut message, connection_id, &previous, server);
+    }
+
+    {
+        let mut conn = connection.write().unwrap();
+        let encryption = conn.encryption_for(&server.rooms, room, epoch);
+        if previous_encryption != encryption {
+            let message = format!("Joined #{}{}", room, ROOM_KEY_SWITCH);
+            conn.write_from_passed_buffer(&mut message.into_bytes());
+            let context = encryption.create_context(&conn.traffic_salt);
+            conn.switch_encryption_context(context);
+        } else {
+            let message = format!("Joined #{}\n", room);
+            conn.write_from_passed_buffer(&mut message.into_bytes());
+        }
+    }
+
+    let mut message = format!("{} has joined #{}\n", name, room).into_bytes();
+    broadcast_to_room(&mut message, connection_id, room, server);
+    true
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::io::{Read, Write};
+
+/*
+   Length prefixed frames for everything sent after the handshake on an encrypted connection.
+
+   TCP hands over a stream of bytes, not the writes that went into it, so two frames written back to back can
+   come out of one read and a long one can take several. Each frame goes on the wire as its length, 4 bytes big
+   endian, followed by the frame itself, and FrameReader puts them back together on the other side.
+   Plaintext telnet connections don't use this, a stock telnet client knows nothing about it
+*/
+pub const FRAME_HEADER_BYTES: usize = 4;
+/*
+   Room for a full scrollback or resume backlog in one frame, anything claiming to be longer is garbage
+*/
+pub const MAX_FRAME_BYTES: usize = 1 << 20;
+const READ_CHUNK_BYTES: usize = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    TooLong(usize),
+}
+
+impl FrameError {
+    pub fn message(&self) -> String {
+        match self {
+            FrameError::TooLong(length) => format!(
+                "Frame of {} bytes is over the {} byte limit",
+                length, MAX_FRAME_BYTES
+            ),
+        }
+    }
+}
+
+pub fn encode_frame(frame: &[u8]) -> Vec<u8> {
+    let mut output = (frame.len() as u32).to_be_bytes().to_vec();
+    output.extend_from_slice(frame);
+    output
+}
+
+/*
+   One write per frame so the prefix and the frame can't be split up by another thread writing in between
+*/
+pub fn write_frame<W: Write>(stream: &mut W, frame: &[u8]) -> io::Result<()> {
+    stream.write_all(&encode_frame(frame))
+}
+
+/*
+   Collects bytes as they arrive and hands out whole frames. Whatever is left over, the start of the next frame
+   or a second frame that came in the same read, stays buffered for the next call
+*/
+#[derive(Debug, Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn new() -> FrameReader {
+        FrameReader { buffer: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /*
+       The next whole frame if one has arrived. A length over the limit is an error and stays one, there is no
+       finding the next frame boundary after it
+    */
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let length = match self.buffer.get(..FRAME_HEADER_BYTES) {
+            Some(x) => u32::from_be_bytes(x.try_into().unwrap()) as usize,
+            None => return Ok(None),
+        };
+        if length > MAX_FRAME_BYTES {
+            return Err(FrameError::TooLong(length));
+        }
+        if self.buffer.len() < FRAME_HEADER_BYTES + length {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[FRAME_HEADER_BYTES..FRAME_HEADER_BYTES + length].to_vec();
+        self.buffer.drain(..FRAME_HEADER_BYTES + length);
+        Ok(Some(frame))
+    }
+
+    /*
+       Reads until a whole frame is in. Errors from the stream, WouldBlock and TimedOut included, come straight
+       back and nothing read so far is lost, so this works on nonblocking sockets and ones with a read timeout.
+       The other end closing is UnexpectedEof
+    */
+    pub fn read_frame<R: Read>(&mut self, stream: &mut R) -> io::Result<Vec<u8>> {
+        let mut chunk = [0u8; READ_CHUNK_BYTES];
+        loop {
+            match self.next_frame() {
+                Ok(Some(frame)) => return Ok(frame),
+                Ok(None) => {}
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.message())),
+            }
+
+            let length = stream.read(&mut chunk)?;
+            if length == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.push(&chunk[..length]);
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use crate::arg_handling::arg_handling::arg_handling::{EncryptionInfo, KeySize};
+use crate::cryptography::cryptography::{from_hex, to_hex};
+use crate::cryptography::ed25519::{
+    ed25519_verify, public_key_to_spki_pem, Ed25519KeyPair, ED25519_PUBLIC_KEY_SIZE_BYTES,
+};
+use crate::cryptography::ratchet::RatchetRole;
+use crate::cryptography::rc4::KEY_SIZE_BYTES as RC4_KEY_SIZE_BYTES;
+use crate::cryptography::rsa::{RsaPrivateKey, RsaPublicKey};
+use crate::cryptography::sha256::{constant_time_eq, hkdf, hmac_sha256};
+use rand::RngCore;
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/*
+   Plaintext hello exchanged before anything is encrypted, one line per message:
+
+   client: KRYPTOS/1 HELLO <client nonce> <suite>,<suite>,...
+   server: KRYPTOS/1 CHOOSE <server nonce> <suite>
+   client: KRYPTOS/1 CONFIRM <client confirmation>
+   server: KRYPTOS/1 CONFIRM <server confirmation>
+   either: KRYPTOS/1 ERROR <reason>
+
+   The server picks the strongest suite it allows that the client offered. The suite key is
+   HKDF-SHA256(salt = empty, session key, "kryptos suite <suite>"), as long as that suite's key, and each direction
+   of the connection encrypts under its own traffic key taken from it,
+   HKDF-SHA256(salt = client nonce | server nonce, suite key, "kryptos traffic client to server" or
+   "kryptos traffic server to client"). The nonces make those keys different for every connection.
+   Both ends prove they hold the same session key with HMAC-SHA256 over the transcript, the client hello line and
+   the CHOOSE line, keyed with HKDF-SHA256(salt = empty, suite key, "kryptos key confirmation") and prefixed with
+   "server" or "client" depending on who sends it. A mismatch means the wrong key, not a guess. The client goes
+   first and the server only answers a confirmation it has checked, so connecting hands nobody a value to guess
+   the session key against offline, a wrong one gets "ERROR wrong key" instead.
+
+   A client that doesn't know the session key but has the server's RSA public key adds a fresh 32 byte transport
+   key to its hello, RSA-OAEP encrypted to the server with the label "kryptos session key":
+
+   client: KRYPTOS/1 HELLO <client nonce> <suite>,<suite>,... <wrapped transport key>
+   server: KRYPTOS/1 CHOOSE <server nonce> <suite>
+
+   The transport key then is that connection's session key, confirmations and traffic keys come from it exactly
+   as they would from the shared one, and the server's own session key never leaves it
+
+   Straight after its CHOOSE line the server proves who it is with its Ed25519 host key:
+
+   server: KRYPTOS/1 HOSTKEY <public key> <signature>
+
+   The signature is over "kryptos host key", a newline, the client hello line, a newline and the whole CHOOSE line.
+   Clients check it before confirming, and compare the key with the one they pinned for that server
+*/
+pub const PROTOCOL: &str = "KRYPTOS";
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const NONCE_SIZE_BYTES: usize = 16;
+/*
+   Room for a hello carrying a key wrapped to a 4096 bit RSA key, 1024 hex characters on its own
+*/
+const MAX_LINE_LENGTH: usize = 2048;
+const SUITE_INFO: &str = "kryptos suite ";
+const CONFIRMATION_INFO: &[u8] = b"kryptos key confirmation";
+const CLIENT_TRAFFIC_INFO: &[u8] = b"kryptos traffic client to server";
+const SERVER_TRAFFIC_INFO: &[u8] = b"kryptos traffic server to client";
+const WRAP_LABEL: &[u8] = b"kryptos session key";
+const HOST_KEY_CONTEXT: &str = "kryptos host key";
+const WRONG_KEY_REASON: &str = "wrong key";
+pub const TRANSPORT_KEY_SIZE_BYTES: usize = 32;
+pub const DEFAULT_RSA_KEY_BITS: usize = 2048;
+pub const DEFAULT_HOST_KEY_FILE: &str = "kryptos.host_key";
+
+/*
+   The server's long lived keys, the host key it signs every handshake with and the RSA key clients may wrap
+   a transport key to
+*/
+pub struct ServerKeys {
+    pub host_key: Ed25519KeyPair,
+    pub rsa_key: Option<RsaPrivateKey>,
+}
+
+/*
+   What the client side comes away with, the host key is for the caller to pin if it hadn't already
+*/
+#[derive(Debug, PartialEq)]
+pub struct ClientHandshake {
+    pub suite: CipherSuite,
+    pub session_key: Vec<u8>,
+    pub host_key: [u8; ED25519_PUBLIC_KEY_SIZE_BYTES],
+    /*
+       Client nonce then server nonce, what the traffic keys are salted with
+    */
+    pub traffic_salt: Vec<u8>,
+}
+
+/*
+   What the server side comes away with
+*/
+#[derive(Debug, PartialEq)]
+pub struct ServerHandshake {
+    pub suite: CipherSuite,
+    pub traffic_salt: Vec<u8>,
+    /*
+       The transport key an RSA client wrapped to us, the session key for that connection in place of ours
+    */
+    pub session_key: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct CipherSuite {
+    pub encryption_type: EncryptionInfo,
+    pub key_size: KeySize,
+}
+
+const fn suite(encryption_type: EncryptionInfo, key_size: KeySize) -> CipherSuite {
+    CipherSuite {
+        encryption_type,
+        key_size,
+    }
+}
+
+/*
+   Strongest first, the modes that hide patterns before ECB, bigger keys before smaller ones and RC4 last
+*/
+pub const SUITE_PREFERENCE: [CipherSuite; 12] = [
+    suite(EncryptionInfo::AesCtr, KeySize::Size256),
+    suite(EncryptionInfo::AesCbc, KeySize::Size256),
+    suite(EncryptionInfo::AesCtr, KeySize::Size192),
+    suite(EncryptionInfo::AesCbc, KeySize::Size192),
+    suite(EncryptionInfo::AesCtr, KeySize::Size128),
+    suite(EncryptionInfo::AesCbc, KeySize::Size128),
+    suite(EncryptionInfo::AesEcb, KeySize::Size256),
+    suite(EncryptionInfo::AesEcb, KeySize::Size192),
+    suite(EncryptionInfo::AesEcb, KeySize::Size128),
+    suite(EncryptionInfo::Rc4, KeySize::Size256),
+    suite(EncryptionInfo::Rc4, KeySize::Size192),
+    suite(EncryptionInfo::Rc4, KeySize::Size128),
+];
+
+impl CipherSuite {
+    pub fn new(encryption_type: EncryptionInfo, key_size: KeySize) -> CipherSuite {
+        suite(encryption_type, key_size)
+    }
+
+    /*
+       Same names as on the command line joined by a dash, e.g. AesCtr-256
+    */
+    pub fn name(&self) -> String {
+        let encryption_type = match self.encryption_type {
+            EncryptionInfo::AesCbc => "AesCbc",
+            EncryptionInfo::AesCtr => "AesCtr",
+            EncryptionInfo::AesEcb => "AesEcb",
+            EncryptionInfo::Rc4 => "Rc4",
+        };
+        format!(
+            "{}-{}",
+            encryption_type,
+            <KeySize as Into<usize>>::into(self.key_size)
+        )
+    }
+
+    pub fn parse(name: &str) -> Option<CipherSuite> {
+        SUITE_PREFERENCE.iter().find(|x| x.name() == name).copied()
+    }
+
+    /*
+       RC4 always takes a full size key whatever size it is configured with
+    */
+    pub fn key_length(&self) -> usize {
+        match self.encryption_type {
+            EncryptionInfo::Rc4 => RC4_KEY_SIZE_BYTES,
+            _ => <KeySize as Into<usize>>::into(self.key_size) / 8,
+        }
+    }
+
+    pub fn derive_key(&self, session_key: &[u8]) -> Vec<u8> {
+        let info = format!("{}{}", SUITE_INFO, self.name());
+        hkdf(&[], session_key, info.as_bytes(), self.key_length())
+    }
+}
+
+/*
+   Key for the frames one end of a connection sends, from the key the room or suite uses. The salt is the
+   connection's two hello nonces so no two connections, and neither direction of one, share a traffic key
+*/
+pub fn traffic_key(key: &[u8], salt: &[u8], sender: RatchetRole, length: usize) -> Vec<u8> {
+    let info = match sender {
+        RatchetRole::Client => CLIENT_TRAFFIC_INFO,
+        RatchetRole::Server => SERVER_TRAFFIC_INFO,
+    };
+    hkdf(salt, key, info, length)
+}
+
+impl fmt::Debug for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub type ClientHello = (Vec<u8>, Vec<CipherSuite>, Option<Vec<u8>>);
+
+#[derive(Debug, PartialEq)]
+pub enum HandshakeError {
+    Io(String),
+    Malformed,
+    UnsupportedVersion(u32),
+    NoCommonSuite,
+    WrongKey,
+    /*
+       The client wrapped a key to us but we were started without --rsa-key
+    */
+    NoRsaKey,
+    /*
+       The host key signature over the handshake didn't check out
+    */
+    BadHostSignature,
+    /*
+       The server presented a different host key than the one pinned for it
+    */
+    HostKeyChanged,
+    /*
+       The other end sent an ERROR line, this is its reason
+    */
+    Refused(String),
+}
+
+impl HandshakeError {
+    pub fn message(&self) -> String {
+        match self {
+            HandshakeError::Io(x) => format!("Connection failed during the handshake: {}", x),
+            HandshakeError::Malformed => "Handshake message was not understood".to_string(),
+            HandshakeError::UnsupportedVersion(x) => format!(
+                "Protocol version {} is not supported, this end speaks {}",
+                x, PROTOCOL_VERSION
+            ),
+            HandshakeError::NoCommonSuite => "No cipher suite in common".to_string(),
+            HandshakeError::WrongKey => {
+                "Key confirmation failed, the session keys do not match".to_string()
+            }
+            HandshakeError::NoRsaKey => {
+                "The server has no RSA key to unwrap a session key with".to_string()
+            }
+            HandshakeError::BadHostSignature => {
+                "The server's host key signature over the handshake is not valid".to_string()
+            }
+            HandshakeError::HostKeyChanged => {
+                "The server's host key has changed, someone may be intercepting the connection"
+                    .to_string()
+            }
+            HandshakeError::Refused(x) => format!("The other end refused the handshake: {}", x),
+        }
+    }
+}
+
+/*
+   Picks the strongest suite that is both allowed here and offered by the other end
+*/
+pub fn negotiate(allowed: &[CipherSuite], offered: &[CipherSuite]) -> Option<CipherSuite> {
+    SUITE_PREFERENCE
+        .iter()
+        .find(|x| allowed.contains(x) && offered.contains(x))
+        .copied()
+}
+
+/*
+   Runs the server side of the hello over a fresh connection, returns the suite both ends settled on, the
+   salt for the traffic keys and the client's own session key if it wrapped one to us. Without an RSA key, those
+   clients are turned away
+*/
+pub fn server_handshake<S: Read + Write>(
+    stream: &mut S,
+    session_key: &[u8],
+    allowed: &[CipherSuite],
+    keys: &ServerKeys,
+) -> Result<ServerHandshake, HandshakeError> {
+    let client_line = read_line(stream)?;
+    let (client_nonce, offered, wrapped_key) = match parse_hello(&client_line) {
+        Ok(x) => x,
+        Err(e) => return Err(refuse(stream, e)),
+    };
+    if client_nonce.len() != NONCE_SIZE_BYTES {
+        return Err(refuse(stream, HandshakeError::Malformed));
+    }
+    let suite = match negotiate(allowed, &offered) {
+        Some(x) => x,
+        None => return Err(refuse(stream, HandshakeError::NoCommonSuite)),
+    };
+
+    let transport_key = match (wrapped_key, &keys.rsa_key) {
+        (None, _) => None,
+        (Some(_), None) => return Err(refuse(stream, HandshakeError::NoRsaKey)),
+        (Some(wrapped_key), Some(rsa_key)) => match rsa_key
+            .decrypt_oaep(&wrapped_key, WRAP_LABEL)
+            .ok()
+            .filter(|x| x.len() == TRANSPORT_KEY_SIZE_BYTES)
+        {
+            Some(x) => Some(x),
+            None => return Err(refuse(stream, HandshakeError::Malformed)),
+        },
+    };
+
+    let server_nonce = generate_nonce();
+    let server_line = format!(
+        "{} CHOOSE {} {}",
+        version_tag(),
+        to_hex(&server_nonce),
+        suite.name()
+    );
+    write_line(stream, &server_line)?;
+
+    let signature = keys
+        .host_key
+        .sign(host_key_message(&client_line, &server_line).as_bytes());
+    write_line(
+        stream,
+        &format!(
+            "{} HOSTKEY {} {}",
+            version_tag(),
+            to_hex(&keys.host_key.public),
+            to_hex(&signature)
+        ),
+    )?;
+
+    let confirm_line = read_line(stream)?;
+    let fields = match parse_fields(&confirm_line) {
+        Ok(x) => x,
+        Err(e @ HandshakeError::Refused(_)) => return Err(e),
+        Err(e) => return Err(refuse(stream, e)),
+    };
+    let client_confirmation = match fields.as_slice() {
+        ["CONFIRM", x] => from_hex(x),
+        _ => None,
+    };
+    let client_confirmation = match client_confirmation {
+        Some(x) => x,
+        None => return Err(refuse(stream, HandshakeError::Malformed)),
+    };
+
+    let transcript = format!("{}\n{}", client_line, server_line);
+    let confirmation_key = confirmation_key(transport_key.as_deref().unwrap_or(session_key), suite);
+    if !constant_time_eq(
+        &client_confirmation,
+        &confirmation(&confirmation_key, "client", &transcript),
+    ) {
+        return Err(refuse(stream, HandshakeError::WrongKey));
+    }
+    write_line(
+        stream,
+        &format!(
+            "{} CONFIRM {}",
+            version_tag(),
+            to_hex(&confirmation(&confirmation_key, "server", &transcript))
+        ),
+    )?;
+    Ok(ServerHandshake {
+        suite,
+        traffic_salt: [client_nonce, server_nonce.to_vec()].concat(),
+        session_key: transport_key,
+    })
+}
+
+/*
+   Runs the client side, offering the suites in the order given. With a pinned host key the server has to
+   present exactly that one, otherwise any correctly signed key is accepted and handed back for pinning
+*/
+pub fn client_handshake<S: Read + Write>(
+    stream: &mut S,
+    session_key: &[u8],
+    offered: &[CipherSuite],
+    pinned_host_key: Option<&[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]>,
+) -> Result<ClientHandshake, HandshakeError> {
+    run_client_handshake(stream, Some(session_key), None, offered, pinned_host_key)
+}
+
+/*
+   Client side for when we don't know the session key, a fresh transport key is wrapped to the server's RSA key
+   and used as the session key for this connection
+*/
+pub fn client_handshake_with_rsa<S: Read + Write>(
+    stream: &mut S,
+    server_key: &RsaPublicKey,
+    offered: &[CipherSuite],
+    pinned_host_key: Option<&[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]>,
+) -> Result<ClientHandshake, HandshakeError> {
+    run_client_handshake(stream, None, Some(server_key), offered, pinned_host_key)
+}
+
+fn run_client_handshake<S: Read + Write>(
+    stream: &mut S,
+    session_key: Option<&[u8]>,
+    server_key: Option<&RsaPublicKey>,
+    offered: &[CipherSuite],
+    pinned_host_key: Option<&[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]>,
+) -> Result<ClientHandshake, HandshakeError> {
+    let suites: Vec<String> = offered.iter().map(|x| x.name()).collect();
+    let client_nonce = generate_nonce();
+    let mut client_line = format!(
+        "{} HELLO {} {}",
+        version_tag(),
+        to_hex(&client_nonce),
+        suites.join(",")
+    );
+    let mut transport_key = [0u8; TRANSPORT_KEY_SIZE_BYTES];
+    if let Some(server_key) = server_key {
+        rand::rng().fill_bytes(&mut transport_key);
+        let wrapped_key = server_key
+            .encrypt_oaep(&transport_key, WRAP_LABEL)
+            .map_err(|_| HandshakeError::Malformed)?;
+        client_line.push(' ');
+        client_line.push_str(&to_hex(&wrapped_key));
+    }
+    write_line(stream, &client_line)?;
+
+    let server_line = read_line(stream)?;
+    let fields = parse_fields(&server_line)?;
+    let (nonce, suite) = match fields.as_slice() {
+        ["CHOOSE", nonce, suite] => (*nonce, *suite),
+        _ => return Err(HandshakeError::Malformed),
+    };
+    let server_nonce = from_hex(nonce)
+        .filter(|x| x.len() == NONCE_SIZE_BYTES)
+        .ok_or(HandshakeError::Malformed)?;
+    let suite = CipherSuite::parse(suite)
+        .filter(|x| offered.contains(x))
+        .ok_or(HandshakeError::Malformed)?;
+
+    let session_key = match session_key {
+        Some(x) => x.to_vec(),
+        None => transport_key.to_vec(),
+    };
+    let host_key = read_host_key(stream, &client_line, &server_line)?;
+    if pinned_host_key.is_some_and(|x| *x != host_key) {
+        return Err(HandshakeError::HostKeyChanged);
+    }
+
+    let transcript = format!("{}\n{}", client_line, server_line);
+    let confirmation_key = confirmation_key(&session_key, suite);
+
+    write_line(
+        stream,
+        &format!(
+            "{} CONFIRM {}",
+            version_tag(),
+            to_hex(&confirmation(&confirmation_key, "client", &transcript))
+        ),
+    )?;
+
+    /*
+       The server turns a confirmation it can't match away with "wrong key", which means the same to us as a
+       server confirmation we can't match
+    */
+    let confirm_line = read_line(stream)?;
+    let server_confirmation = match parse_fields(&confirm_line) {
+        Ok(fields) => match fields.as_slice() {
+            ["CONFIRM", x] => from_hex(x).ok_or(HandshakeError::Malformed)?,
+            _ => return Err(HandshakeError::Malformed),
+        },
+        Err(HandshakeError::Refused(x)) if x == WRONG_KEY_REASON => {
+            return Err(HandshakeError::WrongKey)
+        }
+        Err(e) => return Err(e),
+    };
+    if !constant_time_eq(
+        &server_confirmation,
+        &confirmation(&confirmation_key, "server", &transcript),
+    ) {
+        return Err(HandshakeError::WrongKey);
+    }
+    Ok(ClientHandshake {
+        suite,
+        session_key,
+        host_key,
+        traffic_salt: [client_nonce.to_vec(), server_nonce].concat(),
+    })
+}
+
+fn host_key_message(client_line: &str, server_line: &str) -> String {
+    format!("{}\n{}\n{}", HOST_KEY_CONTEXT, client_line, server_line)
+}
+
+/*
+   Reads the HOSTKEY line and checks its signature over the hello lines, returns the key it was made with
+*/
+fn read_host_key<S: Read>(
+    stream: &mut S,
+    client_line: &str,
+    server_line: &str,
+) -> Result<[u8; ED25519_PUBLIC_KEY_SIZE_BYTES], HandshakeError> {
+    let line = read_line(stream)?;
+    let (host_key, signature) = match parse_fields(&line)?.as_slice() {
+        ["HOSTKEY", key, signature] => (
+            from_hex(key)
+                .and_then(|x| x.try_into().ok())
+                .ok_or(HandshakeError::Malformed)?,
+            from_hex(signature).ok_or(HandshakeError::Malformed)?,
+        ),
+        _ => return Err(HandshakeError::Malformed),
+    };
+
+    let message = host_key_message(client_line, server_line);
+    if !ed25519_verify(&host_key, message.as_bytes(), &signature) {
+        return Err(HandshakeError::BadHostSignature);
+    }
+    Ok(host_key)
+}
+
+fn version_tag() -> String {
+    format!("{}/{}", PROTOCOL, PROTOCOL_VERSION)
+}
+
+fn generate_nonce() -> [u8; NONCE_SIZE_BYTES] {
+    let mut nonce = [0u8; NONCE_SIZE_BYTES];
+    rand::rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+fn confirmation_key(session_key: &[u8], suite: CipherSuite) -> Vec<u8> {
+    hkdf(&[], &suite.derive_key(session_key), CONFIRMATION_INFO, 32)
+}
+
+fn confirmation(key: &[u8], sender: &str, transcript: &str) -> [u8; 32] {
+    hmac_sha256(key, format!("{}\n{}", sender, transcript).as_bytes())
+}
+
+/*
+   Checks the version tag and hands back the rest of the line split on spaces.
+   An ERROR line from the other end comes back as Refused
+*/
+fn parse_fields(line: &str) -> Result<Vec<&str>, HandshakeError> {
+    let (tag, rest) = line.split_once(' ').ok_or(HandshakeError::Malformed)?;
+    let version = tag
+        .strip_prefix(PROTOCOL)
+        .and_then(|x| x.strip_prefix('/'))
+        .and_then(|x| x.parse::<u32>().ok())
+        .ok_or(HandshakeError::Malformed)?;
+
+    if let Some(reason) = rest.strip_prefix("ERROR ") {
+        return Err(HandshakeError::Refused(reason.to_string()));
+    }
+    if version != PROTOCOL_VERSION {
+        return Err(HandshakeError::UnsupportedVersion(version));
+    }
+    Ok(rest.split(' ').collect())
+}
+
+/*
+   Returns the client nonce, the suites it offered and the transport key it wrapped to us if any,
+   names we don't know are skipped
+*/
+pub fn parse_hello(line: &str) -> Result<ClientHello, HandshakeError> {
+    let (nonce, suites, wrapped_key) = match parse_fields(line)?.as_slice() {
+        ["HELLO", nonce, suites] => (*nonce, *suites, None),
+        ["HELLO", nonce, suites, wrapped_key] => (
+            *nonce,
+            *suites,
+            Some(from_hex(wrapped_key).ok_or(HandshakeError::Malformed)?),
+        ),
+        _ => return Err(HandshakeError::Malformed),
+    };
+    Ok((
+        from_hex(nonce).ok_or(HandshakeError::Malformed)?,
+        suites.split(',').filter_map(CipherSuite::parse).collect(),
+        wrapped_key,
+    ))
+}
+
+/*
+   Reads the server's Ed25519 host key from a PKCS#8 PEM file, generating one on first run.
+   Its public half is written next to it as <file>.pub
+*/
+pub fn load_host_key(path: &str) -> Result<Ed25519KeyPair, String> {
+    if Path::new(path).exists() {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        return Ed25519KeyPair::from_pkcs8_pem(&text)
+            .ok_or(format!("{}: not a valid Ed25519 private key", path));
+    }
+
+    let key = Ed25519KeyPair::generate();
+    write_private_key(path, &key.to_pkcs8_pem())?;
+    let public_path = format!("{}.pub", path);
+    fs::write(&public_path, public_key_to_spki_pem(&key.public))
+        .map_err(|e| format!("{}: {}", public_path, e))?;
+    Ok(key)
+}
+
+/*
+   Reads the server's RSA key from a PKCS#1 or PKCS#8 PEM file, generating one of the given size on first run.
+   A new key's public half is written next to it as <file>.pub for handing out to clients
+*/
+pub fn load_rsa_key(path: &str, bits: usize) -> Result<RsaPrivateKey, String> {
+    if Path::new(path).exists() {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        return RsaPrivateKey::from_pem(&text).map_err(|e| format!("{}: {}", path, e.message()));
+    }
+
+    let key = RsaPrivateKey::generate(bits).map_err(|e| e.message())?;
+    write_private_key(path, &key.to_pkcs8_pem())?;
+    let public_path = format!("{}.pub", path);
+    fs::write(&public_path, key.public_key().to_spki_pem())
+        .map_err(|e| format!("{}: {}", public_path, e))?;
+    Ok(key)
+}
+
+/*
+   Private keys are created owner only from the start rather than chmodded after, so there is no window where
+   anyone else can read them, and a file that showed up since we checked is left alone
+*/
+fn write_private_key(path: &str, pem: &str) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+        .open(path)
+        .and_then(|mut x| x.write_all(pem.as_bytes()))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/*
+   Tells the other end why we are giving up, best effort since we are about to hang up anyway
+*/
+fn refuse<S: Write>(stream: &mut S, error: HandshakeError) -> HandshakeError {
+    let reason = match &error {
+        HandshakeError::WrongKey => WRONG_KEY_REASON.to_string(),
+        HandshakeError::NoCommonSuite => "no common cipher suite".to_string(),
+        HandshakeError::NoRsaKey => "no rsa key".to_string(),
+        HandshakeError::UnsupportedVersion(_) => {
+            format!("only version {} is supported", PROTOCOL_VERSION)
+        }
+        _ => "malformed handshake".to_string(),
+    };
+    let _ = write_line(stream, &format!("{} ERROR {}", version_tag(), reason));
+    error
+}
+
+/*
+   Turns a client away before the handshake has started, as an ERROR line it can show, there is no key yet that
+   anything else could be sent under
+*/
+pub fn refuse_before_handshake<S: Write>(stream: &mut S, reason: &str) {
+    let _ = write_line(stream, &format!("{} ERROR {}", version_tag(), reason));
+}
+
+fn write_line<S: Write>(stream: &mut S, line: &str) -> Result<(), HandshakeError> {
+    stream
+        .write_all(format!("{}\n", line).as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(|e| HandshakeError::Io(e.to_string()))
+}
+
+/*
+   Reads a byte at a time so nothing past the newline, the first encrypted frame, gets swallowed
+*/
+fn read_line<S: Read>(stream: &mut S) -> Result<String, HandshakeError> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        match stream.read(&mut byte) {
+            Ok(0) => return Err(HandshakeError::Io("connection closed".to_string())),
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(e) => return Err(HandshakeError::Io(e.to_string())),
+        }
+        if line.len() > MAX_LINE_LENGTH {
+            return Err(HandshakeError::Malformed);
+        }
+    }
+
+    String::from_utf8(line)
+        .map(|x| x.trim_end_matches('\r').to_string())
+        .map_err(|_| HandshakeError::Malformed)
+}
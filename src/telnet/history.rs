@@ -0,0 +1,58 @@
+use crate::telnet::transcript::{format_timestamp, TranscriptRecord};
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+pub const DEFAULT_HISTORY_LENGTH: usize = 50;
+pub const END_OF_HISTORY: &str = "--- end of history ---\n";
+
+/*
+   The last few messages said in each room, so people joining have some idea what is going on.
+   Only lives in memory, when there is a transcript it is refilled from that on startup instead of
+   keeping a second copy of the chat on disk
+*/
+pub struct RoomHistory {
+    length: usize,
+    rooms: RwLock<HashMap<String, VecDeque<TranscriptRecord>>>,
+}
+
+impl RoomHistory {
+    pub fn new(length: usize) -> RoomHistory {
+        RoomHistory {
+            length,
+            rooms: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn push(&self, record: TranscriptRecord) {
+        if self.length == 0 {
+            return;
+        }
+
+        let mut rooms = self.rooms.write().unwrap();
+        let history = rooms.entry(record.room.clone()).or_default();
+        if history.len() == self.length {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /*
+       Everything we have for the room as one block, ready to send, or None if nothing has been said there yet
+    */
+    pub fn replay(&self, room: &str) -> Option<String> {
+        let rooms = self.rooms.read().unwrap();
+        let history = rooms.get(room).filter(|x| !x.is_empty())?;
+
+        let mut output = String::new();
+        for record in history {
+            output.push_str(&format!(
+                "[{}] {}: {}\n",
+                format_timestamp(record.timestamp),
+                record.sender,
+                record.text
+            ));
+        }
+        output.push_str(END_OF_HISTORY);
+        Some(output)
+    }
+}
@@ -0,0 +1,122 @@
+use crate::cryptography::ed25519::{
+    public_key_from_ssh_blob, ssh_public_key_blob, ED25519_PUBLIC_KEY_SIZE_BYTES, SSH_KEY_TYPE,
+};
+use crate::cryptography::pem::{base64_decode, base64_encode};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::warn;
+
+pub const DEFAULT_KNOWN_HOSTS_FILE: &str = "kryptos.known_hosts";
+
+/*
+   What a client makes of the host key a server presented
+*/
+#[derive(Debug, PartialEq)]
+pub enum HostKeyStatus {
+    /*
+       Matches the key pinned for that server
+    */
+    Trusted,
+    /*
+       Never seen this server before, the key has just been pinned
+    */
+    Pinned,
+    /*
+       The server presented a different key than the one pinned for it, someone may be in the middle
+    */
+    Changed,
+}
+
+/*
+   Host keys pinned on first use, one "host:port ssh-ed25519 <base64 key>" line per server in the same shape as
+   OpenSSH's known_hosts so the keys can be compared with ssh tooling. Lines are only ever appended
+*/
+pub struct KnownHosts {
+    path: PathBuf,
+    entries: Vec<(String, [u8; ED25519_PUBLIC_KEY_SIZE_BYTES])>,
+}
+
+impl KnownHosts {
+    /*
+       A file that doesn't exist yet is just an empty list
+    */
+    pub fn open(path: &str) -> io::Result<KnownHosts> {
+        let mut entries = Vec::new();
+
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                for (number, line) in contents.lines().enumerate() {
+                    if line.trim().is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    match Self::parse_line(line) {
+                        Some(x) => entries.push(x),
+                        None => warn!(line = number + 1, path, "skipping malformed known host"),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(KnownHosts {
+            path: PathBuf::from(path),
+            entries,
+        })
+    }
+
+    fn parse_line(line: &str) -> Option<(String, [u8; ED25519_PUBLIC_KEY_SIZE_BYTES])> {
+        match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [host, key_type, key] if *key_type == SSH_KEY_TYPE => Some((
+                host.to_string(),
+                public_key_from_ssh_blob(&base64_decode(key)?)?,
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn lookup(&self, host: &str) -> Option<&[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]> {
+        self.entries
+            .iter()
+            .find(|(x, _)| x == host)
+            .map(|(_, key)| key)
+    }
+
+    /*
+       Trust on first use, a host we have no key for gets this one written down. A changed key is never
+       replaced here, the line has to be removed from the file by hand
+    */
+    pub fn check(
+        &mut self,
+        host: &str,
+        key: &[u8; ED25519_PUBLIC_KEY_SIZE_BYTES],
+    ) -> io::Result<HostKeyStatus> {
+        match self.lookup(host) {
+            Some(x) if x == key => Ok(HostKeyStatus::Trusted),
+            Some(_) => Ok(HostKeyStatus::Changed),
+            None => {
+                self.pin(host, key)?;
+                Ok(HostKeyStatus::Pinned)
+            }
+        }
+    }
+
+    fn pin(&mut self, host: &str, key: &[u8; ED25519_PUBLIC_KEY_SIZE_BYTES]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(
+            file,
+            "{} {} {}",
+            host,
+            SSH_KEY_TYPE,
+            base64_encode(&ssh_public_key_blob(key))
+        )?;
+        self.entries.push((host.to_string(), *key));
+        Ok(())
+    }
+}
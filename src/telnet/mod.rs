@@ -1,3 +1,14 @@
+pub mod accounts;
+pub mod commands;
+pub mod framing;
+pub mod handshake;
+pub mod history;
+pub mod known_hosts;
+pub mod rekey;
+pub mod rooms;
+pub mod sessions;
 pub mod telnet;
+pub mod transcript;
+pub mod usernames;
 
 pub use telnet::*;
@@ -0,0 +1,132 @@
+use crate::cryptography::sha256::hkdf;
+use crate::telnet::rooms::LOBBY;
+use crate::telnet::{Connection, Server, ServerFunctions};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/*
+   In-band key rotation.
+
+   Every key the server hands out (the session key, any per room keys and the keys RSA clients brought to their
+   own connections) moves forward together one epoch at a time,
+   key(n) = HKDF-SHA256(salt = n as 8 big endian bytes, key(n - 1), "kryptos rekey"), keeping the key length.
+   The new key never goes over the wire and old keys can't be worked back out from new ones.
+
+   For each connection the server sends "/rekey n" under the old key and encrypts everything after it under the
+   new one. The announce is a frame of its own and the length prefix keeps it apart from the first frame under the
+   new key even when both come out of one read, see framing.rs. The client answers "/rekey-ack n" under the old
+   key and switches over, so the server keeps reading with the old key until it sees that acknowledgement. New
+   connections always start on the session key at epoch 0 and are brought up to the current epoch the same way
+   once they have joined.
+*/
+pub const REKEY_ANNOUNCE: &str = "/rekey";
+pub const REKEY_ACK: &str = "/rekey-ack";
+const REKEY_INFO: &[u8] = b"kryptos rekey";
+const REKEY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn derive_next_key(key: &[u8], epoch: u64) -> Vec<u8> {
+    hkdf(&epoch.to_be_bytes(), key, REKEY_INFO, key.len())
+}
+
+/*
+   The epoch a decrypted frame announces. Only a frame that is "/rekey n\n" and nothing else counts, and only for
+   the epoch after the one the client is on, so a line of chat can't pass for one and a forged epoch can't push
+   the client's keys anywhere the server's aren't
+*/
+pub fn parse_rekey_announce(frame: &str, current_epoch: u64) -> Option<u64> {
+    let epoch = frame
+        .strip_prefix(REKEY_ANNOUNCE)?
+        .strip_prefix(' ')?
+        .strip_suffix('\n')?;
+    if epoch.is_empty() || !epoch.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    epoch
+        .parse::<u64>()
+        .ok()
+        .filter(|x| current_epoch.checked_add(1) == Some(*x))
+}
+
+/*
+   Moves every key forward one epoch and rekeys every joined connection onto its room's new key
+*/
+pub fn rotate_keys(server: &Server, reason: &str) -> u64 {
+    let _keys = server.rooms.lock_keys_for_rotation();
+    let epoch = server.rooms.rotate();
+
+    let pool_ref = server.pool.read().unwrap();
+    for connection in pool_ref.iter() {
+        let mut conn = match connection.write() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let room = server
+            .rooms
+            .room_of(conn.connection_id)
+            .unwrap_or(LOBBY.to_string());
+        let encryption = conn.encryption_for(&server.rooms, &room, epoch);
+        let context = encryption.create_context(&conn.traffic_salt);
+        conn.rekey(epoch, context);
+    }
+
+    info!(epoch, reason, "rotated keys");
+    epoch
+}
+
+/*
+   Brings a connection that just joined up to the current epoch, it was let in under the epoch 0 session key.
+   Clients only follow an announce for the next epoch, so it gets one announce per epoch it missed
+*/
+pub fn catch_up(connection: &Connection, server: &Server) {
+    let _keys = server.rooms.lock_keys();
+    let epoch = server.rooms.epoch();
+
+    let mut conn = connection.write().unwrap();
+    let mut default = server.rooms.session_encryption().clone();
+    for step in 1..=epoch {
+        default.key = derive_next_key(&default.key, step);
+        if step <= conn.key_epoch {
+            continue;
+        }
+        let encryption = conn.encryption_over(&server.rooms, LOBBY, step, &default);
+        let context = encryption.create_context(&conn.traffic_salt);
+        conn.rekey(step, context);
+    }
+}
+
+/*
+   Rotates on a timer and/or once any one connection has had enough bytes encrypted under the current key
+*/
+pub fn spawn_rekey_thread(server: Server, interval: Option<Duration>, byte_limit: Option<u64>) {
+    if interval.is_none() && byte_limit.is_none() {
+        return;
+    }
+
+    thread::spawn(move || {
+        let mut last_rotation = Instant::now();
+        loop {
+            thread::sleep(REKEY_CHECK_INTERVAL);
+
+            let reason = if interval.is_some_and(|x| last_rotation.elapsed() >= x) {
+                "interval"
+            } else if byte_limit.is_some_and(|x| most_bytes_sent(&server) >= x) {
+                "byte limit"
+            } else {
+                continue;
+            };
+
+            rotate_keys(&server, reason);
+            last_rotation = Instant::now();
+        }
+    });
+}
+
+fn most_bytes_sent(server: &Server) -> u64 {
+    let pool_ref = server.pool.read().unwrap();
+    pool_ref
+        .iter()
+        .filter_map(|x| x.read().ok().map(|conn| conn.bytes_sent))
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,302 @@
+use crate::arg_handling::arg_handling::arg_handling::{EncryptionInfo, KeySize, RoomConfig};
+use crate::telnet::handshake::CipherSuite;
+use crate::telnet::rekey::derive_next_key;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::warn;
+
+pub const LOBBY: &str = "lobby";
+const MAX_ROOM_NAME_LENGTH: usize = 24;
+
+/*
+   The cipher, key size and key a room encrypts with. Every room without its own entry falls back on the
+   server-wide one that was handed out on startup. The key moves on every time keys are rotated.
+   With ratchet set connections don't use the key directly, it seeds a per message key chain
+*/
+#[derive(Clone, PartialEq)]
+pub struct RoomEncryption {
+    pub encryption_type: EncryptionInfo,
+    pub key_size: KeySize,
+    pub key: Vec<u8>,
+    pub ratchet: bool,
+}
+
+impl RoomEncryption {
+    /*
+       The server default as used by a connection that negotiated the given suite, the key is derived from ours
+    */
+    pub fn for_suite(&self, suite: CipherSuite) -> RoomEncryption {
+        RoomEncryption {
+            encryption_type: suite.encryption_type,
+            key_size: suite.key_size,
+            key: suite.derive_key(&self.key),
+            ratchet: self.ratchet,
+        }
+    }
+}
+
+/*
+   Connections derive Debug and hold one of these, the key stays out of it
+*/
+impl fmt::Debug for RoomEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RoomEncryption")
+            .field(
+                "suite",
+                &CipherSuite::new(self.encryption_type, self.key_size),
+            )
+            .field("ratchet", &self.ratchet)
+            .finish()
+    }
+}
+
+pub struct Room {
+    pub name: String,
+    pub members: Vec<u64>,
+    encryption: Option<RoomEncryption>,
+    /*
+       Rooms created with /join are thrown away once the last member leaves, the lobby and rooms from the
+       command line stick around
+    */
+    persistent: bool,
+}
+
+impl Room {
+    fn new(name: &str, encryption: Option<RoomEncryption>, persistent: bool) -> Room {
+        Room {
+            name: name.to_string(),
+            members: Vec::new(),
+            encryption,
+            persistent,
+        }
+    }
+
+    pub fn has_own_encryption(&self) -> bool {
+        self.encryption.is_some()
+    }
+}
+
+pub struct RoomRegistry {
+    rooms: RwLock<BTreeMap<String, Room>>,
+    default_encryption: RwLock<RoomEncryption>,
+    /*
+       The default as it was on startup, what every connection does its handshake against before being caught up
+    */
+    session_encryption: RoomEncryption,
+    suites: Vec<CipherSuite>,
+    epoch: AtomicU64,
+    /*
+       Held for writing while keys are rotated, and for reading by anyone handing a key to a connection, so
+       nobody is given a key from the epoch that is just being replaced
+    */
+    key_lock: RwLock<()>,
+}
+
+/*
+   Strips the leading # and checks the name is something we are willing to print back to everyone
+*/
+pub fn normalize_room_name(name: &str) -> Option<String> {
+    let name = name.trim().trim_start_matches('#').to_lowercase();
+
+    if name.is_empty() || name.len() > MAX_ROOM_NAME_LENGTH {
+        return None;
+    }
+
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+
+    Some(name)
+}
+
+impl RoomRegistry {
+    pub fn new(default_encryption: RoomEncryption, configured: &[RoomConfig]) -> RoomRegistry {
+        let mut rooms = BTreeMap::new();
+        rooms.insert(LOBBY.to_string(), Room::new(LOBBY, None, true));
+
+        for room in configured {
+            let name = match normalize_room_name(&room.name) {
+                Some(x) => x,
+                None => {
+                    warn!(room = %room.name, "room name is not valid, skipping it");
+                    continue;
+                }
+            };
+            let encryption = room
+                .encryption
+                .as_ref()
+                .map(|(encryption_type, key_size, key)| RoomEncryption {
+                    encryption_type: *encryption_type,
+                    key_size: *key_size,
+                    key: key.as_bytes().to_vec(),
+                    ratchet: default_encryption.ratchet,
+                });
+            rooms.insert(name.clone(), Room::new(&name, encryption, true));
+        }
+
+        RoomRegistry {
+            rooms: RwLock::new(rooms),
+            suites: vec![CipherSuite::new(
+                default_encryption.encryption_type,
+                default_encryption.key_size,
+            )],
+            session_encryption: default_encryption.clone(),
+            default_encryption: RwLock::new(default_encryption),
+            epoch: AtomicU64::new(0),
+            key_lock: RwLock::new(()),
+        }
+    }
+
+    /*
+       Moves a member into a room, creating it on the fly if needed. Returns the room it was in before, if any
+    */
+    pub fn join(&self, room: &str, connection_id: u64) -> Option<String> {
+        let previous = self.leave(connection_id);
+        let mut rooms = self.rooms.write().unwrap();
+        rooms
+            .entry(room.to_string())
+            .or_insert_with(|| Room::new(room, None, false))
+            .members
+            .push(connection_id);
+        previous
+    }
+
+    /*
+       Removes a member from whatever room it is in and returns the name of that room
+    */
+    pub fn leave(&self, connection_id: u64) -> Option<String> {
+        let mut rooms = self.rooms.write().unwrap();
+        let name = rooms
+            .values()
+            .find(|room| room.members.contains(&connection_id))
+            .map(|room| room.name.clone())?;
+
+        let room = rooms.get_mut(&name).unwrap();
+        room.members.retain(|x| *x != connection_id);
+        if room.members.is_empty() && !room.persistent {
+            rooms.remove(&name);
+        }
+        Some(name)
+    }
+
+    pub fn room_of(&self, connection_id: u64) -> Option<String> {
+        let rooms = self.rooms.read().unwrap();
+        rooms
+            .values()
+            .find(|room| room.members.contains(&connection_id))
+            .map(|room| room.name.clone())
+    }
+
+    pub fn members(&self, room: &str) -> Vec<u64> {
+        let rooms = self.rooms.read().unwrap();
+        match rooms.get(room) {
+            Some(x) => x.members.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /*
+       Suites connections may negotiate for the default key, only the startup cipher unless told otherwise
+    */
+    pub fn with_suites(mut self, suites: &[CipherSuite]) -> RoomRegistry {
+        if !suites.is_empty() {
+            self.suites = suites.to_vec();
+        }
+        self
+    }
+
+    pub fn suites(&self) -> &[CipherSuite] {
+        &self.suites
+    }
+
+    pub fn session_encryption(&self) -> &RoomEncryption {
+        &self.session_encryption
+    }
+
+    pub fn has_own_encryption(&self, room: &str) -> bool {
+        let rooms = self.rooms.read().unwrap();
+        rooms.get(room).is_some_and(|x| x.has_own_encryption())
+    }
+
+    /*
+       The encryption a member of the room should be using, either the room's own or the server default under
+       the suite the member negotiated
+    */
+    pub fn encryption_for(&self, room: &str, suite: CipherSuite) -> RoomEncryption {
+        let rooms = self.rooms.read().unwrap();
+        match rooms.get(room).and_then(|x| x.encryption.clone()) {
+            Some(x) => x,
+            None => self.default_encryption.read().unwrap().for_suite(suite),
+        }
+    }
+
+    /*
+       Same as encryption_for with the given key in place of the server default, for connections that brought
+       their own session key
+    */
+    pub fn encryption_over(
+        &self,
+        room: &str,
+        suite: CipherSuite,
+        default: &RoomEncryption,
+    ) -> RoomEncryption {
+        let rooms = self.rooms.read().unwrap();
+        match rooms.get(room).and_then(|x| x.encryption.clone()) {
+            Some(x) => x,
+            None => default.for_suite(suite),
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    pub fn lock_keys(&self) -> RwLockReadGuard<'_, ()> {
+        self.key_lock.read().unwrap()
+    }
+
+    pub fn lock_keys_for_rotation(&self) -> RwLockWriteGuard<'_, ()> {
+        self.key_lock.write().unwrap()
+    }
+
+    /*
+       Moves the default key and every room key on to the next epoch, returns the new epoch.
+       The caller should be holding lock_keys_for_rotation
+    */
+    pub fn rotate(&self) -> u64 {
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let mut default_encryption = self.default_encryption.write().unwrap();
+        default_encryption.key = derive_next_key(&default_encryption.key, epoch);
+
+        let mut rooms = self.rooms.write().unwrap();
+        for room in rooms.values_mut() {
+            if let Some(encryption) = room.encryption.as_mut() {
+                encryption.key = derive_next_key(&encryption.key, epoch);
+            }
+        }
+        epoch
+    }
+
+    /*
+       One line per room for /list
+    */
+    pub fn list(&self) -> Vec<String> {
+        let rooms = self.rooms.read().unwrap();
+        rooms
+            .values()
+            .map(|room| {
+                let mut line = format!("#{} ({} users)", room.name, room.members.len());
+                if room.has_own_encryption() {
+                    line.push_str(" [own key]");
+                }
+                line
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,143 @@
+use crate::generate_session_token;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_RESUME_GRACE_SECONDS: u64 = 120;
+pub const DEFAULT_MISSED_MESSAGE_LIMIT: usize = 100;
+
+/*
+   One per joined user. While the user is online the entry just holds the token, once they drop it keeps
+   their name reserved and collects what is said in their room until the grace window runs out
+*/
+struct ResumeEntry {
+    connection_id: u64,
+    name: String,
+    room: String,
+    disconnected_at: Option<Instant>,
+    missed: VecDeque<Vec<u8>>,
+}
+
+/*
+   What a successful /resume hands back to the connection taking the session over
+*/
+pub struct Resumption {
+    pub name: String,
+    pub room: String,
+    pub missed: Vec<Vec<u8>>,
+}
+
+pub struct SessionRegistry {
+    grace: Duration,
+    missed_limit: usize,
+    entries: Mutex<HashMap<String, ResumeEntry>>,
+}
+
+impl SessionRegistry {
+    pub fn new(grace: Duration, missed_limit: usize) -> SessionRegistry {
+        SessionRegistry {
+            grace,
+            missed_limit,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /*
+       Hands out a fresh token for a connection that just joined, any older token it had stops working
+    */
+    pub fn issue(&self, connection_id: u64, name: &str, room: &str) -> String {
+        let token = generate_session_token();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.connection_id != connection_id);
+        entries.insert(
+            token.clone(),
+            ResumeEntry {
+                connection_id,
+                name: name.to_string(),
+                room: room.to_string(),
+                disconnected_at: None,
+                missed: VecDeque::new(),
+            },
+        );
+        token
+    }
+
+    /*
+       Starts the grace window for a connection that just went away, room is where it was when it dropped
+    */
+    pub fn disconnected(&self, connection_id: u64, room: Option<&str>) {
+        let mut entries = self.entries.lock().unwrap();
+        if self.grace.is_zero() {
+            entries.retain(|_, entry| entry.connection_id != connection_id);
+            return;
+        }
+
+        for entry in entries.values_mut() {
+            if entry.connection_id == connection_id {
+                entry.disconnected_at = Some(Instant::now());
+                if let Some(room) = room {
+                    entry.room = room.to_string();
+                }
+            }
+        }
+    }
+
+    /*
+       True while someone who dropped is still inside their grace window for this name
+    */
+    pub fn is_reserved(&self, name: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        self.prune(&mut entries);
+        entries
+            .values()
+            .any(|entry| entry.disconnected_at.is_some() && entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /*
+       Keeps a copy of a room message for everyone who dropped out of that room, oldest ones fall off past the limit
+    */
+    pub fn record(&self, room: &str, message: &[u8]) {
+        if self.missed_limit == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        for entry in entries.values_mut() {
+            if entry.disconnected_at.is_none() || entry.room != room {
+                continue;
+            }
+            if entry.missed.len() == self.missed_limit {
+                entry.missed.pop_front();
+            }
+            entry.missed.push_back(message.to_vec());
+        }
+    }
+
+    /*
+       Redeems a token. Only works once and only for a session that has actually dropped, so a leaked token
+       can't be used to kick someone who is still online
+    */
+    pub fn take(&self, token: &str) -> Option<Resumption> {
+        let mut entries = self.entries.lock().unwrap();
+        self.prune(&mut entries);
+
+        entries.get(token)?.disconnected_at?;
+        let entry = entries.remove(token)?;
+        Some(Resumption {
+            name: entry.name,
+            room: entry.room,
+            missed: entry.missed.into(),
+        })
+    }
+
+    fn prune(&self, entries: &mut HashMap<String, ResumeEntry>) {
+        entries.retain(|_, entry| match entry.disconnected_at {
+            Some(x) => x.elapsed() < self.grace,
+            None => true,
+        });
+    }
+}
@@ -1,30 +1,460 @@
 use crate::arg_handling::arg_handling::arg_handling::{EncryptionInfo, KeySize};
 use crate::cryptography::aes::{AESContext, AesMode, AesSize};
 use crate::cryptography::cryptography::EncryptionContext;
+use crate::cryptography::duplex::DuplexContext;
+use crate::cryptography::null_cipher::NullCipher;
+use crate::cryptography::ratchet::{RatchetContext, RatchetRole};
 use crate::cryptography::rc4::Rc4State;
-use crate::{GREETING, INVALID_NAME, PORT, SUCCESS_STRING};
+use crate::cryptography::sequence::{SequencedContext, STREAM_REPLAY_WINDOW};
+use crate::telnet::accounts::AccountStore;
+use crate::telnet::commands::{change_room, handle_command};
+use crate::telnet::framing::{encode_frame, FrameReader};
+use crate::telnet::handshake::{
+    refuse_before_handshake, server_handshake, traffic_key, CipherSuite, ServerKeys,
+};
+use crate::telnet::history::RoomHistory;
+use crate::telnet::rekey::{catch_up, derive_next_key, REKEY_ACK, REKEY_ANNOUNCE};
+use crate::telnet::rooms::{RoomEncryption, RoomRegistry, LOBBY};
+use crate::telnet::sessions::{Resumption, SessionRegistry};
+use crate::telnet::transcript::{Transcript, TranscriptRecord};
+use crate::telnet::usernames::{UsernameError, UsernamePolicy};
+use crate::{GREETING, SUCCESS_STRING};
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
-use std::ops::DerefMut;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, trace, warn, Span};
 
 pub type ConnectionPool = Arc<RwLock<VecDeque<Connection>>>;
 pub type Connection = Arc<RwLock<TelnetServerConnection>>;
 pub const VALID_CONNECTION: u64 = 0xFFFFFFFFFFFF;
+const PLAINTEXT_READ_BYTES: usize = 1024;
+const MAX_LOGIN_ATTEMPTS: u32 = 3;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_READ_TIMEOUT: Duration = Duration::from_secs(1);
+/*
+   How long one write may wait on a client that isn't reading before the connection is given up on, broadcasts
+   write under the pool lock so a stalled client can't be allowed to hold everyone else up for longer
+*/
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const SHUTDOWN_MESSAGE: &str = "Server is shutting down, goodbye.\n";
+pub const INSECURE_BANNER: &str = "*** WARNING: this connection is NOT encrypted. Anyone on the network can read everything you type, passwords included. Everyone else sees you marked as insecure. ***\n";
+const INSECURE_MARK: &str = " [insecure]";
+/*
+   Source id for messages from the server itself, so a broadcast doesn't skip anyone
+*/
+const NO_SOURCE: u64 = u64::MAX;
+
+/*
+   Connection ids are handed out from here so they stay unique even after others leave the pool
+*/
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/*
+   Everything the connection threads share, handed to each of them behind an Arc
+*/
+pub struct ServerState {
+    pub pool: ConnectionPool,
+    pub rooms: RoomRegistry,
+    pub usernames: UsernamePolicy,
+    pub accounts: Option<AccountStore>,
+    pub sessions: SessionRegistry,
+    pub transcript: Option<Transcript>,
+    pub history: RoomHistory,
+    /*
+       Host key every handshake is signed with and the RSA key clients may wrap a key to, see handshake.rs
+    */
+    pub keys: ServerKeys,
+}
+
+pub type Server = Arc<ServerState>;
+
+impl ServerState {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pool: ConnectionPool,
+        rooms: RoomRegistry,
+        usernames: UsernamePolicy,
+        accounts: Option<AccountStore>,
+        sessions: SessionRegistry,
+        transcript: Option<Transcript>,
+        history: RoomHistory,
+        keys: ServerKeys,
+    ) -> Server {
+        Arc::new(ServerState {
+            pool,
+            rooms,
+            usernames,
+            accounts,
+            sessions,
+            transcript,
+            history,
+            keys,
+        })
+    }
+}
+
+/*
+   Telnet commands (RFC 854) and the options we know about: ECHO (RFC 857), SUPPRESS-GO-AHEAD (RFC 858),
+   TERMINAL-TYPE (RFC 1091) and NAWS, the window size (RFC 1073)
+*/
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+pub const OPTION_ECHO: u8 = 1;
+pub const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+pub const OPTION_TERMINAL_TYPE: u8 = 24;
+pub const OPTION_NAWS: u8 = 31;
+const TERMINAL_TYPE_IS: u8 = 0;
+const TERMINAL_TYPE_SEND: u8 = 1;
+/*
+   Longer subnegotiations are cut off, nothing we understand comes close
+*/
+const MAX_SUBNEGOTIATION_LENGTH: usize = 64;
+
+/*
+   Where one side of an option stands, the "Q method" of RFC 1143 without the queue. The Want states remember
+   we asked so the answer isn't taken as a request and answered again
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OptionState {
+    No,
+    Yes,
+    WantYes,
+    WantNo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParseState {
+    Data,
+    Command,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+/*
+   Option negotiation (RFC 855) for one connection. Everything read from the client goes through receive, which
+   takes the IAC sequences out so only chat text is left and answers the negotiation. Sequences may be split
+   across reads. Nothing is sent to clients that have never shown they speak telnet, our own client would print it
+*/
+#[derive(Debug)]
+pub struct TelnetOptions {
+    /*
+       Options we perform, indexed by option code
+    */
+    local: [OptionState; 256],
+    /*
+       Options the client performs
+    */
+    remote: [OptionState; 256],
+    state: ParseState,
+    subnegotiation: Vec<u8>,
+    active: bool,
+    /*
+       Columns and rows from NAWS
+    */
+    pub window_size: Option<(u16, u16)>,
+    pub terminal_type: Option<String>,
+}
+
+impl Default for TelnetOptions {
+    fn default() -> Self {
+        TelnetOptions::new()
+    }
+}
+
+impl TelnetOptions {
+    pub fn new() -> TelnetOptions {
+        TelnetOptions {
+            local: [OptionState::No; 256],
+            remote: [OptionState::No; 256],
+            state: ParseState::Data,
+            subnegotiation: Vec::new(),
+            active: false,
+            window_size: None,
+            terminal_type: None,
+        }
+    }
+
+    /*
+       True once the client sent a telnet command or start was called
+    */
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn local_enabled(&self, option: u8) -> bool {
+        self.local[option as usize] == OptionState::Yes
+    }
+
+    pub fn remote_enabled(&self, option: u8) -> bool {
+        self.remote[option as usize] == OptionState::Yes
+    }
+
+    fn supports_local(option: u8) -> bool {
+        matches!(option, OPTION_ECHO | OPTION_SUPPRESS_GO_AHEAD)
+    }
+
+    fn supports_remote(option: u8) -> bool {
+        matches!(
+            option,
+            OPTION_SUPPRESS_GO_AHEAD | OPTION_NAWS | OPTION_TERMINAL_TYPE
+        )
+    }
+
+    /*
+       For connections known to be telnet clients, asks for the window size and terminal type up front.
+       Returns the bytes to send
+    */
+    pub fn start(&mut self) -> Vec<u8> {
+        self.active = true;
+        let mut replies = Vec::new();
+        for option in [OPTION_NAWS, OPTION_TERMINAL_TYPE] {
+            replies.extend(self.request_remote(option, true));
+        }
+        replies
+    }
+
+    /*
+       Asks to turn one of our options on or off, nothing to send if it is already where we want it
+    */
+    pub fn request_local(&mut self, option: u8, enable: bool) -> Vec<u8> {
+        let state = &mut self.local[option as usize];
+        match (*state, enable) {
+            (OptionState::No, true) => {
+                *state = OptionState::WantYes;
+                vec![IAC, WILL, option]
+            }
+            (OptionState::Yes, false) => {
+                *state = OptionState::WantNo;
+                vec![IAC, WONT, option]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn request_remote(&mut self, option: u8, enable: bool) -> Vec<u8> {
+        let state = &mut self.remote[option as usize];
+        match (*state, enable) {
+            (OptionState::No, true) => {
+                *state = OptionState::WantYes;
+                vec![IAC, DO, option]
+            }
+            (OptionState::Yes, false) => {
+                *state = OptionState::WantNo;
+                vec![IAC, DONT, option]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /*
+       Splits what the client sent into chat data and the replies the negotiation needs, (data, replies)
+    */
+    pub fn receive(&mut self, input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut data = Vec::with_capacity(input.len());
+        let mut replies = Vec::new();
+
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (ParseState::Data, IAC) => {
+                    self.active = true;
+                    ParseState::Command
+                }
+                (ParseState::Data, x) => {
+                    data.push(x);
+                    ParseState::Data
+                }
+                (ParseState::Command, IAC) => {
+                    data.push(IAC);
+                    ParseState::Data
+                }
+                (ParseState::Command, x @ (WILL | WONT | DO | DONT)) => ParseState::Negotiation(x),
+                (ParseState::Command, SB) => {
+                    self.subnegotiation.clear();
+                    ParseState::Subnegotiation
+                }
+                // NOP, GA, AYT and the rest carry nothing for a chat server
+                (ParseState::Command, _) => ParseState::Data,
+                (ParseState::Negotiation(verb), option) => {
+                    replies.extend(self.negotiate(verb, option));
+                    ParseState::Data
+                }
+                (ParseState::Subnegotiation, IAC) => ParseState::SubnegotiationCommand,
+                (ParseState::Subnegotiation, x) => {
+                    if self.subnegotiation.len() < MAX_SUBNEGOTIATION_LENGTH {
+                        self.subnegotiation.push(x);
+                    }
+                    ParseState::Subnegotiation
+                }
+                (ParseState::SubnegotiationCommand, IAC) => {
+                    if self.subnegotiation.len() < MAX_SUBNEGOTIATION_LENGTH {
+                        self.subnegotiation.push(IAC);
+                    }
+                    ParseState::Subnegotiation
+                }
+                (ParseState::SubnegotiationCommand, SE) => {
+                    self.finish_subnegotiation();
+                    ParseState::Data
+                }
+                // Anything else ends a broken subnegotiation, it is dropped
+                (ParseState::SubnegotiationCommand, _) => ParseState::Data,
+            };
+        }
+
+        (data, replies)
+    }
+
+    fn negotiate(&mut self, verb: u8, option: u8) -> Vec<u8> {
+        let enable = matches!(verb, WILL | DO);
+        let (state, supported, accept, refuse) = match verb {
+            WILL | WONT => (
+                &mut self.remote[option as usize],
+                Self::supports_remote(option),
+                DO,
+                DONT,
+            ),
+            _ => (
+                &mut self.local[option as usize],
+                Self::supports_local(option),
+                WILL,
+                WONT,
+            ),
+        };
+
+        let reply = match (*state, enable) {
+            (OptionState::No, true) if supported => {
+                *state = OptionState::Yes;
+                Some(accept)
+            }
+            (OptionState::No, true) => Some(refuse),
+            (OptionState::Yes, false) => {
+                *state = OptionState::No;
+                Some(refuse)
+            }
+            (OptionState::WantYes, true) => {
+                *state = OptionState::Yes;
+                None
+            }
+            (OptionState::WantYes | OptionState::WantNo, _) => {
+                *state = OptionState::No;
+                None
+            }
+            _ => None,
+        };
+
+        let mut replies = match reply {
+            Some(x) => vec![IAC, x, option],
+            None => Vec::new(),
+        };
+        if matches!(verb, WILL)
+            && option == OPTION_TERMINAL_TYPE
+            && self.remote_enabled(OPTION_TERMINAL_TYPE)
+        {
+            replies.extend([IAC, SB, OPTION_TERMINAL_TYPE, TERMINAL_TYPE_SEND, IAC, SE]);
+        }
+        replies
+    }
+
+    fn finish_subnegotiation(&mut self) {
+        match self.subnegotiation.as_slice() {
+            [OPTION_NAWS, w1, w2, h1, h2] => {
+                self.window_size = Some((
+                    u16::from_be_bytes([*w1, *w2]),
+                    u16::from_be_bytes([*h1, *h2]),
+                ));
+                debug!(window_size = ?self.window_size, "client window size");
+            }
+            [OPTION_TERMINAL_TYPE, TERMINAL_TYPE_IS, name @ ..] => {
+                self.terminal_type = Some(String::from_utf8_lossy(name).to_string());
+                debug!(terminal_type = ?self.terminal_type, "client terminal type");
+            }
+            _ => {}
+        }
+    }
+}
+
+/*
+   IAC in data has to be doubled so the client doesn't take it as a command
+*/
+pub fn escape_iac(buffer: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(buffer.len());
+    for &byte in buffer {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+/*
+   What a client sent as text, without the padding NULs decryption can leave behind or the line ending
+*/
+pub fn frame_text(buffer: &[u8]) -> String {
+    String::from_utf8_lossy(buffer)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+/*
+   frame_text on one line. Line breaks inside become spaces, otherwise whatever follows one would reach everyone
+   as a line of its own, looking like it came from the server ("/rekey n" included)
+*/
+pub fn chat_text(buffer: &[u8]) -> String {
+    frame_text(buffer).replace(['\r', '\n'], " ")
+}
 
 #[derive(Debug)]
 pub struct TelnetServerConnection {
     socket_addr: SocketAddr,
     pub connection_id: u64,
     pub stream: TcpStream,
+    /*
+       Frames that came in together or only partly, encrypted connections only, see framing.rs
+    */
+    frames: FrameReader,
     pub read_buffer: Vec<u8>,
     pub name: String,
+    /*
+       What the handshake settled on, decides which key this connection gets for rooms on the server default
+    */
+    pub suite: CipherSuite,
+    /*
+       Both hello nonces, every key this connection encrypts with is salted with them, see handshake.rs
+    */
+    pub traffic_salt: Vec<u8>,
+    /*
+       The session key an RSA client wrapped to us, it stands in for the server default on this connection.
+       Moved on with every rotation like the default, own_epoch is the epoch it is at
+    */
+    own_encryption: Option<RoomEncryption>,
+    own_epoch: u64,
     encryption_context: EncryptionContext,
+    /*
+       Contexts for keys we have rotated or switched rooms away from but the client may still be sending under,
+       oldest first. One is dropped for each acknowledgement the client sends
+    */
+    receive_contexts: VecDeque<EncryptionContext>,
+    pub key_epoch: u64,
+    pub bytes_sent: u64,
     log: bool,
     log_file: Option<File>,
+    pub telnet: TelnetOptions,
+    /*
+       Came in on the plaintext listener, stays on NullCipher for good and is marked as such to everyone else
+    */
+    pub insecure: bool,
 }
 
 impl PartialEq<Self> for TelnetServerConnection {
@@ -39,15 +469,155 @@ impl TelnetServerConnection {
             socket_addr: socket,
             connection_id,
             stream,
+            frames: FrameReader::new(),
             read_buffer: vec![0; 1024],
             name: "".to_string(),
+            suite: CipherSuite::new(EncryptionInfo::Rc4, KeySize::Size256),
+            traffic_salt: Vec::new(),
+            own_encryption: None,
+            own_epoch: 0,
             encryption_context: EncryptionContext::new(Rc4State::new(None)),
+            receive_contexts: VecDeque::new(),
+            key_epoch: 0,
+            bytes_sent: 0,
             log: false,
             log_file: None,
+            telnet: TelnetOptions::new(),
+            insecure: false,
         };
 
         new_connection
     }
+
+    /*
+       The encryption this connection should use in a room at the given epoch, see RoomRegistry::encryption_for.
+       A session key of its own is moved up to that epoch first
+    */
+    pub fn encryption_for(
+        &mut self,
+        rooms: &RoomRegistry,
+        room: &str,
+        epoch: u64,
+    ) -> RoomEncryption {
+        let own = match self.own_encryption.as_mut() {
+            Some(x) => x,
+            None => return rooms.encryption_for(room, self.suite),
+        };
+        while self.own_epoch < epoch {
+            self.own_epoch += 1;
+            own.key = derive_next_key(&own.key, self.own_epoch);
+        }
+        rooms.encryption_over(room, self.suite, own)
+    }
+
+    /*
+       Same as encryption_for at an epoch the server default has already moved past, with the default as it was
+       then, for catching a connection up one epoch at a time
+    */
+    pub fn encryption_over(
+        &mut self,
+        rooms: &RoomRegistry,
+        room: &str,
+        epoch: u64,
+        default: &RoomEncryption,
+    ) -> RoomEncryption {
+        match self.own_encryption {
+            Some(_) => self.encryption_for(rooms, room, epoch),
+            None => rooms.encryption_over(room, self.suite, default),
+        }
+    }
+
+    /*
+       Decrypts with the oldest key the client may still be using, the current one if no rekey is pending.
+       Returns false if the frame was thrown away, replayed or otherwise unreadable, the read buffer is empty then
+    */
+    fn decrypt_into_read_buffer(&mut self, encrypted_buffer: &mut Vec<u8>) -> bool {
+        let context = match self.receive_contexts.front_mut() {
+            Some(x) => x,
+            None => &mut self.encryption_context,
+        };
+        context
+            .context
+            .decrypt(encrypted_buffer, &mut self.read_buffer);
+        !context.context.rejected_last_frame()
+    }
+
+    /*
+       Next frame from the client. Encrypted connections are put back together from the length prefix, plaintext
+       ones take whatever one read returned since stock telnet clients don't frame anything.
+       WouldBlock comes back as is on a nonblocking socket, a closed connection is UnexpectedEof
+    */
+    fn receive_frame(&mut self) -> io::Result<Vec<u8>> {
+        if !self.insecure {
+            return self.frames.read_frame(&mut self.stream);
+        }
+
+        let mut buffer = vec![0; PLAINTEXT_READ_BYTES];
+        let length = self.stream.read(&mut buffer)?;
+        if length == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.truncate(length);
+        Ok(buffer)
+    }
+
+    /*
+       Takes telnet commands out of the read buffer and answers them. Returns how much chat data is left
+    */
+    fn filter_telnet(&mut self) -> usize {
+        let (data, replies) = self.telnet.receive(&self.read_buffer);
+        if !replies.is_empty() {
+            self.write_encrypted(&replies);
+        }
+        self.read_buffer = data;
+        self.read_buffer.len()
+    }
+
+    fn write_encrypted(&mut self, buffer: &[u8]) {
+        let mut plain = buffer.to_vec();
+        let mut encrypted_buffer = plain.clone();
+        self.encryption_context
+            .context
+            .encrypt(&mut plain, &mut encrypted_buffer);
+        self.bytes_sent += encrypted_buffer.len() as u64;
+        if !self.insecure {
+            encrypted_buffer = encode_frame(&encrypted_buffer);
+        }
+        self.send_bytes(&encrypted_buffer);
+    }
+
+    /*
+       A client that hasn't taken anything for WRITE_TIMEOUT is cut off, it may have half a frame so nothing after
+       it could be read anyway. Shutting the socket down wakes its reader thread, which cleans up as usual
+    */
+    fn send_bytes(&mut self, buffer: &[u8]) {
+        if let Err(e) = write_all_within(&mut self.stream, buffer, WRITE_TIMEOUT) {
+            warn!(error = %e, "could not write to the client, closing");
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /*
+       The name as other people see it, with a mark for connections that aren't encrypted
+    */
+    pub fn display_name(&self) -> String {
+        match self.insecure {
+            true => format!("{}{}", self.name, INSECURE_MARK),
+            false => self.name.clone(),
+        }
+    }
+
+    /*
+       Stops a telnet client echoing what is typed, by claiming the echo ourselves and not doing it
+    */
+    pub fn hide_input(&mut self, hide: bool) {
+        if self.telnet.is_active() {
+            let request = self.telnet.request_local(OPTION_ECHO, hide);
+            if !request.is_empty() {
+                self.write_encrypted(&request);
+            }
+        }
+    }
 }
 
 pub fn print_vec(buffer: &[u8]) {
@@ -79,6 +649,12 @@ pub trait ServerFunctions {
     fn set_name(&mut self, name: String) -> u64;
 
     fn read_from_connection_blocking(&mut self) -> usize;
+
+    fn set_encryption_context(&mut self, context: EncryptionContext);
+
+    fn switch_encryption_context(&mut self, context: EncryptionContext);
+
+    fn rekey(&mut self, epoch: u64, context: EncryptionContext);
 }
 macro_rules! write_to_log {
     ($self:expr) => {
@@ -99,15 +675,10 @@ impl ServerFunctions for TelnetServerConnection {
         if let Err(_) = self.stream.set_nonblocking(true) {
             return 0;
         }
-        let mut encrypted_buffer = vec![0; 1024];
-
-        let ret = match self.stream.read(&mut encrypted_buffer) {
-            Ok(0) => {
-                return 0;
-            }
+        let mut encrypted_buffer = match self.receive_frame() {
             Ok(x) => x,
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                // No data available now, return immediately
+                // No whole frame available now, return immediately
                 return VALID_CONNECTION as usize;
             }
             Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {
@@ -115,36 +686,44 @@ impl ServerFunctions for TelnetServerConnection {
                 return 0;
             }
             Err(_) => {
-                // Other errors, handle appropriately
+                // Closed, or sent a length no frame could have
                 return 0;
             }
         };
-        self.read_buffer.resize(ret, 0);
-        encrypted_buffer.resize(ret, 0);
-        self.encryption_context
-            .context
-            .decrypt(&mut encrypted_buffer, &mut self.read_buffer);
+        self.read_buffer.resize(encrypted_buffer.len(), 0);
+        if !self.decrypt_into_read_buffer(&mut encrypted_buffer) {
+            return VALID_CONNECTION as usize;
+        }
+
+        /*
+           The acknowledgement is the last thing the client sends under the old key, swallow it and move on
+        */
+        if !self.receive_contexts.is_empty() && self.read_buffer.starts_with(REKEY_ACK.as_bytes()) {
+            self.receive_contexts.pop_front();
+            self.flush_read_buffer();
+            return VALID_CONNECTION as usize;
+        }
+
+        if self.filter_telnet() == 0 {
+            return VALID_CONNECTION as usize;
+        }
 
         write_to_log!(self);
-        ret
+        self.read_buffer.len()
     }
 
     fn write_from_passed_encrypted_buffer(&mut self, buffer: &mut Vec<u8>) {
-        match self.stream.write_all(&buffer) {
-            Ok(x) => x,
-            Err(_) => return,
-        };
+        if !self.insecure {
+            *buffer = encode_frame(buffer);
+        }
+        self.send_bytes(buffer);
     }
 
-    fn write_from_passed_buffer(&mut self, mut buffer: &mut Vec<u8>) {
-        let mut encrypted_buffer = buffer.clone();
-        self.encryption_context
-            .context
-            .encrypt(&mut buffer, &mut encrypted_buffer);
-        match self.stream.write_all(&encrypted_buffer) {
-            Ok(x) => x,
-            Err(_) => return,
-        };
+    fn write_from_passed_buffer(&mut self, buffer: &mut Vec<u8>) {
+        match self.telnet.is_active() {
+            true => self.write_encrypted(&escape_iac(buffer)),
+            false => self.write_encrypted(buffer),
+        }
     }
 
     fn fetch_address(&mut self) -> SocketAddr {
@@ -155,13 +734,23 @@ impl ServerFunctions for TelnetServerConnection {
         self.flush_read_buffer();
 
         if !message.is_some() {
-            self.stream.shutdown(Shutdown::Both).unwrap();
+            let _ = self.stream.shutdown(Shutdown::Both);
             return;
         }
 
         let message = message.unwrap();
+        if !self.insecure && self.traffic_salt.is_empty() {
+            /*
+               Still before the handshake, the client couldn't read anything under the session key
+            */
+            refuse_before_handshake(&mut self.stream, message.trim_end());
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return;
+        }
         self.write_from_passed_buffer(&mut message.as_bytes().to_vec());
         self.flush_read_buffer();
+        let _ = self.stream.flush();
+        let _ = self.stream.shutdown(Shutdown::Both);
     }
 
     fn flush_read_buffer(&mut self) {
@@ -187,7 +776,7 @@ impl ServerFunctions for TelnetServerConnection {
             self.log_file = Option::from(file.unwrap());
             0
         } else {
-            println!("Log file {} could not be opened", log_file);
+            warn!(log_file, "log file could not be opened");
             1
         }
     }
@@ -210,119 +799,198 @@ impl ServerFunctions for TelnetServerConnection {
             return 0;
         }
 
-        let mut encrypted_buffer = vec![0; 1024];
-        let ret = match self.stream.read(&mut encrypted_buffer) {
-            Ok(0) => 0,
-            Ok(x) => x,
-            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {
-                //Connection was reset (dropped by peer)
+        /*
+           A read that was nothing but telnet negotiation doesn't count, wait for the next one
+        */
+        loop {
+            let mut encrypted_buffer = match self.receive_frame() {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                    //Connection was reset (dropped by peer)
+                    return 0;
+                }
+                Err(_) => {
+                    // Closed, or sent a length no frame could have
+                    return 0;
+                }
+            };
+            trace!(bytes = encrypted_buffer.len(), "blocking read");
+            self.read_buffer.resize(encrypted_buffer.len(), 0);
+            /*
+               Blocking reads only happen before someone has logged in, a frame we can't read this early
+               almost always means the wrong session key
+            */
+            if !self.decrypt_into_read_buffer(&mut encrypted_buffer) {
+                warn!("sent a frame that could not be read, they likely have the wrong session key, closing connection");
                 return 0;
             }
-            Err(_) => {
-                // Other errors, handle appropriately
-                return 0;
+            if self.filter_telnet() == 0 {
+                continue;
             }
+            write_to_log!(self);
+            return self.read_buffer.len();
+        }
+    }
+
+    fn set_encryption_context(&mut self, context: EncryptionContext) {
+        if !self.insecure {
+            self.encryption_context = context;
+        }
+    }
+
+    /*
+       Sends under the new context from here on but keeps reading under the current one until the client
+       acknowledges with REKEY_ACK, whatever it sent before it saw the switch still gets through
+    */
+    fn switch_encryption_context(&mut self, context: EncryptionContext) {
+        if self.insecure {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.encryption_context, context);
+        self.receive_contexts.push_back(previous);
+    }
+
+    /*
+       Announces the new epoch under the current key, then sends under the new one straight away, the framing
+       lets the client tell where one key stops and the other starts. Reading stays on the current key until the
+       client acknowledges
+    */
+    fn rekey(&mut self, epoch: u64, context: EncryptionContext) {
+        if self.insecure {
+            self.key_epoch = epoch;
+            return;
+        }
+        let mut announce = format!("{} {}\n", REKEY_ANNOUNCE, epoch).into_bytes();
+        self.write_from_passed_buffer(&mut announce);
+
+        self.switch_encryption_context(context);
+        self.key_epoch = epoch;
+        self.bytes_sent = 0;
+    }
+}
+
+/*
+   Builds the encryption context for the given cipher and key size, keyed with the passed key
+*/
+pub fn create_encryption_context(
+    encryption_type: EncryptionInfo,
+    key_size: KeySize,
+    key: &[u8],
+) -> EncryptionContext {
+    let aes_size = match key_size {
+        KeySize::Size128 => AesSize::S128,
+        KeySize::Size192 => AesSize::S192,
+        KeySize::Size256 => AesSize::S256,
+    };
+
+    let mut context = match encryption_type {
+        EncryptionInfo::AesCbc => {
+            EncryptionContext::new(AESContext::new(AesMode::CBC, aes_size, Some(key)))
+        }
+        EncryptionInfo::AesCtr => {
+            EncryptionContext::new(AESContext::new(AesMode::CTR, aes_size, Some(key)))
+        }
+        EncryptionInfo::AesEcb => {
+            EncryptionContext::new(AESContext::new(AesMode::ECB, aes_size, Some(key)))
+        }
+        EncryptionInfo::Rc4 => EncryptionContext::new(Rc4State::new(Some(key))),
+    };
+
+    context.context.set_key(key);
+    context
+}
+
+impl RoomEncryption {
+    pub fn create_context(&self, traffic_salt: &[u8]) -> EncryptionContext {
+        self.create_context_for(RatchetRole::Server, traffic_salt)
+    }
+
+    /*
+       The role picks which of the two traffic keys we send under, the salt is the connection's, see handshake.rs.
+       With the ratchet on each direction's traffic key seeds its chain, and it keys that direction's sequence MAC
+    */
+    pub fn create_context_for(&self, role: RatchetRole, traffic_salt: &[u8]) -> EncryptionContext {
+        let length = CipherSuite::new(self.encryption_type, self.key_size).key_length();
+        let peer = match role {
+            RatchetRole::Client => RatchetRole::Server,
+            RatchetRole::Server => RatchetRole::Client,
         };
-        println!("READ {ret} BYTES");
-        encrypted_buffer.resize(ret, 0);
-        self.read_buffer.resize(ret, 0);
-        self.encryption_context
-            .context
-            .decrypt(&mut encrypted_buffer, &mut self.read_buffer);
-        write_to_log!(self);
-        ret
+        let sending_key = traffic_key(&self.key, traffic_salt, role, length);
+        let receiving_key = traffic_key(&self.key, traffic_salt, peer, length);
+        let direction = |key: &[u8]| {
+            let mut context =
+                create_encryption_context(self.encryption_type, self.key_size, key).context;
+            if self.ratchet {
+                context = Box::new(RatchetContext::from_boxed(context, role, key));
+            }
+            context
+        };
+
+        let context = DuplexContext::new(direction(&sending_key), direction(&receiving_key));
+        EncryptionContext::new(SequencedContext::new(
+            Box::new(context),
+            STREAM_REPLAY_WINDOW,
+            &sending_key,
+            &receiving_key,
+        ))
     }
 }
+/*
+   Accepts a connection on the plaintext listener, it skips the handshake and never leaves NullCipher
+*/
+pub fn open_insecure_telnet_connection(
+    listener: &TcpListener,
+) -> io::Result<TelnetServerConnection> {
+    let (stream, socket) = listener.accept()?;
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    let mut connection = TelnetServerConnection::new(
+        socket,
+        NEXT_CONNECTION_ID.fetch_add(1, Ordering::SeqCst),
+        stream,
+    );
+    connection.encryption_context = EncryptionContext::new(NullCipher);
+    connection.insecure = true;
+    Ok(connection)
+}
+
 /*
    Open connection sets up a new TelnetServerConnection object with the new connection found on the listener.
 */
 
 pub fn open_telnet_connection(
     listener: Arc<RwLock<TcpListener>>,
-    session_key: String,
-    encryption_type: EncryptionInfo,
-    key_size: KeySize,
-) -> TelnetServerConnection {
+    session_encryption: &RoomEncryption,
+) -> io::Result<TelnetServerConnection> {
     let listener = listener.read().unwrap();
-    let (tcp_conn, sock_addr) = listener.accept().expect("Failed to accept connection");
+    let (tcp_conn, sock_addr) = listener.accept()?;
+    let _ = tcp_conn.set_write_timeout(Some(WRITE_TIMEOUT));
 
     let read_buff = vec![0u8; 4096];
-    let new_encryption_context = match encryption_type {
-        EncryptionInfo::AesCbc => match key_size {
-            KeySize::Size128 => EncryptionContext::new(AESContext::new(
-                AesMode::CBC,
-                AesSize::S128,
-                Some(session_key.as_bytes()),
-            )),
-            KeySize::Size192 => EncryptionContext::new(AESContext::new(
-                AesMode::CBC,
-                AesSize::S192,
-                Some(session_key.as_bytes()),
-            )),
-            KeySize::Size256 => EncryptionContext::new(AESContext::new(
-                AesMode::CBC,
-                AesSize::S256,
-                Some(session_key.as_bytes()),
-            )),
-        },
-        EncryptionInfo::AesCtr => match key_size {
-            KeySize::Size128 => EncryptionContext::new(AESContext::new(
-                AesMode::CTR,
-                AesSize::S128,
-                Some(session_key.as_bytes()),
-            )),
-            KeySize::Size192 => EncryptionContext::new(AESContext::new(
-                AesMode::CTR,
-                AesSize::S192,
-                Some(session_key.as_bytes()),
-            )),
-            KeySize::Size256 => EncryptionContext::new(AESContext::new(
-                AesMode::CTR,
-                AesSize::S256,
-                Some(session_key.as_bytes()),
-            )),
-        },
-        EncryptionInfo::AesEcb => match key_size {
-            KeySize::Size128 => EncryptionContext::new(AESContext::new(
-                AesMode::ECB,
-                AesSize::S128,
-                Some(session_key.as_bytes()),
-            )),
-            KeySize::Size192 => EncryptionContext::new(AESContext::new(
-                AesMode::ECB,
-                AesSize::S192,
-                Some(session_key.as_bytes()),
-            )),
-            KeySize::Size256 => EncryptionContext::new(AESContext::new(
-                AesMode::ECB,
-                AesSize::S256,
-                Some(session_key.as_bytes()),
-            )),
-        },
-        EncryptionInfo::Rc4 => match key_size {
-            KeySize::Size128 => EncryptionContext::new(Rc4State::new(Some(session_key.as_bytes()))),
-            KeySize::Size192 => EncryptionContext::new(Rc4State::new(Some(session_key.as_bytes()))),
-            KeySize::Size256 => EncryptionContext::new(Rc4State::new(Some(session_key.as_bytes()))),
-        },
-    };
+    let new_encryption_context = session_encryption.create_context(&[]);
 
-    let mut server_connection = TelnetServerConnection {
-        connection_id: 0,
+    Ok(TelnetServerConnection {
+        connection_id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::SeqCst),
         stream: tcp_conn,
+        frames: FrameReader::new(),
         socket_addr: sock_addr,
         read_buffer: read_buff,
         name: "".to_string(),
+        suite: CipherSuite::new(
+            session_encryption.encryption_type,
+            session_encryption.key_size,
+        ),
+        traffic_salt: Vec::new(),
+        own_encryption: None,
+        own_epoch: 0,
         encryption_context: new_encryption_context,
+        receive_contexts: VecDeque::new(),
+        key_epoch: 0,
+        bytes_sent: 0,
         log: false,
         log_file: None,
-    };
-
-    server_connection
-        .encryption_context
-        .context
-        .set_key(session_key.as_bytes());
-
-    server_connection
+        telnet: TelnetOptions::new(),
+        insecure: false,
+    })
 }
 
 /*
@@ -347,12 +1015,247 @@ pub fn broadcast_message(message: &mut Vec<u8>, source: u64, pool: &ConnectionPo
 }
 
 /*
-Handle grabbing the username of the new connection, must be between 5 and 25 characters long
+Warns everyone still connected, gives them the grace period to read it, then disconnects each of them and
+makes sure the transcript is on disk. The caller exits the process afterwards.
+*/
+pub fn shutdown_server(server: &Server, grace: Duration) {
+    let mut notice = format!("Server shutting down in {} seconds\n", grace.as_secs()).into_bytes();
+    broadcast_message(&mut notice, NO_SOURCE, &server.pool);
+    std::thread::sleep(grace);
+
+    let pool_ref = server.pool.read().unwrap();
+    for connection in pool_ref.iter() {
+        if let Ok(mut conn) = connection.write() {
+            conn.send_closing_message_and_disconnect(Some(SHUTDOWN_MESSAGE.to_string()));
+        }
+    }
+    drop(pool_ref);
+
+    if let Some(transcript) = &server.transcript {
+        if let Err(e) = transcript.flush() {
+            error!("{}", e.message());
+        }
+    }
+}
+
+/*
+Same as broadcast_message but only reaches the members of the given room
+*/
+pub fn broadcast_to_room(message: &mut Vec<u8>, source: u64, room: &str, server: &Server) {
+    /*
+       Copied before sending since encrypting can pad the buffer in place
+    */
+    server.sessions.record(room, message);
+    let members = server.rooms.members(room);
+    let pool_ref = server.pool.read().unwrap();
+
+    for connection in pool_ref.iter() {
+        let mut conn = match connection.write() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        if conn.connection_id == source || !members.contains(&conn.connection_id) {
+            continue;
+        }
+
+        conn.write_from_passed_buffer(message);
+    }
+}
+
+/*
+Writes a chat message to the scrollback and the transcript if there is one, a failed transcript write is reported but doesn't stop the chat
+*/
+fn record_message(connection: &Connection, room: &str, text: &str, server: &Server) {
+    let sender = connection.read().unwrap().display_name();
+    let record = TranscriptRecord::new(room, &sender, text);
+
+    if let Some(transcript) = &server.transcript {
+        if let Err(e) = transcript.append(&record) {
+            error!("{}", e.message());
+        }
+    }
+    server.history.push(record);
+}
+
+/*
+Sends the scrollback for a room to just this connection, under whatever key it is using now
+*/
+pub fn replay_history(connection: &Connection, room: &str, server: &Server) {
+    if let Some(history) = server.history.replay(room) {
+        connection
+            .write()
+            .unwrap()
+            .write_from_passed_buffer(&mut history.into_bytes());
+    }
+}
+
+/*
+Look up a connection in the pool by username, names are matched case insensitively
+*/
+pub fn find_connection_by_name(name: &str, server: &Server) -> Option<Connection> {
+    let pool_ref = server.pool.read().unwrap();
+
+    for connection in pool_ref.iter() {
+        let conn = match connection.read() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        if conn.name.eq_ignore_ascii_case(name) {
+            return Some(connection.clone());
+        }
+    }
+    None
+}
+
+/*
+Validates the name and, if nobody else has it, names the connection and inserts it into the pool.
+The uniqueness check and the insert happen under the same pool lock so two people can't race for one name.
+The caller must not be holding the lock on the connection.
+*/
+pub fn claim_username(
+    connection: &Connection,
+    name: &str,
+    server: &Server,
+) -> Result<(), UsernameError> {
+    server.usernames.validate(name)?;
+
+    let mut pool_ref = server.pool.write().unwrap();
+    if server.sessions.is_reserved(name) {
+        return Err(UsernameError::Taken);
+    }
+
+    for other in pool_ref.iter() {
+        let other = match other.read() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        if other.name.eq_ignore_ascii_case(name) {
+            return Err(UsernameError::Taken);
+        }
+    }
+
+    connection.write().unwrap().set_name(name.to_string());
+    pool_ref.push_back(connection.clone());
+    Ok(())
+}
+
+/*
+Takes over a dropped session with its resume token. The name was held for us so it goes straight into the pool,
+again under the pool lock so nobody can slip in between the token being redeemed and the name being claimed.
+*/
+fn resume_session(connection: &Connection, token: &str, server: &Server) -> Option<Resumption> {
+    let mut pool_ref = server.pool.write().unwrap();
+    let resumption = server.sessions.take(token)?;
+
+    connection
+        .write()
+        .unwrap()
+        .set_name(resumption.name.clone());
+    pool_ref.push_back(connection.clone());
+    Some(resumption)
+}
+
+/*
+If accounts are enabled and the name is registered, ask for the password before letting anyone have it.
+Names without an account are let through as guests.
+*/
+fn authenticate(connection: &Connection, name: &str, server: &Server) -> bool {
+    let accounts = match &server.accounts {
+        Some(x) => x,
+        None => return true,
+    };
+
+    if !accounts.exists(name) {
+        return true;
+    }
+
+    let password = {
+        let mut conn = connection.write().unwrap();
+        conn.hide_input(true);
+        conn.write_from_passed_buffer(&mut format!("Password for {}: ", name).into_bytes());
+        if conn.read_from_connection_blocking() == 0 {
+            return false;
+        }
+        conn.hide_input(false);
+        let password = String::from_utf8_lossy(&conn.read_buffer)
+            .trim_matches(|c: char| c == '\0' || c == '\r' || c == '\n')
+            .to_string();
+        conn.flush_read_buffer();
+        password
+    };
+
+    accounts.verify(name, &password)
+}
+
+/*
+   Runs the plaintext hello and switches the connection onto the negotiated suite, see handshake.rs
+*/
+fn negotiate_suite(connection: &Connection, server: &Server) -> bool {
+    let mut conn = connection.write().unwrap();
+    if conn.stream.set_nonblocking(false).is_err()
+        || conn
+            .stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .is_err()
+    {
+        return false;
+    }
+
+    let session = server.rooms.session_encryption();
+    let handshake = match server_handshake(
+        &mut conn.stream,
+        &session.key,
+        server.rooms.suites(),
+        &server.keys,
+    ) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(reason = %e.message(), "handshake failed, closing connection");
+            return false;
+        }
+    };
+
+    let _ = conn.stream.set_read_timeout(None);
+    let suite = handshake.suite;
+    conn.suite = suite;
+    conn.traffic_salt = handshake.traffic_salt;
+    conn.own_encryption = handshake.session_key.map(|key| RoomEncryption {
+        key,
+        ..session.clone()
+    });
+    let context = conn
+        .own_encryption
+        .as_ref()
+        .unwrap_or(session)
+        .for_suite(suite)
+        .create_context(&conn.traffic_salt);
+    conn.set_encryption_context(context);
+    info!(suite = ?suite, "negotiated cipher suite");
+    true
+}
+
+/*
+Handle grabbing the username of the new connection, see UsernamePolicy for what is accepted
 Sets up the connection with the username provided and inserts it into the connection pool before returning into the main server thread loop
 */
-pub fn handle_new_connection(connection: Connection, pool: ConnectionPool) -> bool {
+pub fn handle_new_connection(connection: Connection, server: Server) -> bool {
     let mut greeted = false;
+    let mut failed_logins = 0;
     let username: String;
+    let mut resumed = None;
+
+    let insecure = connection.read().unwrap().insecure;
+    if insecure {
+        let mut conn = connection.write().unwrap();
+        let negotiation = conn.telnet.start();
+        conn.write_encrypted(&negotiation);
+        conn.write_from_passed_buffer(&mut INSECURE_BANNER.as_bytes().to_vec());
+    } else if !negotiate_suite(&connection, &server) {
+        return false;
+    }
 
     loop {
         let mut conn = connection.write().unwrap();
@@ -364,72 +1267,215 @@ pub fn handle_new_connection(connection: Connection, pool: ConnectionPool) -> bo
 
         let length = conn.read_from_connection_blocking();
 
-        for byte in conn.read_buffer.iter() {
-            if !(*byte).is_ascii() {
-                eprintln!("Connection {} on {} is sending invalid ascii, this likely means that they have the wrong session key! Closing connection.",conn.connection_id,conn.socket_addr);
-                return false;
+        if length == 0 {
+            return false;
+        }
+
+        let name = frame_text(&conn.read_buffer);
+        drop(conn);
+
+        if let Some(token) = name.strip_prefix("/resume ") {
+            if let Some(resumption) = resume_session(&connection, token.trim(), &server) {
+                info!(username = %resumption.name, "session resumed");
+                username = resumption.name.clone();
+                resumed = Some(resumption);
+                break;
+            }
+        } else if authenticate(&connection, &name, &server) {
+            match claim_username(&connection, &name, &server) {
+                Ok(()) => {
+                    info!(username = %name, "joined");
+                    let mut conn = connection.write().unwrap();
+                    conn.write_from_passed_buffer(&mut SUCCESS_STRING.as_bytes().to_vec());
+                    username = name;
+                    break;
+                }
+                Err(e) => {
+                    let mut conn = connection.write().unwrap();
+                    conn.write_from_passed_buffer(&mut e.message().into_bytes());
+                    continue;
+                }
             }
         }
 
-        if length == 0 {
+        failed_logins += 1;
+        let mut conn = connection.write().unwrap();
+        if failed_logins >= MAX_LOGIN_ATTEMPTS {
+            warn!(attempted = %name, "too many failed logins, closing connection");
+            conn.send_closing_message_and_disconnect(Some(
+                "Too many failed logins, goodbye.\n".to_string(),
+            ));
             return false;
         }
+        let message = match name.starts_with("/resume ") {
+            true => "That resume token is not valid or has expired. What will your username be? :",
+            false => "Wrong password. What will your username be? :",
+        };
+        conn.write_from_passed_buffer(&mut message.as_bytes().to_vec());
+    }
+    Span::current().record("username", username.as_str());
+    catch_up(&connection, &server);
 
-        let name = String::from_utf8_lossy(&conn.read_buffer)
-            .trim()
-            .to_string();
+    /*
+       Once user has provided a valid username they are in the pool, put them in the lobby and broadcast a message to all other connected parties
+    */
+    let connection_id = connection.read().unwrap().connection_id;
 
-        let length = name.len();
-        if length > 4 && length < 25 {
-            let mut name = name.clone();
-            println!("New connection: {}", name);
+    match resumed {
+        Some(resumption) => {
+            let mut conn = connection.write().unwrap();
+            let message = format!("Welcome back {}, resuming session\n", username);
+            conn.write_from_passed_buffer(&mut message.into_bytes());
+            drop(conn);
 
-            conn.write_from_passed_buffer(&mut SUCCESS_STRING.as_bytes().to_vec());
+            // Only fails for plaintext users whose room has its own key
+            if !change_room(&connection, &server, &resumption.room) {
+                change_room(&connection, &server, LOBBY);
+            }
 
-            conn.set_name(name);
-            username = conn.name.clone();
-            break;
+            if !resumption.missed.is_empty() {
+                let mut message =
+                    format!("--- {} messages you missed ---\n", resumption.missed.len())
+                        .into_bytes();
+                for missed in resumption.missed {
+                    message.extend_from_slice(&missed);
+                }
+                message.extend_from_slice(b"--- end of missed messages ---\n");
+                connection
+                    .write()
+                    .unwrap()
+                    .write_from_passed_buffer(&mut message);
+            }
+        }
+        None => {
+            server.rooms.join(LOBBY, connection_id);
+            replay_history(&connection, LOBBY, &server);
+
+            let name = connection.read().unwrap().display_name();
+            let message = format!("{} has joined #{}\n", name, LOBBY);
+            let mut message_vec = message.into_bytes();
+            broadcast_to_room(&mut message_vec, connection_id, LOBBY, &server);
         }
-
-        conn.write_from_passed_buffer(&mut INVALID_NAME.as_bytes().to_vec());
     }
-    /*
-       Once user has provided a valid username we will insert into the pool and broadcast a message to all other connected parties
-    */
-    let count;
 
     /*
-       Put the bottom two snippets in their own scope block so as to keep the lock/unlock timing from blocking too much should somewhere else need to access the connection or pool
+       The token lets this user take their name back if they drop, hand it out only once they are fully in
     */
-    {
-        let mut pool_ref = pool.write().unwrap();
-        count = pool_ref.iter().count();
-        pool_ref.insert(count, connection.clone());
-    }
+    let room = server
+        .rooms
+        .room_of(connection_id)
+        .unwrap_or(LOBBY.to_string());
+    let token = server.sessions.issue(connection_id, &username, &room);
+    let message = format!(
+        "Your resume token is {}, if you get disconnected send /resume {} as your username within {} seconds to get your session back\n",
+        token,
+        token,
+        server.sessions.grace().as_secs()
+    );
+    connection
+        .write()
+        .unwrap()
+        .write_from_passed_buffer(&mut message.into_bytes());
+    true
+}
 
-    {
-        let mut conn = connection.write().unwrap();
-        conn.connection_id = count as u64;
-    }
+/*
+   Blocks until the client has sent something, closed the connection or a second has passed. Only asks the socket
+   whether it is readable, the mode and timeouts it shares with the connection are left alone so broadcasters
+   writing to it in the meantime aren't affected, and whatever arrived is left for the frame reader
+*/
+fn wait_for_input(stream: &TcpStream) {
+    wait_until_ready(stream, false, IDLE_READ_TIMEOUT);
+}
+
+/*
+   Waits up to timeout for the socket to be readable, or writable, and says whether it is
+*/
+#[cfg(unix)]
+fn wait_until_ready(stream: &TcpStream, writable: bool, timeout: Duration) -> bool {
+    use std::os::unix::io::AsRawFd;
+    let mut descriptor = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: if writable {
+            libc::POLLOUT
+        } else {
+            libc::POLLIN
+        },
+        revents: 0,
+    };
+    let milliseconds = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    unsafe { libc::poll(&mut descriptor, 1, milliseconds) > 0 }
+}
 
-    let message = format!("{} has joined\n", username);
-    let mut message_vec = message.into_bytes();
-    broadcast_message(&mut message_vec, count as u64, &pool);
+/*
+   Without poll there is nothing to wait on, so give it a moment and let the caller try again
+*/
+#[cfg(not(unix))]
+fn wait_until_ready(_stream: &TcpStream, _writable: bool, timeout: Duration) -> bool {
+    std::thread::sleep(timeout.min(Duration::from_millis(10)));
     true
 }
 
 /*
-   Main server loop, socket is nonblocking so that it will not stay blocked while inside a locked context (this would break the broadcast function) , broadcasts on leave so others are alerted
+   write_all with a deadline, for a socket that may be nonblocking (the main loop) or blocking with a write timeout
+   (logins), both come back with WouldBlock or TimedOut when the client stops taking data
+*/
+fn write_all_within(
+    stream: &mut TcpStream,
+    mut buffer: &[u8],
+    timeout: Duration,
+) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    while !buffer.is_empty() {
+        match stream.write(buffer) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(x) => buffer = &buffer[x..],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() || !wait_until_ready(stream, true, left) {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/*
+   Main server loop, reads are nonblocking under the lock so that it will not stay blocked while inside a locked context (this would break the broadcast function) and the waiting happens in wait_for_input with the lock let go, broadcasts on leave so others are alerted
 */
-pub fn spawn_server_thread(connection: Connection, pool: ConnectionPool) {
+pub fn spawn_server_thread(connection: Connection, server: Server) {
+    let span = {
+        let conn = connection.read().unwrap();
+        info_span!(
+            "connection",
+            conn_id = conn.connection_id,
+            peer = %conn.socket_addr,
+            username = Empty
+        )
+    };
+
     std::thread::spawn(move || {
-        let (mut read_buffer, mut connection_id, mut val);
-        let result = handle_new_connection(connection.clone(), pool.clone());
+        let _span = span.enter();
+        let (mut read_buffer, mut connection_id, mut val, mut text);
+        let result = handle_new_connection(connection.clone(), server.clone());
 
         if !result {
             return;
         }
 
+        let waiter = match connection.read().unwrap().stream.try_clone() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                warn!(error = %e, "could not clone the socket to wait on, closing");
+                None
+            }
+        };
+
         loop {
             let mut conn = match connection.write() {
                 Ok(x) => x,
@@ -443,21 +1489,40 @@ pub fn spawn_server_thread(connection: Connection, pool: ConnectionPool) {
                 v if v > 0 && v != VALID_CONNECTION as usize => {
                     read_buffer = conn.read_buffer.clone();
                     read_buffer.resize(v, 0);
-                    let mut prefix = conn.name.clone().into_bytes();
-                    prefix.push(b':');
-                    prefix.push(b' ');
-                    prefix.extend_from_slice(&read_buffer);
-                    prefix.push(b'\n');
-                    read_buffer = prefix;
+
+                    if read_buffer.first() == Some(&b'/') {
+                        let line = chat_text(&conn.read_buffer);
+                        conn.flush_read_buffer();
+                        drop(conn);
+                        handle_command(&connection, &server, &line);
+                        continue;
+                    }
+
+                    text = chat_text(&read_buffer);
+                    read_buffer = format!("{}: {}\n", conn.display_name(), text).into_bytes();
+                }
+                v if v == VALID_CONNECTION as usize => {
+                    /*
+                       Nothing to read, let go of the lock so broadcasts and logins can get at this connection and
+                       block on the socket until the client sends something
+                    */
+                    drop(conn);
+                    match &waiter {
+                        Some(x) => wait_for_input(x),
+                        None => break,
+                    }
+                    continue;
                 }
-                v if v == VALID_CONNECTION as usize => continue,
                 0 => break,
                 _ => continue,
             }
-            
+
             drop(conn);
 
-            broadcast_message(&mut read_buffer, connection_id, &pool);
+            if let Some(room) = server.rooms.room_of(connection_id) {
+                broadcast_to_room(&mut read_buffer, connection_id, &room, &server);
+                record_message(&connection, &room, &text, &server);
+            }
 
             let mut conn = match connection.write() {
                 Ok(x) => x,
@@ -471,42 +1536,21 @@ pub fn spawn_server_thread(connection: Connection, pool: ConnectionPool) {
 
         let conn = connection.read().unwrap();
         conn_id = conn.connection_id;
-        name = conn.name.clone();
+        name = conn.display_name();
         drop(conn);
 
-        println!("Connection {} closed", conn_id);
+        info!("connection closed");
 
-        let mut pool_unlocked = pool.write().unwrap();
-        pool_unlocked.remove(conn_id as usize);
+        let mut pool_unlocked = server.pool.write().unwrap();
+        pool_unlocked.retain(|x| !Arc::ptr_eq(x, &connection));
         drop(pool_unlocked);
 
-        let message = format!("{} has left\n", name);
-        let mut message_vec = message.into_bytes();
-        broadcast_message(&mut message_vec, conn_id, &pool);
-    });
-}
-/*
-This function is just for testing purposes
-*/
-#[allow(dead_code)]
-pub fn spawn_connect_thread() {
-    std::thread::spawn(move || {
-        println!("Starting client thread");
-        let mut tcp_stream = TcpStream::connect(format!("127.0.0.1:{}", PORT)).unwrap();
-        let mut buf = Box::new([0; 1024]);
-        let write = tcp_stream
-            .write(&Vec::from(String::from("TESTBOT\n").as_bytes()))
-            .expect("Could not write to tcp output stream");
-        loop {
-            let read = tcp_stream.read(buf.deref_mut()).unwrap();
-            if buf[0] != b'\0' {
-                println!(
-                    "Received a message from the server : {}",
-                    String::from_utf8(buf.to_vec()).unwrap()
-                );
-
-                buf.iter_mut().for_each(|x| *x = 0);
-            }
+        let room = server.rooms.leave(conn_id);
+        if let Some(ref room) = room {
+            let message = format!("{} has left #{}\n", name, room);
+            let mut message_vec = message.into_bytes();
+            broadcast_to_room(&mut message_vec, conn_id, room, &server);
         }
+        server.sessions.disconnected(conn_id, room.as_deref());
     });
 }
@@ -0,0 +1,401 @@
+use crate::cryptography::aead::CtrHmacAead;
+use crate::cryptography::scrypt::{scrypt, ScryptError, ScryptParams, DEFAULT_SCRYPT_PARAMS};
+use crate::telnet::accounts::read_password;
+use crate::{ERROR, SUCCESS};
+use rand::RngCore;
+use regex::Regex;
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+   Server wide chat transcript, encrypted at rest under its own key so the file is useless without it even if
+   someone walks off with the session key too.
+
+   File layout:
+     header  : MAGIC | log_n (1) | r (4) | p (4) | salt (16)
+     records : length (4) | AEAD frame, the record's position in the file is the associated data so records
+               can't be dropped from the middle or shuffled around without it being noticed
+   Record plaintext is timestamp (8) | room length (2) | room | sender length (2) | sender | text
+*/
+const MAGIC: &[u8] = b"KRYPTOSLOG1\n";
+const SALT_SIZE_BYTES: usize = 16;
+const HEADER_SIZE_BYTES: usize = MAGIC.len() + 1 + 4 + 4 + SALT_SIZE_BYTES;
+const KEY_SIZE_BYTES: usize = 32;
+pub const TRANSCRIPT_KEY_VARIABLE: &str = "KRYPTOS_LOG_KEY";
+
+#[derive(Debug, PartialEq)]
+pub enum TranscriptError {
+    Io(String),
+    NotATranscript,
+    Truncated,
+    Tampered(u64),
+    BadParameters(ScryptError),
+}
+
+impl TranscriptError {
+    pub fn message(&self) -> String {
+        match self {
+            TranscriptError::Io(e) => format!("Could not access the transcript: {}", e),
+            TranscriptError::NotATranscript => "That file is not a kryptos transcript".to_string(),
+            TranscriptError::Truncated => "The transcript ends part way through a record".to_string(),
+            TranscriptError::Tampered(index) => format!(
+                "Record {} failed authentication, either the key is wrong or the file was tampered with",
+                index
+            ),
+            TranscriptError::BadParameters(e) => {
+                format!("The transcript header is not usable: {}", e.message())
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TranscriptRecord {
+    pub timestamp: u64,
+    pub room: String,
+    pub sender: String,
+    pub text: String,
+}
+
+impl TranscriptRecord {
+    pub fn new(room: &str, sender: &str, text: &str) -> TranscriptRecord {
+        TranscriptRecord {
+            timestamp: unix_time(),
+            room: room.to_string(),
+            sender: sender.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut output = self.timestamp.to_be_bytes().to_vec();
+        for field in [&self.room, &self.sender] {
+            output.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            output.extend_from_slice(field.as_bytes());
+        }
+        output.extend_from_slice(self.text.as_bytes());
+        output
+    }
+
+    fn decode(buffer: &[u8]) -> Option<TranscriptRecord> {
+        let timestamp = u64::from_be_bytes(buffer.get(..8)?.try_into().ok()?);
+        let mut rest = &buffer[8..];
+        let mut fields = Vec::new();
+        for _ in 0..2 {
+            let length = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+            fields.push(String::from_utf8_lossy(rest.get(2..2 + length)?).to_string());
+            rest = &rest[2 + length..];
+        }
+
+        Some(TranscriptRecord {
+            timestamp,
+            room: fields.remove(0),
+            sender: fields.remove(0),
+            text: String::from_utf8_lossy(rest).to_string(),
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "[{}] #{} <{}> {}",
+            format_timestamp(self.timestamp),
+            self.room,
+            self.sender,
+            self.text
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"timestamp\":\"{}\",\"room\":{},\"sender\":{},\"text\":{}}}",
+            format_timestamp(self.timestamp).replace(' ', "T") + "Z",
+            json_string(&self.room),
+            json_string(&self.sender),
+            json_string(&self.text)
+        )
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/*
+   UTC "YYYY-MM-DD HH:MM:SS", days to date conversion from Howard Hinnant's civil_from_days
+*/
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn derive_cipher(
+    key: &str,
+    salt: &[u8],
+    params: ScryptParams,
+) -> Result<CtrHmacAead, TranscriptError> {
+    scrypt(key.as_bytes(), salt, params, KEY_SIZE_BYTES)
+        .map(|x| CtrHmacAead::new(&x))
+        .map_err(TranscriptError::BadParameters)
+}
+
+fn parse_header(contents: &[u8]) -> Result<(ScryptParams, Vec<u8>), TranscriptError> {
+    if contents.len() < HEADER_SIZE_BYTES || !contents.starts_with(MAGIC) {
+        return Err(TranscriptError::NotATranscript);
+    }
+
+    let header = &contents[MAGIC.len()..HEADER_SIZE_BYTES];
+    let params = ScryptParams {
+        log_n: header[0],
+        r: u32::from_be_bytes(header[1..5].try_into().unwrap()),
+        p: u32::from_be_bytes(header[5..9].try_into().unwrap()),
+    };
+    /*
+       The header isn't authenticated, a doctored one mustn't be able to make opening the file eat all the memory
+    */
+    params.validate().map_err(TranscriptError::BadParameters)?;
+    Ok((params, header[9..].to_vec()))
+}
+
+/*
+   Splits the body of the file into the raw AEAD frames
+*/
+fn split_records(mut body: &[u8]) -> Result<Vec<&[u8]>, TranscriptError> {
+    let mut frames = Vec::new();
+    while !body.is_empty() {
+        let length = match body.get(..4) {
+            Some(x) => u32::from_be_bytes(x.try_into().unwrap()) as usize,
+            None => return Err(TranscriptError::Truncated),
+        };
+        let frame = body.get(4..4 + length).ok_or(TranscriptError::Truncated)?;
+        frames.push(frame);
+        body = &body[4 + length..];
+    }
+    Ok(frames)
+}
+
+/*
+   Decrypts and authenticates every record in a transcript
+*/
+pub fn read_transcript(path: &str, key: &str) -> Result<Vec<TranscriptRecord>, TranscriptError> {
+    let contents = fs::read(path).map_err(|e| TranscriptError::Io(e.to_string()))?;
+    let (params, salt) = parse_header(&contents)?;
+    let mut cipher = derive_cipher(key, &salt, params)?;
+
+    let mut records = Vec::new();
+    for (index, frame) in split_records(&contents[HEADER_SIZE_BYTES..])?
+        .into_iter()
+        .enumerate()
+    {
+        let index = index as u64;
+        let record = cipher
+            .open(&index.to_be_bytes(), frame)
+            .and_then(|x| TranscriptRecord::decode(&x))
+            .ok_or(TranscriptError::Tampered(index))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+struct TranscriptWriter {
+    file: File,
+    cipher: CtrHmacAead,
+    next_index: u64,
+}
+
+pub struct Transcript {
+    writer: Mutex<TranscriptWriter>,
+}
+
+impl Transcript {
+    pub fn open(path: &str, key: &str) -> Result<Transcript, TranscriptError> {
+        Self::open_with_params(path, key, DEFAULT_SCRYPT_PARAMS)
+    }
+
+    /*
+       Appends to an existing transcript or starts a new one. An existing one has to open with the key given,
+       otherwise we would end up with records under two different keys in the one file
+    */
+    pub fn open_with_params(
+        path: &str,
+        key: &str,
+        params: ScryptParams,
+    ) -> Result<Transcript, TranscriptError> {
+        let io_error = |e: std::io::Error| TranscriptError::Io(e.to_string());
+
+        let (cipher, next_index) = match fs::read(path) {
+            Ok(contents) if !contents.is_empty() => {
+                let (params, salt) = parse_header(&contents)?;
+                let mut cipher = derive_cipher(key, &salt, params)?;
+                let frames = split_records(&contents[HEADER_SIZE_BYTES..])?;
+                if let Some(frame) = frames.first() {
+                    if cipher.open(&0u64.to_be_bytes(), frame).is_none() {
+                        return Err(TranscriptError::Tampered(0));
+                    }
+                }
+                (cipher, frames.len() as u64)
+            }
+            _ => {
+                params.validate().map_err(TranscriptError::BadParameters)?;
+                let mut salt = vec![0u8; SALT_SIZE_BYTES];
+                rand::rng().fill_bytes(&mut salt);
+
+                let mut header = MAGIC.to_vec();
+                header.push(params.log_n);
+                header.extend_from_slice(&params.r.to_be_bytes());
+                header.extend_from_slice(&params.p.to_be_bytes());
+                header.extend_from_slice(&salt);
+                fs::write(path, header).map_err(io_error)?;
+
+                (derive_cipher(key, &salt, params)?, 0)
+            }
+        };
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+
+        Ok(Transcript {
+            writer: Mutex::new(TranscriptWriter {
+                file,
+                cipher,
+                next_index,
+            }),
+        })
+    }
+
+    pub fn flush(&self) -> Result<(), TranscriptError> {
+        let writer = self.writer.lock().unwrap();
+        writer
+            .file
+            .sync_all()
+            .map_err(|e| TranscriptError::Io(e.to_string()))
+    }
+
+    pub fn append(&self, record: &TranscriptRecord) -> Result<(), TranscriptError> {
+        let mut writer = self.writer.lock().unwrap();
+        let index = writer.next_index;
+        let frame = writer.cipher.seal(&index.to_be_bytes(), &record.encode());
+
+        let mut output = (frame.len() as u32).to_be_bytes().to_vec();
+        output.extend_from_slice(&frame);
+        writer
+            .file
+            .write_all(&output)
+            .map_err(|e| TranscriptError::Io(e.to_string()))?;
+        writer.next_index += 1;
+        Ok(())
+    }
+}
+
+/*
+   The key is never taken on the command line so it doesn't end up in shell history or the process list
+*/
+pub fn transcript_key() -> String {
+    match env::var(TRANSCRIPT_KEY_VARIABLE) {
+        Ok(x) => x,
+        Err(_) => read_password("Transcript key: "),
+    }
+}
+
+/*
+   kryptos-server log decrypt file
+   kryptos-server log grep pattern file
+   kryptos-server log export --json file
+*/
+pub fn log_command(args: &[String]) -> i32 {
+    let usage = "Usage: kryptos-server log decrypt file | kryptos-server log grep pattern file | kryptos-server log export --json file";
+    let (pattern, path) = match (args.first().map(|x| x.as_str()), args.len()) {
+        (Some("decrypt"), 2) => (None, &args[1]),
+        (Some("grep"), 3) => (Some(&args[1]), &args[2]),
+        (Some("export"), 3) if args[1] == "--json" => (None, &args[2]),
+        _ => {
+            println!("{}", usage);
+            println!(
+                "The key is read from {} if set, asked for otherwise",
+                TRANSCRIPT_KEY_VARIABLE
+            );
+            return ERROR;
+        }
+    };
+
+    let pattern = match pattern.map(|x| Regex::new(x)) {
+        Some(Ok(x)) => Some(x),
+        Some(Err(e)) => {
+            eprintln!("Invalid pattern: {}", e);
+            return ERROR;
+        }
+        None => None,
+    };
+
+    let records = match read_transcript(path, &transcript_key()) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e.message());
+            return ERROR;
+        }
+    };
+
+    if args[0] == "export" {
+        let records: Vec<String> = records.iter().map(|x| x.to_json()).collect();
+        println!("[{}]", records.join(",\n"));
+        return SUCCESS;
+    }
+
+    for record in records {
+        let line = record.to_line();
+        if pattern.as_ref().is_none_or(|x| x.is_match(&line)) {
+            println!("{}", line);
+        }
+    }
+    SUCCESS
+}
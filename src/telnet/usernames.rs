@@ -0,0 +1,92 @@
+use regex::Regex;
+
+pub const MIN_USERNAME_LENGTH: usize = 5;
+pub const MAX_USERNAME_LENGTH: usize = 24;
+pub const DEFAULT_USERNAME_PATTERN: &str = r"^[A-Za-z0-9_\-]+$";
+pub const DEFAULT_RESERVED_NAMES: [&str; 2] = ["server", "admin"];
+
+const NAME_PROMPT: &str = "What will your username be? :";
+
+/*
+   Every reason we can turn a name down, each gets its own message so the user knows what to fix
+*/
+#[derive(Debug, PartialEq)]
+pub enum UsernameError {
+    TooShort,
+    TooLong,
+    InvalidCharacters,
+    Reserved,
+    Taken,
+}
+
+impl UsernameError {
+    pub fn message(&self) -> String {
+        let reason = match self {
+            UsernameError::TooShort => format!(
+                "That username is too short, it must be at least {} characters.",
+                MIN_USERNAME_LENGTH
+            ),
+            UsernameError::TooLong => format!(
+                "That username is too long, it must be at most {} characters.",
+                MAX_USERNAME_LENGTH
+            ),
+            UsernameError::InvalidCharacters => {
+                "That username contains characters that are not allowed.".to_string()
+            }
+            UsernameError::Reserved => "That username is reserved.".to_string(),
+            UsernameError::Taken => "That username is already taken.".to_string(),
+        };
+        format!("{} {}", reason, NAME_PROMPT)
+    }
+}
+
+pub struct UsernamePolicy {
+    pattern: Regex,
+    reserved: Vec<String>,
+}
+
+impl UsernamePolicy {
+    /*
+       Reserved names are compared case insensitively so "Admin" and "ADMIN" are caught too
+    */
+    pub fn new(pattern: &str, reserved: &[String]) -> Result<UsernamePolicy, regex::Error> {
+        Ok(UsernamePolicy {
+            pattern: Regex::new(pattern)?,
+            reserved: reserved.iter().map(|x| x.to_lowercase()).collect(),
+        })
+    }
+
+    /*
+       Checks everything that doesn't depend on who else is connected, uniqueness is checked when the name is claimed
+    */
+    pub fn validate(&self, name: &str) -> Result<(), UsernameError> {
+        let length = name.chars().count();
+        if length < MIN_USERNAME_LENGTH {
+            return Err(UsernameError::TooShort);
+        }
+
+        if length > MAX_USERNAME_LENGTH {
+            return Err(UsernameError::TooLong);
+        }
+
+        if name.chars().any(|c| c.is_control()) || !self.pattern.is_match(name) {
+            return Err(UsernameError::InvalidCharacters);
+        }
+
+        if self.reserved.contains(&name.to_lowercase()) {
+            return Err(UsernameError::Reserved);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        let reserved: Vec<String> = DEFAULT_RESERVED_NAMES
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        UsernamePolicy::new(DEFAULT_USERNAME_PATTERN, &reserved).unwrap()
+    }
+}
@@ -0,0 +1,354 @@
+/*
+   An in-process server and scripted clients for end to end tests and the loopback bench, outside the crate's own
+   tests it is kryptos::test_support behind the test-support feature. The server listens on port 0 so tests don't
+   need anything running beforehand and can run side by side without fighting over a port
+*/
+use crate::arg_handling::arg_handling::arg_handling::{EncryptionInfo, KeySize, RoomConfig};
+use crate::cryptography::cryptography::EncryptionContext;
+use crate::cryptography::ed25519::Ed25519KeyPair;
+use crate::cryptography::ratchet::RatchetRole;
+use crate::cryptography::rsa::{RsaPrivateKey, RsaPublicKey};
+use crate::generate_session_token;
+use crate::telnet::commands::ROOM_KEY_SWITCH;
+use crate::telnet::framing::{encode_frame, write_frame, FrameReader};
+use crate::telnet::handshake::{
+    client_handshake, client_handshake_with_rsa, CipherSuite, ClientHandshake, HandshakeError,
+    ServerKeys,
+};
+use crate::telnet::history::{RoomHistory, DEFAULT_HISTORY_LENGTH};
+use crate::telnet::rekey::{parse_rekey_announce, REKEY_ACK};
+use crate::telnet::rooms::{RoomEncryption, RoomRegistry, LOBBY};
+use crate::telnet::sessions::{
+    SessionRegistry, DEFAULT_MISSED_MESSAGE_LIMIT, DEFAULT_RESUME_GRACE_SECONDS,
+};
+use crate::telnet::usernames::UsernamePolicy;
+use crate::telnet::{
+    open_telnet_connection, spawn_server_thread, ConnectionPool, Server, ServerState,
+};
+use crate::{GREETING, SUCCESS_STRING};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub server: Server,
+    pub session_key: Vec<u8>,
+    pub suite: CipherSuite,
+    /*
+       Rooms the server was started with, clients know their keys the way --room hands them to kryptos-client
+    */
+    rooms: Vec<RoomConfig>,
+}
+
+impl TestServer {
+    /*
+       Same setup as kryptos-server with default options, minus the files. Returns once the port is bound
+    */
+    pub fn start(encryption_type: EncryptionInfo, key_size: KeySize) -> TestServer {
+        TestServer::start_with(encryption_type, key_size, None, &[])
+    }
+
+    /*
+       Same with --room for each of the given rooms
+    */
+    pub fn start_with_rooms(
+        encryption_type: EncryptionInfo,
+        key_size: KeySize,
+        rooms: &[RoomConfig],
+    ) -> TestServer {
+        TestServer::start_with(encryption_type, key_size, None, rooms)
+    }
+
+    /*
+       Same with --rsa-key, for clients that don't know the session key
+    */
+    pub fn start_with_rsa_key(
+        encryption_type: EncryptionInfo,
+        key_size: KeySize,
+        rsa_key: Option<RsaPrivateKey>,
+    ) -> TestServer {
+        TestServer::start_with(encryption_type, key_size, rsa_key, &[])
+    }
+
+    fn start_with(
+        encryption_type: EncryptionInfo,
+        key_size: KeySize,
+        rsa_key: Option<RsaPrivateKey>,
+        rooms: &[RoomConfig],
+    ) -> TestServer {
+        let mut session_key = generate_session_token();
+        session_key.truncate(<KeySize as Into<usize>>::into(key_size) / 8);
+        let session_encryption = RoomEncryption {
+            encryption_type,
+            key_size,
+            key: session_key.as_bytes().to_vec(),
+            ratchet: false,
+        };
+        let server = ServerState::new(
+            ConnectionPool::new(RwLock::new(Default::default())),
+            RoomRegistry::new(session_encryption.clone(), rooms),
+            UsernamePolicy::default(),
+            None,
+            SessionRegistry::new(
+                Duration::from_secs(DEFAULT_RESUME_GRACE_SECONDS),
+                DEFAULT_MISSED_MESSAGE_LIMIT,
+            ),
+            None,
+            RoomHistory::new(DEFAULT_HISTORY_LENGTH),
+            ServerKeys {
+                host_key: Ed25519KeyPair::generate(),
+                rsa_key,
+            },
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let listener = Arc::new(RwLock::new(listener));
+        let accepting = Arc::clone(&server);
+        thread::spawn(move || loop {
+            match open_telnet_connection(Arc::clone(&listener), &session_encryption) {
+                Ok(x) => spawn_server_thread(Arc::new(RwLock::new(x)), Arc::clone(&accepting)),
+                Err(_) => return,
+            }
+        });
+
+        TestServer {
+            address,
+            server,
+            session_key: session_key.into_bytes(),
+            suite: CipherSuite::new(encryption_type, key_size),
+            rooms: rooms.to_vec(),
+        }
+    }
+
+    /*
+       Connects, shakes hands and takes the given name, the client is in the lobby once this returns
+    */
+    pub fn join(&self, name: &str) -> TestClient {
+        self.join_after(name, |stream| {
+            client_handshake(stream, &self.session_key, &[self.suite], None)
+        })
+    }
+
+    /*
+       Joins without knowing the session key, wrapping a key of its own to the server's RSA key instead
+    */
+    pub fn join_with_rsa(&self, name: &str, server_key: &RsaPublicKey) -> TestClient {
+        self.join_after(name, |stream| {
+            client_handshake_with_rsa(stream, server_key, &[self.suite], None)
+        })
+    }
+
+    fn join_after<F>(&self, name: &str, handshake: F) -> TestClient
+    where
+        F: FnOnce(&mut TcpStream) -> Result<ClientHandshake, HandshakeError>,
+    {
+        let mut stream = TcpStream::connect(self.address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let handshake =
+            handshake(&mut stream).unwrap_or_else(|e| panic!("handshake failed: {}", e.message()));
+        let keys = RoomRegistry::new(
+            RoomEncryption {
+                encryption_type: handshake.suite.encryption_type,
+                key_size: handshake.suite.key_size,
+                key: handshake.session_key,
+                ratchet: false,
+            },
+            &self.rooms,
+        );
+        let context = keys
+            .encryption_for(LOBBY, handshake.suite)
+            .create_context_for(RatchetRole::Client, &handshake.traffic_salt);
+
+        let mut client = TestClient {
+            name: name.to_string(),
+            stream,
+            frames: FrameReader::new(),
+            context,
+            keys,
+            suite: handshake.suite,
+            traffic_salt: handshake.traffic_salt,
+            room: LOBBY.to_string(),
+            received: VecDeque::new(),
+        };
+        client.expect(GREETING);
+        client.send(name);
+        client.expect(SUCCESS_STRING.trim_end());
+        client.settle();
+        client
+    }
+}
+
+/*
+   Decrypts everything the server sends into lines that the test can wait for. Key rotations and switches to a
+   room's own key are followed the way kryptos-client does it
+*/
+pub struct TestClient {
+    pub name: String,
+    stream: TcpStream,
+    frames: FrameReader,
+    context: EncryptionContext,
+    keys: RoomRegistry,
+    suite: CipherSuite,
+    traffic_salt: Vec<u8>,
+    room: String,
+    received: VecDeque<String>,
+}
+
+impl TestClient {
+    pub fn send(&mut self, text: &str) {
+        let mut plain = text.as_bytes().to_vec();
+        let mut encrypted = plain.clone();
+        self.context.context.encrypt(&mut plain, &mut encrypted);
+        write_frame(&mut self.stream, &encrypted).unwrap();
+    }
+
+    /*
+       Several frames in one write, the way they can come out of a busy socket
+    */
+    pub fn send_together(&mut self, texts: &[&str]) {
+        let mut output = Vec::new();
+        for text in texts {
+            let mut plain = text.as_bytes().to_vec();
+            let mut encrypted = plain.clone();
+            self.context.context.encrypt(&mut plain, &mut encrypted);
+            output.extend_from_slice(&encode_frame(&encrypted));
+        }
+        self.stream.write_all(&output).unwrap();
+    }
+
+    /*
+       Waits for a line containing the needle and returns it, dropping whatever came before it
+    */
+    pub fn expect(&mut self, needle: &str) -> String {
+        let deadline = Instant::now() + RECEIVE_TIMEOUT;
+        let mut skipped = Vec::new();
+        loop {
+            while let Some(line) = self.received.pop_front() {
+                if line.contains(needle) {
+                    return line;
+                }
+                skipped.push(line);
+            }
+            if !self.receive_until(deadline) {
+                panic!(
+                    "{} never got {:?}, received {:?}",
+                    self.name, needle, skipped
+                );
+            }
+        }
+    }
+
+    /*
+       Fails if anything containing the needle shows up within the given time
+    */
+    pub fn expect_silence(&mut self, needle: &str, wait: Duration) {
+        let deadline = Instant::now() + wait;
+        while self.receive_until(deadline) {}
+        if let Some(line) = self.received.iter().find(|x| x.contains(needle)) {
+            panic!("{} was not meant to get {:?}", self.name, line);
+        }
+    }
+
+    /*
+       Reads until the server has been quiet for a moment, e.g. after joining, which sends several frames at once
+    */
+    pub fn settle(&mut self) {
+        while self.receive_until(Instant::now() + SETTLE_TIME) {}
+    }
+
+    /*
+       Reads one frame and hands back every line received so far, None once the timeout passes or the server
+       hangs up
+    */
+    pub fn receive(&mut self, timeout: Duration) -> Option<Vec<String>> {
+        if !self.receive_until(Instant::now() + timeout) {
+            return None;
+        }
+        Some(self.received.drain(..).collect())
+    }
+
+    /*
+       A second handle on the socket, to hang up a client that was moved onto a thread of its own
+    */
+    pub fn stream_handle(&self) -> TcpStream {
+        self.stream.try_clone().unwrap()
+    }
+
+    pub fn leave(self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    /*
+       Reads one frame, false once the deadline passes or the server hangs up
+    */
+    fn receive_until(&mut self, deadline: Instant) -> bool {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+        self.stream.set_read_timeout(Some(remaining)).unwrap();
+
+        let mut encrypted = match self.frames.read_frame(&mut self.stream) {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return false,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return false
+            }
+            Err(e) => panic!("{} lost the connection: {}", self.name, e),
+        };
+
+        let mut plain = vec![0u8; encrypted.len()];
+        self.context.context.decrypt(&mut encrypted, &mut plain);
+        assert!(
+            !self.context.context.rejected_last_frame(),
+            "{} got a frame it could not read",
+            self.name
+        );
+        let text = String::from_utf8_lossy(&plain)
+            .trim_matches('\0')
+            .to_string();
+        if let Some(epoch) = parse_rekey_announce(&text, self.keys.epoch()) {
+            self.rekey(epoch);
+            return true;
+        }
+        if let Some(room) = text
+            .strip_prefix("Joined #")
+            .and_then(|x| x.strip_suffix(ROOM_KEY_SWITCH))
+        {
+            self.send(&format!("{} {}\n", REKEY_ACK, self.keys.epoch()));
+            self.room = room.to_string();
+            self.switch_context();
+        } else if let Some(room) = text.strip_prefix("Joined #") {
+            self.room = room.trim().to_string();
+        }
+        self.received.extend(text.lines().map(|x| x.to_string()));
+        true
+    }
+
+    /*
+       Acknowledges under the old key, then moves on to the announced epoch, see rekey.rs
+    */
+    fn rekey(&mut self, epoch: u64) {
+        self.send(&format!("{} {}\n", REKEY_ACK, epoch));
+        self.keys.rotate();
+        self.switch_context();
+    }
+
+    /*
+       Picks up the key of the room we're in for the current epoch
+    */
+    fn switch_context(&mut self) {
+        self.context = self
+            .keys
+            .encryption_for(&self.room, self.suite)
+            .create_context_for(RatchetRole::Client, &self.traffic_salt);
+    }
+}
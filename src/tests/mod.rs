@@ -1 +1,5 @@
+#[cfg(any(test, feature = "test-support"))]
+pub mod harness;
+#[cfg(test)]
+pub mod rsp;
 pub mod tests;
@@ -0,0 +1,151 @@
+/*
+   Reader for the .rsp files NIST's CAVP publishes its test vectors in, kept in src/tests/vectors/<family>.
+   A file is comment lines starting with #, bracketed headers like [ENCRYPT] or [L = 32] and records of
+   NAME = value lines separated by blank lines. Headers in a row belong to the same section, which is how the
+   GCM and HMAC files give several parameters at once. Nothing here knows about AES, new families only need
+   a directory of files and a test that walks them
+*/
+use crate::cryptography::cryptography::from_hex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const VECTORS_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/vectors");
+
+#[derive(Default)]
+pub struct RspSection {
+    /*
+       [ENCRYPT] comes out as ("ENCRYPT", ""), [Keylen = 128] as ("Keylen", "128")
+    */
+    pub parameters: Vec<(String, String)>,
+    pub records: Vec<RspRecord>,
+}
+
+#[derive(Default)]
+pub struct RspRecord {
+    pub line: usize,
+    fields: Vec<(String, String)>,
+}
+
+pub struct RspFile {
+    pub path: PathBuf,
+    pub sections: Vec<RspSection>,
+}
+
+impl RspSection {
+    pub fn has(&self, name: &str) -> bool {
+        self.parameters.iter().any(|(x, _)| x == name)
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl RspRecord {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /*
+       Hex field as bytes, panics naming the line since a broken vector file is a broken test
+    */
+    pub fn bytes(&self, name: &str) -> Vec<u8> {
+        let value = self
+            .get(name)
+            .unwrap_or_else(|| panic!("line {}: no {} field", self.line, name));
+        from_hex(value).unwrap_or_else(|| panic!("line {}: {} is not hex", self.line, name))
+    }
+}
+
+impl RspFile {
+    pub fn name(&self) -> &str {
+        self.path.file_name().unwrap().to_str().unwrap()
+    }
+}
+
+pub fn parse_rsp(text: &str) -> Result<Vec<RspSection>, String> {
+    let mut sections: Vec<RspSection> = Vec::new();
+    let mut record = RspRecord::default();
+    let mut in_header = false;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            finish_record(&mut sections, &mut record)?;
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| format!("line {}: unterminated header", index + 1))?;
+            finish_record(&mut sections, &mut record)?;
+            if !in_header {
+                sections.push(RspSection::default());
+            }
+            in_header = true;
+            let (name, value) = header.split_once('=').unwrap_or((header, ""));
+            sections
+                .last_mut()
+                .unwrap()
+                .parameters
+                .push((name.trim().to_string(), value.trim().to_string()));
+            continue;
+        }
+
+        in_header = false;
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected NAME = value", index + 1))?;
+        if record.fields.is_empty() {
+            record.line = index + 1;
+        }
+        record
+            .fields
+            .push((name.trim().to_string(), value.trim().to_string()));
+    }
+    finish_record(&mut sections, &mut record)?;
+
+    Ok(sections)
+}
+
+fn finish_record(sections: &mut [RspSection], record: &mut RspRecord) -> Result<(), String> {
+    if record.fields.is_empty() {
+        return Ok(());
+    }
+    match sections.last_mut() {
+        Some(x) => {
+            x.records.push(std::mem::take(record));
+            Ok(())
+        }
+        None => Err(format!("line {}: record before any [section]", record.line)),
+    }
+}
+
+/*
+   Every .rsp file under vectors/<family>, sorted by name so failures come out in the same order every run
+*/
+pub fn load_vectors(family: &str) -> Vec<RspFile> {
+    let directory = Path::new(VECTORS_DIRECTORY).join(family);
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", directory.display(), e))
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "rsp"))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let text = fs::read_to_string(&path).unwrap();
+            let sections = parse_rsp(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            RspFile { path, sections }
+        })
+        .collect()
+}
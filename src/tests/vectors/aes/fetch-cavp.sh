@@ -0,0 +1,12 @@
+#!/bin/sh
+# Replaces the ECB and CBC files here with the unmodified ones from NIST's CAVP downloads, KAT_AES.zip and
+# aesmmt.zip. CTRSP800-38A.rsp isn't from CAVP and is left alone. Needs curl and unzip
+set -e
+cd "$(dirname "$0")"
+base=https://csrc.nist.gov/CSRC/media/Projects/Cryptographic-Algorithm-Validation-Program/documents/aes
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+for archive in KAT_AES.zip aesmmt.zip; do
+    curl -fsSL -o "$work/$archive" "$base/$archive"
+    unzip -o -j -q "$work/$archive" '*ECB*.rsp' '*CBC*.rsp' -d .
+done